@@ -8,7 +8,10 @@ use rand::RngCore;
 use std::env;
 use std::time::Duration;
 
-use bytecount::{count, naive_count, naive_count_32, naive_num_chars, num_chars};
+use bytecount::{
+    count, count_any, histogram, naive_count, naive_count_32, naive_count_any, naive_histogram,
+    naive_num_chars, num_chars,
+};
 
 fn random_bytes(len: usize) -> Vec<u8> {
     let mut result = vec![0; len];
@@ -67,6 +70,53 @@ fn bench_counts(criterion: &mut Criterion) {
     }
 }
 
+fn bench_count_any(criterion: &mut Criterion) {
+    fn naive(b: &mut Bencher, s: &usize) {
+        let haystack = random_bytes(*s);
+        b.iter(|| naive_count_any(&haystack, b"\r\n\0"))
+    }
+    fn hyper(b: &mut Bencher, s: &usize) {
+        let haystack = random_bytes(*s);
+        b.iter(|| count_any(&haystack, b"\r\n\0"))
+    }
+    let counts = get_counts();
+    let mut group = criterion.benchmark_group("count_any");
+    for count in counts {
+        group.throughput(criterion::Throughput::Bytes(count as u64));
+        group.bench_with_input(BenchmarkId::new("naive", count), &count, naive);
+        group.bench_with_input(BenchmarkId::new("hyper", count), &count, hyper);
+    }
+}
+
+fn bench_histogram(criterion: &mut Criterion) {
+    fn naive(b: &mut Bencher, s: &usize) {
+        let haystack = random_bytes(*s);
+        b.iter(|| naive_histogram(&haystack))
+    }
+    fn count_each(b: &mut Bencher, s: &usize) {
+        let haystack = random_bytes(*s);
+        b.iter(|| {
+            let mut histogram = [0; 256];
+            for (i, bin) in histogram.iter_mut().enumerate() {
+                *bin = count(&haystack, i as u8);
+            }
+            histogram
+        })
+    }
+    fn hyper(b: &mut Bencher, s: &usize) {
+        let haystack = random_bytes(*s);
+        b.iter(|| histogram(&haystack))
+    }
+    let counts = get_counts();
+    let mut group = criterion.benchmark_group("histogram");
+    for count in counts {
+        group.throughput(criterion::Throughput::Bytes(count as u64));
+        group.bench_with_input(BenchmarkId::new("naive", count), &count, naive);
+        group.bench_with_input(BenchmarkId::new("count_each", count), &count, count_each);
+        group.bench_with_input(BenchmarkId::new("hyper", count), &count, hyper);
+    }
+}
+
 fn bench_num_chars(criterion: &mut Criterion) {
     fn naive(b: &mut Bencher, s: &usize) {
         let haystack = random_bytes(*s);
@@ -86,5 +136,7 @@ fn bench_num_chars(criterion: &mut Criterion) {
 }
 
 criterion_group!(name = count_bench; config = get_config(); targets = bench_counts);
+criterion_group!(name = count_any_bench; config = get_config(); targets = bench_count_any);
+criterion_group!(name = histogram_bench; config = get_config(); targets = bench_histogram);
 criterion_group!(name = num_chars_bench; config = get_config(); targets = bench_num_chars);
-criterion_main!(count_bench, num_chars_bench);
+criterion_main!(count_bench, count_any_bench, histogram_bench, num_chars_bench);
@@ -0,0 +1,12 @@
+/// The index of the `n`th (counting from zero) lowest set bit of `bits`.
+///
+/// The SIMD code paths use this to turn a movemask of matching bytes into
+/// the position of a particular match. `bits` must have more than `n`
+/// bits set.
+pub(crate) fn nth_set_bit(mut bits: u64, n: usize) -> usize {
+    debug_assert!(bits.count_ones() as usize > n);
+    for _ in 0..n {
+        bits &= bits - 1;
+    }
+    bits.trailing_zeros() as usize
+}
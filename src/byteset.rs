@@ -0,0 +1,156 @@
+#[cfg(not(feature = "runtime-dispatch-simd"))]
+use core::{fmt, iter::FromIterator, ops::RangeInclusive};
+#[cfg(feature = "runtime-dispatch-simd")]
+use std::{fmt, iter::FromIterator, ops::RangeInclusive};
+
+/// A set of bytes, stored as a 256-bit membership table
+///
+/// This is used with [`count_set`](fn.count_set.html) to count all bytes
+/// belonging to some class, e.g. ASCII whitespace or the base64 alphabet.
+///
+/// # Example
+///
+/// ```
+/// use bytecount::ByteSet;
+///
+/// let mut hex = ByteSet::from_range(b'0'..=b'9');
+/// hex.insert_range(b'a'..=b'f');
+/// hex.insert_range(b'A'..=b'F');
+/// assert!(hex.contains(b'c'));
+/// assert!(!hex.contains(b'g'));
+/// assert_eq!(hex.len(), 22);
+/// ```
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct ByteSet {
+    // The table is laid out for nibble lookups: a byte `b` is a member if
+    // bit `(b >> 4) & 7` of `table[(b & 0x0F) | ((b & 0x80) >> 3)]` is set,
+    // so each half can be used directly as a 16-entry shuffle table.
+    table: [u8; 32],
+}
+
+fn index(byte: u8) -> (usize, u8) {
+    (
+        ((byte & 0x0F) | ((byte & 0x80) >> 3)) as usize,
+        1 << ((byte >> 4) & 7),
+    )
+}
+
+impl ByteSet {
+    /// Create an empty set
+    pub const fn new() -> Self {
+        ByteSet { table: [0; 32] }
+    }
+
+    /// Create a set containing all bytes in `bytes`
+    pub fn from_bytes(bytes: &[u8]) -> Self {
+        bytes.iter().cloned().collect()
+    }
+
+    /// Create a set containing all bytes in the inclusive `range`
+    pub fn from_range(range: RangeInclusive<u8>) -> Self {
+        let mut set = ByteSet::new();
+        set.insert_range(range);
+        set
+    }
+
+    /// Create a set containing all bytes for which `predicate` returns `true`
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// let whitespace = bytecount::ByteSet::from_predicate(|b| b.is_ascii_whitespace());
+    /// assert_eq!(whitespace.len(), 5);
+    /// ```
+    pub fn from_predicate<F: FnMut(u8) -> bool>(mut predicate: F) -> Self {
+        (0..=255).filter(|&byte| predicate(byte)).collect()
+    }
+
+    /// Add `byte` to the set
+    pub fn insert(&mut self, byte: u8) {
+        let (i, bit) = index(byte);
+        self.table[i] |= bit;
+    }
+
+    /// Add all bytes in the inclusive `range` to the set
+    pub fn insert_range(&mut self, range: RangeInclusive<u8>) {
+        for byte in range {
+            self.insert(byte);
+        }
+    }
+
+    /// Remove `byte` from the set
+    pub fn remove(&mut self, byte: u8) {
+        let (i, bit) = index(byte);
+        self.table[i] &= !bit;
+    }
+
+    /// Check whether `byte` is in the set
+    pub fn contains(&self, byte: u8) -> bool {
+        let (i, bit) = index(byte);
+        self.table[i] & bit != 0
+    }
+
+    /// The number of bytes in the set
+    pub fn len(&self) -> usize {
+        self.table
+            .iter()
+            .map(|bits| bits.count_ones() as usize)
+            .sum()
+    }
+
+    /// Check whether the set is empty
+    pub fn is_empty(&self) -> bool {
+        self.table.iter().all(|&bits| bits == 0)
+    }
+
+    /// The set of all bytes not in this set
+    pub fn complement(&self) -> Self {
+        let mut table = self.table;
+        for bits in table.iter_mut() {
+            *bits = !*bits;
+        }
+        ByteSet { table }
+    }
+
+    /// The set of all bytes in either this set or `other`
+    pub fn union(&self, other: &ByteSet) -> Self {
+        let mut table = self.table;
+        for (bits, other) in table.iter_mut().zip(other.table.iter()) {
+            *bits |= other;
+        }
+        ByteSet { table }
+    }
+
+    /// Iterate over the bytes in the set in ascending order
+    pub fn iter(&self) -> impl Iterator<Item = u8> + '_ {
+        (0..=255).filter(move |&byte| self.contains(byte))
+    }
+
+    // Only the SIMD code paths look at the raw table.
+    #[allow(dead_code)]
+    pub(crate) fn table(&self) -> &[u8; 32] {
+        &self.table
+    }
+}
+
+impl FromIterator<u8> for ByteSet {
+    fn from_iter<I: IntoIterator<Item = u8>>(iter: I) -> Self {
+        let mut set = ByteSet::new();
+        set.extend(iter);
+        set
+    }
+}
+
+impl Extend<u8> for ByteSet {
+    fn extend<I: IntoIterator<Item = u8>>(&mut self, iter: I) {
+        for byte in iter {
+            self.insert(byte);
+        }
+    }
+}
+
+impl fmt::Debug for ByteSet {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_set().entries(self.iter()).finish()
+    }
+}
@@ -0,0 +1,66 @@
+use crate::count;
+
+/// The number of bytes held back between updates, which is the widest
+/// load of any backend
+const BUFFER: usize = 64;
+
+/// Counts occurrences of a byte in input that arrives in pieces
+///
+/// Pieces shorter than a vector would be counted byte by byte, so the
+/// counter holds back the last `len % 64` bytes of every update and counts
+/// them together with the next one. This keeps many small updates on the
+/// fast path without needing an allocator.
+///
+/// # Example
+///
+/// ```
+/// let mut counter = bytecount::Counter::new(b' ');
+/// for word in &["This ", "is ", "a ", "Text ", "with ", "spaces"] {
+///     counter.update(word.as_bytes());
+/// }
+/// assert_eq!(counter.total(), 5);
+/// ```
+#[derive(Clone, Debug)]
+pub struct Counter {
+    needle: u8,
+    total: u64,
+    buffer: [u8; BUFFER],
+    buffered: usize,
+}
+
+impl Counter {
+    /// Create a counter for occurrences of `needle`
+    pub fn new(needle: u8) -> Self {
+        Counter {
+            needle,
+            total: 0,
+            buffer: [0; BUFFER],
+            buffered: 0,
+        }
+    }
+
+    /// Count the next piece of input
+    pub fn update(&mut self, mut haystack: &[u8]) {
+        if self.buffered > 0 {
+            let take = haystack.len().min(BUFFER - self.buffered);
+            self.buffer[self.buffered..self.buffered + take].copy_from_slice(&haystack[..take]);
+            self.buffered += take;
+            haystack = &haystack[take..];
+            if self.buffered < BUFFER {
+                return;
+            }
+            self.total += count(&self.buffer, self.needle) as u64;
+            self.buffered = 0;
+        }
+        let bulk = haystack.len() - haystack.len() % BUFFER;
+        self.total += count(&haystack[..bulk], self.needle) as u64;
+        let tail = &haystack[bulk..];
+        self.buffer[..tail.len()].copy_from_slice(tail);
+        self.buffered = tail.len();
+    }
+
+    /// The number of occurrences in all input so far
+    pub fn total(&self) -> u64 {
+        self.total + count(&self.buffer[..self.buffered], self.needle) as u64
+    }
+}
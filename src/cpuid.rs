@@ -0,0 +1,71 @@
+use core::arch::x86_64::{__cpuid, __cpuid_count, _xgetbv};
+use core::sync::atomic::{AtomicU8, Ordering};
+
+const SSE2: u8 = 1;
+const SSSE3: u8 = 2;
+const AVX2: u8 = 4;
+/// Set once the features have been detected, so that zero means unknown
+const DETECTED: u8 = 0x80;
+
+static FEATURES: AtomicU8 = AtomicU8::new(0);
+
+// `__cpuid` is only safe to call in newer Rust versions
+#[allow(unused_unsafe)]
+fn detect() -> u8 {
+    let mut features = DETECTED;
+    // Safety: every x86_64 CPU supports `cpuid`
+    let max_leaf = unsafe { __cpuid(0) }.eax;
+    let leaf1 = unsafe { __cpuid(1) };
+    if leaf1.edx & (1 << 26) != 0 {
+        features |= SSE2;
+    }
+    if leaf1.ecx & (1 << 9) != 0 {
+        features |= SSSE3;
+    }
+    // AVX2 is only usable if the OS saves the YMM registers, which `xgetbv`
+    // tells once the OS has enabled it
+    let osxsave = leaf1.ecx & (1 << 27) != 0;
+    let avx = leaf1.ecx & (1 << 28) != 0;
+    if max_leaf >= 7 && osxsave && avx {
+        // Safety: `osxsave` means `xgetbv` is available
+        let xcr0 = unsafe { _xgetbv(0) };
+        let leaf7 = unsafe { __cpuid_count(7, 0) };
+        if xcr0 & 0b110 == 0b110 && leaf7.ebx & (1 << 5) != 0 {
+            features |= AVX2;
+        }
+    }
+    features
+}
+
+/// Whether the CPU supports one of the target features the SIMD kernels
+/// use, like `std`'s `is_x86_feature_detected!`
+///
+/// The features are detected on the first call and cached.
+pub(crate) fn is_x86_feature_detected(feature: &str) -> bool {
+    let bit = match feature {
+        "sse2" => SSE2,
+        "ssse3" => SSSE3,
+        "avx2" => AVX2,
+        _ => unreachable!("{} is not detected", feature),
+    };
+    let mut features = FEATURES.load(Ordering::Relaxed);
+    if features == 0 {
+        features = detect();
+        FEATURES.store(features, Ordering::Relaxed);
+    }
+    features & bit != 0
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::is_x86_feature_detected;
+
+    #[test]
+    fn detects_like_std() {
+        assert_eq!(is_x86_feature_detected("sse2"), std::is_x86_feature_detected!("sse2"));
+        assert_eq!(is_x86_feature_detected("ssse3"), std::is_x86_feature_detected!("ssse3"));
+        assert_eq!(is_x86_feature_detected("avx2"), std::is_x86_feature_detected!("avx2"));
+    }
+}
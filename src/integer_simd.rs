@@ -1,7 +1,10 @@
+use crate::bits::nth_set_bit;
+use crate::substring::Matches;
+
 #[cfg(not(feature = "runtime-dispatch-simd"))]
-use core::{mem, ptr, usize};
+use core::{mem, ptr};
 #[cfg(feature = "runtime-dispatch-simd")]
-use std::{mem, ptr, usize};
+use std::{mem, ptr};
 
 fn splat(byte: u8) -> usize {
     let lo = usize::MAX / 0xFF;
@@ -26,6 +29,24 @@ fn bytewise_equal(lhs: usize, rhs: usize) -> usize {
     !((((x & !hi) + !hi) | x) >> 7) & lo
 }
 
+fn bytewise_sub(lhs: usize, rhs: usize) -> usize {
+    let hi = splat(0x80);
+
+    // Subtract the low seven bits with the top bit set so no borrow can
+    // cross into the next byte, then fix up the top bits.
+    ((lhs | hi) - (rhs & !hi)) ^ ((lhs ^ !rhs) & hi)
+}
+
+fn bytewise_less(lhs: usize, rhs: usize) -> usize {
+    let hi = splat(0x80);
+
+    // The top bit of each byte of `low` is set iff the low seven bits of
+    // `lhs` are at least those of `rhs`, which decides the comparison
+    // whenever the top bits of both bytes agree.
+    let low = (lhs | hi) - (rhs & !hi);
+    (((!lhs & rhs) | (!(lhs ^ rhs) & !low)) & hi) >> 7
+}
+
 fn sum_usize(values: usize) -> usize {
     let every_other_byte_lo = usize::MAX / 0xFFFF;
     let every_other_byte = every_other_byte_lo * 0xFF;
@@ -119,3 +140,610 @@ pub fn chunk_num_chars(utf8_chars: &[u8]) -> usize {
         count
     }
 }
+
+fn bytewise_equal_any(lhs: usize, needles: &[usize]) -> usize {
+    needles
+        .iter()
+        .fold(0, |matches, &needle| matches | bytewise_equal(lhs, needle))
+}
+
+pub fn chunk_count_any(haystack: &[u8], needles: &[u8]) -> usize {
+    let chunksize = mem::size_of::<usize>();
+    assert!(haystack.len() >= chunksize);
+    assert!(needles.len() <= 8);
+
+    unsafe {
+        let mut offset = 0;
+        let mut count = 0;
+
+        let mut splats = [0; 8];
+        for (splatted, &needle) in splats.iter_mut().zip(needles) {
+            *splatted = splat(needle);
+        }
+        let needles = &splats[..needles.len()];
+
+        // 2040
+        while haystack.len() >= offset + chunksize * 255 {
+            let mut counts = 0;
+            for _ in 0..255 {
+                counts += bytewise_equal_any(usize_load_unchecked(haystack, offset), needles);
+                offset += chunksize;
+            }
+            count += sum_usize(counts);
+        }
+
+        // 8
+        let mut counts = 0;
+        for i in 0..(haystack.len() - offset) / chunksize {
+            counts += bytewise_equal_any(
+                usize_load_unchecked(haystack, offset + i * chunksize),
+                needles,
+            );
+        }
+        if haystack.len() % chunksize != 0 {
+            let mask = usize::from_le(!(!0 >> ((haystack.len() % chunksize) * 8)));
+            counts += bytewise_equal_any(
+                usize_load_unchecked(haystack, haystack.len() - chunksize),
+                needles,
+            ) & mask;
+        }
+        count += sum_usize(counts);
+
+        count
+    }
+}
+
+fn bytewise_in_range(values: usize, lows: usize, spans: usize) -> usize {
+    // `lo <= x <= hi` iff `x - lo <= hi - lo` in wrapping arithmetic.
+    bytewise_less(spans, bytewise_sub(values, lows)) ^ splat(1)
+}
+
+pub fn chunk_count_range(haystack: &[u8], lo: u8, hi: u8) -> usize {
+    let chunksize = mem::size_of::<usize>();
+    assert!(haystack.len() >= chunksize);
+    assert!(lo <= hi);
+
+    unsafe {
+        let mut offset = 0;
+        let mut count = 0;
+
+        let lows = splat(lo);
+        let spans = splat(hi - lo);
+
+        // 2040
+        while haystack.len() >= offset + chunksize * 255 {
+            let mut counts = 0;
+            for _ in 0..255 {
+                counts += bytewise_in_range(usize_load_unchecked(haystack, offset), lows, spans);
+                offset += chunksize;
+            }
+            count += sum_usize(counts);
+        }
+
+        // 8
+        let mut counts = 0;
+        for i in 0..(haystack.len() - offset) / chunksize {
+            counts += bytewise_in_range(
+                usize_load_unchecked(haystack, offset + i * chunksize),
+                lows,
+                spans,
+            );
+        }
+        if haystack.len() % chunksize != 0 {
+            let mask = usize::from_le(!(!0 >> ((haystack.len() % chunksize) * 8)));
+            counts += bytewise_in_range(
+                usize_load_unchecked(haystack, haystack.len() - chunksize),
+                lows,
+                spans,
+            ) & mask;
+        }
+        count += sum_usize(counts);
+
+        count
+    }
+}
+
+// Each table bin counts at most a quarter of a block, so u32 cannot
+// overflow before the tables are flushed.
+const HISTOGRAM_BLOCK: usize = 1 << 31;
+
+pub fn chunk_histogram(haystack: &[u8], histogram: &mut [u64; 256]) {
+    // Spreading consecutive bytes over separate tables keeps runs of the
+    // same byte from serializing on a single counter.
+    let mut tables = [[0u32; 256]; 4];
+
+    for block in haystack.chunks(HISTOGRAM_BLOCK) {
+        let mut chunks = block.chunks_exact(8);
+        for chunk in &mut chunks {
+            tables[0][chunk[0] as usize] += 1;
+            tables[1][chunk[1] as usize] += 1;
+            tables[2][chunk[2] as usize] += 1;
+            tables[3][chunk[3] as usize] += 1;
+            tables[0][chunk[4] as usize] += 1;
+            tables[1][chunk[5] as usize] += 1;
+            tables[2][chunk[6] as usize] += 1;
+            tables[3][chunk[7] as usize] += 1;
+        }
+        for &byte in chunks.remainder() {
+            tables[0][byte as usize] += 1;
+        }
+
+        for (i, bin) in histogram.iter_mut().enumerate() {
+            *bin += tables.iter_mut().fold(0, |sum, table| {
+                sum + mem::replace(&mut table[i], 0) as u64
+            });
+        }
+    }
+}
+
+pub fn chunk_count_many<const N: usize>(haystack: &[u8], needles: [u8; N]) -> [usize; N] {
+    let chunksize = mem::size_of::<usize>();
+    assert!(haystack.len() >= chunksize);
+
+    unsafe {
+        let mut offset = 0;
+        let mut count = [0; N];
+
+        let needles = needles.map(splat);
+
+        // 2040
+        while haystack.len() >= offset + chunksize * 255 {
+            let mut counts = [0; N];
+            for _ in 0..255 {
+                let values = usize_load_unchecked(haystack, offset);
+                for (counts, &needles) in counts.iter_mut().zip(needles.iter()) {
+                    *counts += bytewise_equal(values, needles);
+                }
+                offset += chunksize;
+            }
+            for (count, &counts) in count.iter_mut().zip(counts.iter()) {
+                *count += sum_usize(counts);
+            }
+        }
+
+        // 8
+        let mut counts = [0; N];
+        for i in 0..(haystack.len() - offset) / chunksize {
+            let values = usize_load_unchecked(haystack, offset + i * chunksize);
+            for (counts, &needles) in counts.iter_mut().zip(needles.iter()) {
+                *counts += bytewise_equal(values, needles);
+            }
+        }
+        if haystack.len() % chunksize != 0 {
+            let mask = usize::from_le(!(!0 >> ((haystack.len() % chunksize) * 8)));
+            let values = usize_load_unchecked(haystack, haystack.len() - chunksize);
+            for (counts, &needles) in counts.iter_mut().zip(needles.iter()) {
+                *counts += bytewise_equal(values, needles) & mask;
+            }
+        }
+        for (count, &counts) in count.iter_mut().zip(counts.iter()) {
+            *count += sum_usize(counts);
+        }
+
+        count
+    }
+}
+
+pub fn chunk_text_stats(utf8_chars: &[u8]) -> (usize, usize) {
+    let chunksize = mem::size_of::<usize>();
+    assert!(utf8_chars.len() >= chunksize);
+
+    unsafe {
+        let mut offset = 0;
+        let mut newlines = 0;
+        let mut chars = 0;
+
+        let needles = splat(b'\n');
+
+        // 2040
+        while utf8_chars.len() >= offset + chunksize * 255 {
+            let mut newline_counts = 0;
+            let mut char_counts = 0;
+            for _ in 0..255 {
+                let values = usize_load_unchecked(utf8_chars, offset);
+                newline_counts += bytewise_equal(values, needles);
+                char_counts += is_leading_utf8_byte(values);
+                offset += chunksize;
+            }
+            newlines += sum_usize(newline_counts);
+            chars += sum_usize(char_counts);
+        }
+
+        // 8
+        let mut newline_counts = 0;
+        let mut char_counts = 0;
+        for i in 0..(utf8_chars.len() - offset) / chunksize {
+            let values = usize_load_unchecked(utf8_chars, offset + i * chunksize);
+            newline_counts += bytewise_equal(values, needles);
+            char_counts += is_leading_utf8_byte(values);
+        }
+        if utf8_chars.len() % chunksize != 0 {
+            let mask = usize::from_le(!(!0 >> ((utf8_chars.len() % chunksize) * 8)));
+            let values = usize_load_unchecked(utf8_chars, utf8_chars.len() - chunksize);
+            newline_counts += bytewise_equal(values, needles) & mask;
+            char_counts += is_leading_utf8_byte(values) & mask;
+        }
+        newlines += sum_usize(newline_counts);
+        chars += sum_usize(char_counts);
+
+        (newlines, chars)
+    }
+}
+
+pub fn chunk_count_substring(haystack: &[u8], needle: &[u8], overlapping: bool) -> usize {
+    let chunksize = mem::size_of::<usize>();
+    assert!(needle.len() >= 2);
+    assert!(haystack.len() >= needle.len() - 1 + chunksize);
+
+    unsafe {
+        let last = needle.len() - 1;
+        let mut offset = 0;
+        let mut matches = Matches::new(haystack, needle, overlapping);
+
+        let firsts = splat(needle[0]);
+        let lasts = splat(needle[last]);
+
+        // 8
+        while haystack.len() >= offset + last + chunksize {
+            // Put the byte at `offset` lowest regardless of endianness.
+            let mut bits = usize::from_le(
+                bytewise_equal(usize_load_unchecked(haystack, offset), firsts)
+                    & bytewise_equal(usize_load_unchecked(haystack, offset + last), lasts),
+            );
+            while bits != 0 {
+                matches.check(offset + bits.trailing_zeros() as usize / 8);
+                bits &= bits - 1;
+            }
+            offset += chunksize;
+        }
+
+        matches.finish(offset)
+    }
+}
+
+pub fn chunk_nth(haystack: &[u8], needle: u8, mut n: usize) -> Option<usize> {
+    let chunksize = mem::size_of::<usize>();
+    assert!(haystack.len() >= chunksize);
+
+    unsafe {
+        let mut offset = 0;
+
+        let needles = splat(needle);
+
+        // 2040: skip whole blocks that end before the nth occurrence
+        while haystack.len() >= offset + chunksize * 255 {
+            let mut counts = 0;
+            for i in 0..255 {
+                counts += bytewise_equal(
+                    usize_load_unchecked(haystack, offset + i * chunksize),
+                    needles,
+                );
+            }
+            let count = sum_usize(counts);
+            if count > n {
+                break;
+            }
+            n -= count;
+            offset += chunksize * 255;
+        }
+
+        // 8; put the byte at `offset` lowest regardless of endianness
+        while haystack.len() >= offset + chunksize {
+            let bits = usize::from_le(bytewise_equal(
+                usize_load_unchecked(haystack, offset),
+                needles,
+            )) as u64;
+            let count = bits.count_ones() as usize;
+            if count > n {
+                return Some(offset + nth_set_bit(bits, n) / 8);
+            }
+            n -= count;
+            offset += chunksize;
+        }
+        if haystack.len() != offset {
+            let bits = usize::from_le(bytewise_equal(
+                usize_load_unchecked(haystack, haystack.len() - chunksize),
+                needles,
+            )) as u64
+                >> ((offset + chunksize - haystack.len()) * 8);
+            if bits.count_ones() as usize > n {
+                return Some(offset + nth_set_bit(bits, n) / 8);
+            }
+        }
+
+        None
+    }
+}
+
+pub fn chunk_rcount_until(haystack: &[u8], needle: u8, limit: usize) -> (usize, usize) {
+    let chunksize = mem::size_of::<usize>();
+    assert!(haystack.len() >= chunksize && limit > 0);
+
+    unsafe {
+        let mut count = 0;
+        let mut end = haystack.len();
+
+        let needles = splat(needle);
+
+        // 2040: skip whole blocks that start after the last wanted occurrence
+        while end >= chunksize * 255 {
+            let mut counts = 0;
+            for i in 1..=255 {
+                counts += bytewise_equal(
+                    usize_load_unchecked(haystack, end - i * chunksize),
+                    needles,
+                );
+            }
+            let block = sum_usize(counts);
+            if count + block >= limit {
+                break;
+            }
+            count += block;
+            end -= chunksize * 255;
+        }
+
+        // 8; put the byte at the start of the word lowest regardless of endianness
+        while end >= chunksize {
+            let bits = usize::from_le(bytewise_equal(
+                usize_load_unchecked(haystack, end - chunksize),
+                needles,
+            )) as u64;
+            let found = bits.count_ones() as usize;
+            if count + found >= limit {
+                return (limit, end - chunksize + nth_set_bit(bits, count + found - limit) / 8);
+            }
+            count += found;
+            end -= chunksize;
+        }
+        if end != 0 {
+            let bits = usize::from_le(bytewise_equal(usize_load_unchecked(haystack, 0), needles))
+                as u64
+                & ((1 << (8 * end)) - 1);
+            let found = bits.count_ones() as usize;
+            if count + found >= limit {
+                return (limit, nth_set_bit(bits, count + found - limit) / 8);
+            }
+            count += found;
+        }
+
+        (count, 0)
+    }
+}
+
+#[cfg(feature = "runtime-dispatch-simd")]
+pub fn chunk_positions<F: FnMut(usize)>(haystack: &[u8], needle: u8, mut f: F) {
+    let chunksize = mem::size_of::<usize>();
+    assert!(haystack.len() >= chunksize);
+
+    unsafe {
+        let mut offset = 0;
+
+        let needles = splat(needle);
+
+        // 8; put the byte at `offset` lowest regardless of endianness
+        while haystack.len() >= offset + chunksize {
+            let mut bits =
+                usize::from_le(bytewise_equal(usize_load_unchecked(haystack, offset), needles));
+            while bits != 0 {
+                f(offset + bits.trailing_zeros() as usize / 8);
+                bits &= bits - 1;
+            }
+            offset += chunksize;
+        }
+        if haystack.len() != offset {
+            let mut bits = usize::from_le(bytewise_equal(
+                usize_load_unchecked(haystack, haystack.len() - chunksize),
+                needles,
+            )) >> ((offset + chunksize - haystack.len()) * 8);
+            while bits != 0 {
+                f(offset + bits.trailing_zeros() as usize / 8);
+                bits &= bits - 1;
+            }
+        }
+    }
+}
+
+fn is_four_byte_leader(values: usize) -> usize {
+    // the leading byte of a four byte sequence starts with the bits 1111.
+    (values >> 4) & (values >> 5) & (values >> 6) & (values >> 7) & splat(1)
+}
+
+pub fn chunk_utf16_len(utf8_chars: &[u8]) -> usize {
+    let chunksize = mem::size_of::<usize>();
+    assert!(utf8_chars.len() >= chunksize);
+
+    unsafe {
+        let mut offset = 0;
+        let mut fours = 0;
+        let mut chars = 0;
+
+        // 2040
+        while utf8_chars.len() >= offset + chunksize * 255 {
+            let mut four_counts = 0;
+            let mut char_counts = 0;
+            for _ in 0..255 {
+                let values = usize_load_unchecked(utf8_chars, offset);
+                four_counts += is_four_byte_leader(values);
+                char_counts += is_leading_utf8_byte(values);
+                offset += chunksize;
+            }
+            fours += sum_usize(four_counts);
+            chars += sum_usize(char_counts);
+        }
+
+        // 8
+        let mut four_counts = 0;
+        let mut char_counts = 0;
+        for i in 0..(utf8_chars.len() - offset) / chunksize {
+            let values = usize_load_unchecked(utf8_chars, offset + i * chunksize);
+            four_counts += is_four_byte_leader(values);
+            char_counts += is_leading_utf8_byte(values);
+        }
+        if utf8_chars.len() % chunksize != 0 {
+            let mask = usize::from_le(!(!0 >> ((utf8_chars.len() % chunksize) * 8)));
+            let values = usize_load_unchecked(utf8_chars, utf8_chars.len() - chunksize);
+            four_counts += is_four_byte_leader(values) & mask;
+            char_counts += is_leading_utf8_byte(values) & mask;
+        }
+        fours += sum_usize(four_counts);
+        chars += sum_usize(char_counts);
+
+        chars + fours
+    }
+}
+
+unsafe fn usize_load_unchecked_u16(units: &[u16], offset: usize) -> usize {
+    let mut output = 0;
+    ptr::copy_nonoverlapping(
+        units.as_ptr().add(offset),
+        &mut output as *mut usize as *mut u16,
+        mem::size_of::<usize>() / 2,
+    );
+    output
+}
+
+fn lanewise_zero(values: usize) -> usize {
+    // one in each 16-bit lane that is zero
+    let lo = usize::MAX / 0xFFFF;
+    let hi = lo << 15;
+
+    !((((values & !hi) + !hi) | values) >> 15) & lo
+}
+
+fn sum_lanes(values: usize) -> usize {
+    // Multiplication results in top lane holding sum, which must not
+    // exceed 16 bits.
+    let lo = usize::MAX / 0xFFFF;
+    values.wrapping_mul(lo) >> ((mem::size_of::<usize>() - 2) * 8)
+}
+
+fn utf8_deficit(values: usize) -> usize {
+    // The number of bytes short of three that a UTF-16 code unit takes in
+    // UTF-8; a surrogate pair takes four bytes, two each.
+    let lo = usize::MAX / 0xFFFF;
+    let masked = values & (lo * 0xF800);
+    lanewise_zero(values & (lo * 0xFF80))
+        + lanewise_zero(masked)
+        + lanewise_zero(masked ^ (lo * 0xD800))
+}
+
+pub fn chunk_utf8_len_from_utf16(utf16: &[u16]) -> usize {
+    let chunksize = mem::size_of::<usize>() / 2;
+    assert!(utf16.len() >= chunksize);
+
+    unsafe {
+        let mut offset = 0;
+        let mut deficit = 0;
+
+        // 16384
+        while utf16.len() >= offset + chunksize * 4096 {
+            let mut counts = 0;
+            for _ in 0..4096 {
+                counts += utf8_deficit(usize_load_unchecked_u16(utf16, offset));
+                offset += chunksize;
+            }
+            deficit += sum_lanes(counts);
+        }
+
+        // 4
+        let mut counts = 0;
+        for i in 0..(utf16.len() - offset) / chunksize {
+            counts += utf8_deficit(usize_load_unchecked_u16(utf16, offset + i * chunksize));
+        }
+        if utf16.len() % chunksize != 0 {
+            let mask = usize::from_le(!(!0 >> ((utf16.len() % chunksize) * 16)));
+            counts += utf8_deficit(usize_load_unchecked_u16(utf16, utf16.len() - chunksize)) & mask;
+        }
+        deficit += sum_lanes(counts);
+
+        3 * utf16.len() - deficit
+    }
+}
+
+fn is_low_surrogate(values: usize) -> usize {
+    // a low surrogate is one which starts with the bits 110111.
+    let lo = usize::MAX / 0xFFFF;
+    lanewise_zero((values & (lo * 0xFC00)) ^ (lo * 0xDC00))
+}
+
+pub fn chunk_num_chars_utf16(utf16: &[u16]) -> usize {
+    let chunksize = mem::size_of::<usize>() / 2;
+    assert!(utf16.len() >= chunksize);
+
+    unsafe {
+        let mut offset = 0;
+        let mut low_surrogates = 0;
+
+        // 16384
+        while utf16.len() >= offset + chunksize * 4096 {
+            let mut counts = 0;
+            for _ in 0..4096 {
+                counts += is_low_surrogate(usize_load_unchecked_u16(utf16, offset));
+                offset += chunksize;
+            }
+            low_surrogates += sum_lanes(counts);
+        }
+
+        // 4
+        let mut counts = 0;
+        for i in 0..(utf16.len() - offset) / chunksize {
+            counts += is_low_surrogate(usize_load_unchecked_u16(utf16, offset + i * chunksize));
+        }
+        if utf16.len() % chunksize != 0 {
+            let mask = usize::from_le(!(!0 >> ((utf16.len() % chunksize) * 16)));
+            counts +=
+                is_low_surrogate(usize_load_unchecked_u16(utf16, utf16.len() - chunksize)) & mask;
+        }
+        low_surrogates += sum_lanes(counts);
+
+        utf16.len() - low_surrogates
+    }
+}
+
+pub fn chunk_count_below<const N: usize>(haystack: &[u8], bounds: [u8; N]) -> [usize; N] {
+    let chunksize = mem::size_of::<usize>();
+    assert!(haystack.len() >= chunksize);
+
+    unsafe {
+        let mut offset = 0;
+        let mut count = [0; N];
+
+        let bounds = bounds.map(splat);
+
+        // 2040
+        while haystack.len() >= offset + chunksize * 255 {
+            let mut counts = [0; N];
+            for _ in 0..255 {
+                let values = usize_load_unchecked(haystack, offset);
+                for (counts, &bounds) in counts.iter_mut().zip(bounds.iter()) {
+                    *counts += bytewise_less(values, bounds);
+                }
+                offset += chunksize;
+            }
+            for (count, &counts) in count.iter_mut().zip(counts.iter()) {
+                *count += sum_usize(counts);
+            }
+        }
+
+        // 8
+        let mut counts = [0; N];
+        for i in 0..(haystack.len() - offset) / chunksize {
+            let values = usize_load_unchecked(haystack, offset + i * chunksize);
+            for (counts, &bounds) in counts.iter_mut().zip(bounds.iter()) {
+                *counts += bytewise_less(values, bounds);
+            }
+        }
+        if haystack.len() % chunksize != 0 {
+            let mask = usize::from_le(!(!0 >> ((haystack.len() % chunksize) * 8)));
+            let values = usize_load_unchecked(haystack, haystack.len() - chunksize);
+            for (counts, &bounds) in counts.iter_mut().zip(bounds.iter()) {
+                *counts += bytewise_less(values, bounds) & mask;
+            }
+        }
+        for (count, &counts) in count.iter_mut().zip(counts.iter()) {
+            *count += sum_usize(counts);
+        }
+
+        count
+    }
+}
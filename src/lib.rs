@@ -25,6 +25,40 @@
 //!            bytecount::num_chars(sequence.as_bytes()));
 //! ```
 //!
+//! To count several different bytes at once (say, both `\r` and `\n`),
+//! [`count_any`](fn.count_any.html) does so in a single pass:
+//!
+//! ```rust
+//! assert_eq!(3, bytecount::count_any(b"a, b; c, d", b",;"));
+//! ```
+//!
+//! Arbitrary classes of bytes can be counted with a
+//! [`ByteSet`](struct.ByteSet.html) and [`count_set`](fn.count_set.html):
+//!
+//! ```rust
+//! let vowels = bytecount::ByteSet::from_bytes(b"aeiou");
+//! assert_eq!(5, bytecount::count_set(b"the quick brown fox", &vowels));
+//! ```
+//!
+//! Occurrences of longer byte strings are counted by
+//! [`count_substring`](fn.count_substring.html):
+//!
+//! ```rust
+//! assert_eq!(2, bytecount::count_substring(b"<tr><td>1</td></tr><tr></tr>", b"</tr>"));
+//! ```
+//!
+//! With the `runtime-dispatch-simd` feature, a
+//! [`LineIndex`](struct.LineIndex.html) maps byte offsets to lines and
+//! columns:
+//!
+//! ```rust
+//! # #[cfg(feature = "runtime-dispatch-simd")]
+//! # {
+//! let index = bytecount::LineIndex::new(b"fn main() {\n    42\n}\n");
+//! assert_eq!(index.line_col(16), Some((1, 4)));
+//! # }
+//! ```
+//!
 //! For completeness and easy comparison, the "naive" versions of both
 //! count and num_chars are provided. Those are also faster if used on
 //! predominantly small strings. The
@@ -36,88 +70,588 @@
 #![cfg_attr(not(feature = "runtime-dispatch-simd"), no_std)]
 
 #[cfg(not(feature = "runtime-dispatch-simd"))]
-use core::mem;
+use core::{mem, ops::RangeInclusive, str};
 #[cfg(feature = "runtime-dispatch-simd")]
-use std::mem;
+use std::{mem, ops::RangeInclusive, str};
+#[cfg(all(
+    not(feature = "runtime-dispatch-simd"),
+    feature = "cpuid-dispatch-simd",
+    target_arch = "x86_64"
+))]
+use core::sync::atomic::{AtomicPtr, Ordering};
+#[cfg(all(
+    feature = "runtime-dispatch-simd",
+    any(target_arch = "x86", target_arch = "x86_64")
+))]
+use std::sync::atomic::{AtomicPtr, Ordering};
 
+mod byteset;
+pub use byteset::ByteSet;
 mod naive;
 pub use naive::*;
+mod bits;
+mod counter;
+pub use counter::Counter;
+#[cfg(all(
+    not(feature = "runtime-dispatch-simd"),
+    feature = "cpuid-dispatch-simd",
+    target_arch = "x86_64"
+))]
+mod cpuid;
 mod integer_simd;
+#[cfg(feature = "runtime-dispatch-simd")]
+mod line_index;
+#[cfg(feature = "runtime-dispatch-simd")]
+pub use line_index::LineIndex;
+#[cfg(feature = "runtime-dispatch-simd")]
+mod parallel;
+#[cfg(feature = "runtime-dispatch-simd")]
+pub use parallel::{par_count, par_num_chars, split_at_char_boundaries, split_at_lines};
+#[cfg(feature = "runtime-dispatch-simd")]
+mod reader;
+#[cfg(feature = "runtime-dispatch-simd")]
+pub use reader::{
+    count_reader, count_reader_with_buffer_size, num_chars_reader,
+    num_chars_reader_with_buffer_size, DEFAULT_BUFFER_SIZE,
+};
+mod substring;
+mod utf8;
+pub use utf8::{Utf8Counter, Utf8Error};
 
 #[cfg(any(
     all(
-        feature = "runtime-dispatch-simd",
+        any(feature = "runtime-dispatch-simd", target_feature = "sse2"),
         any(target_arch = "x86", target_arch = "x86_64")
     ),
+    all(feature = "cpuid-dispatch-simd", target_arch = "x86_64"),
     all(target_arch = "aarch64", target_endian = "little"),
     target_arch = "wasm32",
     feature = "generic-simd"
 ))]
 mod simd;
 
-/// Count occurrences of a byte in a slice of bytes, fast
+/// Whether the CPU supports an x86 target feature: detected at runtime
+/// with `runtime-dispatch-simd` or `cpuid-dispatch-simd`, or known at
+/// compile time without them
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+macro_rules! has_x86_feature {
+    ($feature:tt) => {{
+        #[cfg(feature = "runtime-dispatch-simd")]
+        let detected = is_x86_feature_detected!($feature);
+        #[cfg(all(
+            not(feature = "runtime-dispatch-simd"),
+            feature = "cpuid-dispatch-simd",
+            target_arch = "x86_64"
+        ))]
+        let detected = cpuid::is_x86_feature_detected($feature);
+        #[cfg(not(any(
+            feature = "runtime-dispatch-simd",
+            all(feature = "cpuid-dispatch-simd", target_arch = "x86_64")
+        )))]
+        let detected = cfg!(target_feature = $feature);
+        detected
+    }};
+}
+
+/// Return the result of a kernel from the fastest SIMD module that this
+/// target and CPU support and that takes an input of `$bytes` bytes
 ///
-/// # Examples
+/// `simd_dispatch!(chunk_x(args), bytes)` tries `chunk_x` of AVX2 and then
+/// SSE2 on x86, generic SIMD, NEON, wasm and finally SWAR on `usize`s, and
+/// falls through to the code after it if none of them apply. AVX2 and
+/// generic SIMD take at least 32 bytes, SSE2, NEON and wasm at least 16
+/// and SWAR at least a `usize`; SSE2 and generic SIMD are never used
+/// together. Kernels whose SSE2 version needs another x86 feature, or that
+/// have no SWAR version, pass them as in
+/// `simd_dispatch!(chunk_x(args), bytes, "ssse3", no_swar)`.
 ///
-/// ```
-/// let s = b"This is a Text with spaces";
-/// let number_of_spaces = bytecount::count(s, b' ');
-/// assert_eq!(number_of_spaces, 5);
-/// ```
-pub fn count(haystack: &[u8], needle: u8) -> usize {
-    if haystack.len() >= 32 {
-        #[cfg(all(feature = "runtime-dispatch-simd", target_arch = "x86_64"))]
-        {
-            if is_x86_feature_detected!("avx2") {
-                unsafe {
-                    return simd::x86_avx2::chunk_count(haystack, needle);
-                }
-            }
-        }
-
-        #[cfg(feature = "generic-simd")]
-        return simd::generic::chunk_count(haystack, needle);
-    }
-
-    if haystack.len() >= 16 {
+/// The `@avx2`, `@sse2` and `@portable` rules are single steps, taking
+/// whether the CPU has the feature as `$detected`, for the variants that
+/// `cached_dispatch!` picks between. `@cfg` puts the predicate of one
+/// module on an item or block.
+macro_rules! simd_dispatch {
+    (@cfg avx2 $($item:tt)*) => {
         #[cfg(all(
-            feature = "runtime-dispatch-simd",
+            any(
+                feature = "runtime-dispatch-simd",
+                feature = "cpuid-dispatch-simd",
+                target_feature = "avx2"
+            ),
+            target_arch = "x86_64"
+        ))]
+        $($item)*
+    };
+    (@cfg sse2 $($item:tt)*) => {
+        #[cfg(all(
+            any(feature = "runtime-dispatch-simd", target_feature = "sse2"),
             any(target_arch = "x86", target_arch = "x86_64"),
             not(feature = "generic-simd")
         ))]
-        {
-            if is_x86_feature_detected!("sse2") {
-                unsafe {
-                    return simd::x86_sse2::chunk_count(haystack, needle);
-                }
-            }
-        }
+        $($item)*
+    };
+    (@cfg generic $($item:tt)*) => {
+        #[cfg(feature = "generic-simd")]
+        $($item)*
+    };
+    (@cfg aarch64 $($item:tt)*) => {
         #[cfg(all(
             target_arch = "aarch64",
             target_endian = "little",
             not(feature = "generic-simd")
         ))]
-        {
+        $($item)*
+    };
+    (@cfg wasm $($item:tt)*) => {
+        #[cfg(target_arch = "wasm32")]
+        $($item)*
+    };
+    (@avx2 $kernel:ident($($arg:expr),*), $bytes:expr, $detected:expr) => {
+        simd_dispatch!(@cfg avx2 if $bytes >= 32 && $detected {
             unsafe {
-                return simd::aarch64::chunk_count(haystack, needle);
+                return simd::x86_avx2::$kernel($($arg),*);
             }
-        }
-
-        #[cfg(target_arch = "wasm32")]
-        {
+        });
+    };
+    (@sse2 $kernel:ident($($arg:expr),*), $bytes:expr, $detected:expr) => {
+        simd_dispatch!(@cfg sse2 if $bytes >= 16 && $detected {
+            unsafe {
+                return simd::x86_sse2::$kernel($($arg),*);
+            }
+        });
+    };
+    (@portable $kernel:ident($($arg:expr),*), $bytes:expr, $swar:ident) => {
+        simd_dispatch!(@cfg generic if $bytes >= 32 {
+            return simd::generic::$kernel($($arg),*);
+        });
+        simd_dispatch!(@cfg aarch64 if $bytes >= 16 {
+            unsafe {
+                return simd::aarch64::$kernel($($arg),*);
+            }
+        });
+        simd_dispatch!(@cfg wasm if $bytes >= 16 {
             unsafe {
-                return simd::wasm::chunk_count(haystack, needle);
+                return simd::wasm::$kernel($($arg),*);
             }
+        });
+        simd_dispatch!(@$swar $kernel($($arg),*), $bytes);
+    };
+    (@swar $kernel:ident($($arg:expr),*), $bytes:expr) => {
+        if $bytes >= mem::size_of::<usize>() {
+            return integer_simd::$kernel($($arg),*);
+        }
+    };
+    (@no_swar $kernel:ident($($arg:expr),*), $bytes:expr) => {};
+    ($kernel:ident($($arg:expr),*), $bytes:expr) => {
+        simd_dispatch!($kernel($($arg),*), $bytes, "sse2", swar)
+    };
+    ($kernel:ident($($arg:expr),*), $bytes:expr, $sse2:tt, $swar:ident) => {
+        simd_dispatch!(@avx2 $kernel($($arg),*), $bytes, has_x86_feature!("avx2"));
+        simd_dispatch!(@sse2 $kernel($($arg),*), $bytes, has_x86_feature!($sse2));
+        simd_dispatch!(@portable $kernel($($arg),*), $bytes, $swar);
+    };
+}
+
+/// Call the kernel that `$select` picks for this CPU, caching the choice in
+/// an atomic function pointer on first use, like `memchr` does
+///
+/// `$select` runs once per process (or a few times, if threads race on the
+/// first call) and must return a function of the given signature.
+#[cfg(any(
+    all(
+        feature = "runtime-dispatch-simd",
+        any(target_arch = "x86", target_arch = "x86_64")
+    ),
+    all(feature = "cpuid-dispatch-simd", target_arch = "x86_64")
+))]
+macro_rules! cached_dispatch {
+    ($select:ident, fn($($arg:ident: $ty:ty),*) -> $ret:ty) => {{
+        type Kernel = fn($($ty),*) -> $ret;
+        static KERNEL: AtomicPtr<()> = AtomicPtr::new(detect as *mut ());
+        fn detect($($arg: $ty),*) -> $ret {
+            let kernel: Kernel = $select();
+            KERNEL.store(kernel as *mut (), Ordering::Relaxed);
+            kernel($($arg),*)
         }
+        let kernel = KERNEL.load(Ordering::Relaxed);
+        // Safety: `KERNEL` only ever holds `detect` or a result of `$select`
+        let kernel = unsafe { mem::transmute::<*mut (), Kernel>(kernel) };
+        kernel($($arg),*)
+    }};
+}
+
+/// Count occurrences of a byte in a slice of bytes, fast
+///
+/// # Examples
+///
+/// ```
+/// let s = b"This is a Text with spaces";
+/// let number_of_spaces = bytecount::count(s, b' ');
+/// assert_eq!(number_of_spaces, 5);
+/// ```
+pub fn count(haystack: &[u8], needle: u8) -> usize {
+    #[cfg(any(
+        all(
+            feature = "runtime-dispatch-simd",
+            any(target_arch = "x86", target_arch = "x86_64")
+        ),
+        all(feature = "cpuid-dispatch-simd", target_arch = "x86_64")
+    ))]
+    return cached_dispatch!(select_count, fn(haystack: &[u8], needle: u8) -> usize);
+    #[cfg(not(any(
+        all(
+            feature = "runtime-dispatch-simd",
+            any(target_arch = "x86", target_arch = "x86_64")
+        ),
+        all(feature = "cpuid-dispatch-simd", target_arch = "x86_64")
+    )))]
+    select_count()(haystack, needle)
+}
+
+/// Pick the variant of [`count`](fn.count.html) for this CPU
+fn select_count() -> fn(&[u8], u8) -> usize {
+    simd_dispatch!(@cfg avx2 if has_x86_feature!("avx2") {
+        return count_avx2;
+    });
+    simd_dispatch!(@cfg sse2 if has_x86_feature!("sse2") {
+        return count_sse2;
+    });
+    count_portable
+}
+
+simd_dispatch! { @cfg avx2
+    /// [`count`](fn.count.html) for x86 CPUs with AVX2
+    fn count_avx2(haystack: &[u8], needle: u8) -> usize {
+        simd_dispatch!(@avx2 chunk_count(haystack, needle), haystack.len(), true);
+        simd_dispatch!(@sse2 chunk_count(haystack, needle), haystack.len(), true);
+        count_portable(haystack, needle)
     }
+}
 
-    if haystack.len() >= mem::size_of::<usize>() {
-        return integer_simd::chunk_count(haystack, needle);
+simd_dispatch! { @cfg sse2
+    /// [`count`](fn.count.html) for x86 CPUs with SSE2
+    fn count_sse2(haystack: &[u8], needle: u8) -> usize {
+        simd_dispatch!(@sse2 chunk_count(haystack, needle), haystack.len(), true);
+        count_portable(haystack, needle)
     }
+}
+
+/// [`count`](fn.count.html) without x86 SIMD
+fn count_portable(haystack: &[u8], needle: u8) -> usize {
+    simd_dispatch!(@portable chunk_count(haystack, needle), haystack.len(), swar);
 
     naive_count(haystack, needle)
 }
 
+/// Find the position of the `n`th occurrence of a byte in a slice of
+/// bytes, fast
+///
+/// Like [`Iterator::nth`], `n` counts from zero, so `nth(haystack, needle,
+/// 0)` finds the first occurrence. Returns `None` if there are at most `n`
+/// occurrences. Large parts of the haystack are skipped by counting, so
+/// this is useful e.g. to find the start of a given line in a large file.
+///
+/// # Examples
+///
+/// ```
+/// let text = b"first\nsecond\nthird\n";
+/// // the third line starts after the second newline
+/// let start = bytecount::nth(text, b'\n', 1).map_or(0, |i| i + 1);
+/// assert_eq!(&text[start..start + 5], b"third");
+/// assert_eq!(bytecount::nth(text, b'\n', 3), None);
+/// ```
+pub fn nth(haystack: &[u8], needle: u8, n: usize) -> Option<usize> {
+    simd_dispatch!(chunk_nth(haystack, needle, n), haystack.len());
+
+    naive_nth(haystack, needle, n)
+}
+
+/// Find the position of the `n`th occurrence of a byte in a slice of
+/// bytes, counting from the end, fast
+///
+/// `n` counts from zero, so `nth_from_end(haystack, needle, 0)` finds the
+/// last occurrence. Returns `None` if there are at most `n` occurrences.
+/// The haystack is scanned backwards, so only the part after the found
+/// occurrence is read.
+///
+/// # Examples
+///
+/// ```
+/// let log = b"one\ntwo\nthree\nfour\n";
+/// // the last two lines start after the third newline from the end
+/// let start = bytecount::nth_from_end(log, b'\n', 2).map_or(0, |i| i + 1);
+/// assert_eq!(&log[start..], b"three\nfour\n");
+/// assert_eq!(bytecount::nth_from_end(log, b'\n', 4), None);
+/// ```
+pub fn nth_from_end(haystack: &[u8], needle: u8, n: usize) -> Option<usize> {
+    let limit = n.checked_add(1)?;
+    match rcount_until(haystack, needle, limit) {
+        (count, start) if count == limit => Some(start),
+        _ => None,
+    }
+}
+
+/// Count occurrences of a byte from the end of a slice of bytes, stopping
+/// once `limit` have been found, fast
+///
+/// Returns the number of occurrences found, which is at most `limit`, and
+/// the offset where the backwards scan stopped: the position of the
+/// `limit`th occurrence from the end, or `0` if there are fewer than
+/// `limit` occurrences. Either way, `haystack[start..]` is the shortest
+/// suffix containing all counted occurrences, or the whole haystack.
+///
+/// # Examples
+///
+/// ```
+/// let log = b"one\ntwo\nthree\n";
+/// assert_eq!(bytecount::rcount_until(log, b'\n', 2), (2, 7));
+/// assert_eq!(bytecount::rcount_until(log, b'\n', 5), (3, 0));
+/// assert_eq!(bytecount::rcount_until(log, b'\n', 0), (0, log.len()));
+/// ```
+pub fn rcount_until(haystack: &[u8], needle: u8, limit: usize) -> (usize, usize) {
+    if limit == 0 {
+        return (0, haystack.len());
+    }
+
+    simd_dispatch!(chunk_rcount_until(haystack, needle, limit), haystack.len());
+
+    naive_rcount_until(haystack, needle, limit)
+}
+
+/// Count occurrences of any of a small set of bytes in a slice of bytes, fast
+///
+/// This makes a single pass over the haystack, so it is considerably
+/// faster than calling [`count`](fn.count.html) once per needle. Each
+/// byte is counted at most once, even if it appears more than once in
+/// `needles`. Sets of more than eight needles are handed to
+/// [`count_set`](fn.count_set.html).
+///
+/// # Examples
+///
+/// ```
+/// let s = b"line one\r\nline two\nline three\r\n";
+/// let line_endings = bytecount::count_any(s, b"\r\n");
+/// assert_eq!(line_endings, 5);
+/// ```
+pub fn count_any(haystack: &[u8], needles: &[u8]) -> usize {
+    if needles.len() > 8 {
+        return count_set(haystack, &ByteSet::from_bytes(needles));
+    }
+
+    simd_dispatch!(chunk_count_any(haystack, needles), haystack.len());
+
+    naive_count_any(haystack, needles)
+}
+
+/// Count occurrences of each of several bytes in a slice of bytes, fast
+///
+/// The counts are returned in the same order as the needles. Unlike
+/// [`count_any`](fn.count_any.html), every needle is counted separately,
+/// but the haystack is still only read once, so this is faster than
+/// calling [`count`](fn.count.html) once per needle as long as `N` is
+/// small.
+///
+/// # Examples
+///
+/// ```
+/// let csv = b"id,name\n1,\"Smith, John\"\n2,Doe\n";
+/// let [newlines, commas, quotes] = bytecount::count_many(csv, [b'\n', b',', b'"']);
+/// assert_eq!((newlines, commas, quotes), (3, 4, 2));
+/// ```
+pub fn count_many<const N: usize>(haystack: &[u8], needles: [u8; N]) -> [usize; N] {
+    simd_dispatch!(chunk_count_many(haystack, needles), haystack.len());
+
+    naive_count_many(haystack, needles)
+}
+
+/// Count bytes belonging to a [`ByteSet`](struct.ByteSet.html) in a slice
+/// of bytes, fast
+///
+/// # Examples
+///
+/// ```
+/// use bytecount::ByteSet;
+///
+/// let base64 = ByteSet::from_predicate(|b| b.is_ascii_alphanumeric() || b == b'+' || b == b'/');
+/// let s = b"aGVsbG8gd29ybGQ=";
+/// assert_eq!(bytecount::count_set(s, &base64), 15);
+/// ```
+pub fn count_set(haystack: &[u8], set: &ByteSet) -> usize {
+    simd_dispatch!(chunk_count_set(haystack, set.table()), haystack.len(), "ssse3", no_swar);
+
+    naive_count_set(haystack, set)
+}
+
+/// Count non-overlapping occurrences of a byte string in a slice of bytes,
+/// fast
+///
+/// Like [`str::matches`], occurrences are found from the left and each
+/// match continues the search after its end. An empty needle matches
+/// `haystack.len() + 1` times.
+///
+/// # Examples
+///
+/// ```
+/// let s = b"line one\r\nline two\r\n\r\n";
+/// assert_eq!(bytecount::count_substring(s, b"\r\n"), 3);
+/// assert_eq!(bytecount::count_substring(b"aaaa", b"aa"), 2);
+/// ```
+pub fn count_substring(haystack: &[u8], needle: &[u8]) -> usize {
+    chunk_count_substring(haystack, needle, false)
+}
+
+/// Count possibly overlapping occurrences of a byte string in a slice of
+/// bytes, fast
+///
+/// Every position at which the needle occurs is counted. An empty needle
+/// matches `haystack.len() + 1` times.
+///
+/// # Examples
+///
+/// ```
+/// assert_eq!(bytecount::count_substring_overlapping(b"aaaa", b"aa"), 3);
+/// ```
+pub fn count_substring_overlapping(haystack: &[u8], needle: &[u8]) -> usize {
+    chunk_count_substring(haystack, needle, true)
+}
+
+fn chunk_count_substring(haystack: &[u8], needle: &[u8], overlapping: bool) -> usize {
+    if needle.is_empty() {
+        return haystack.len() + 1;
+    }
+    if needle.len() == 1 {
+        return count(haystack, needle[0]);
+    }
+    if haystack.len() < needle.len() {
+        return 0;
+    }
+
+    // The number of positions the needle could start at
+    let positions = haystack.len() - needle.len() + 1;
+
+    simd_dispatch!(chunk_count_substring(haystack, needle, overlapping), positions);
+
+    if overlapping {
+        naive_count_substring_overlapping(haystack, needle)
+    } else {
+        naive_count_substring(haystack, needle)
+    }
+}
+
+/// Count bytes within an inclusive range in a slice of bytes, fast
+///
+/// This is handy for counting e.g. ASCII digits, control characters or
+/// non-ASCII bytes. An empty range (`lo > hi`) matches nothing.
+///
+/// # Examples
+///
+/// ```
+/// let s = "Grüße aus Köln!".as_bytes();
+/// let non_ascii = bytecount::count_range(s, 0x80..=0xFF);
+/// assert_eq!(non_ascii, 6);
+/// ```
+pub fn count_range(haystack: &[u8], range: RangeInclusive<u8>) -> usize {
+    let (lo, hi) = range.into_inner();
+    if lo > hi {
+        return 0;
+    }
+
+    simd_dispatch!(chunk_count_range(haystack, lo, hi), haystack.len());
+
+    naive_count_range(haystack, lo..=hi)
+}
+
+/// Count occurrences of every byte value in a slice of bytes, fast
+///
+/// The result is indexed by byte value. This makes a single pass over the
+/// haystack, which is far quicker than calling [`count`](fn.count.html)
+/// for each of the 256 possible bytes.
+///
+/// # Examples
+///
+/// ```
+/// let histogram = bytecount::histogram(b"abracadabra");
+/// assert_eq!(histogram[b'a' as usize], 5);
+/// assert_eq!(histogram[b'z' as usize], 0);
+/// ```
+pub fn histogram(haystack: &[u8]) -> [usize; 256] {
+    let mut counts = [0; 256];
+    histogram_into(&mut counts, haystack);
+
+    let mut histogram = [0; 256];
+    for (bin, &count) in histogram.iter_mut().zip(counts.iter()) {
+        *bin = count as usize;
+    }
+    histogram
+}
+
+/// Add the occurrences of every byte value in a slice of bytes to
+/// `histogram`, fast
+///
+/// This is useful for building a single histogram over data that arrives
+/// in several pieces.
+///
+/// # Examples
+///
+/// ```
+/// let mut histogram = [0; 256];
+/// bytecount::histogram_into(&mut histogram, b"abra");
+/// bytecount::histogram_into(&mut histogram, b"cadabra");
+/// assert_eq!(histogram[b'a' as usize], 5);
+/// ```
+pub fn histogram_into(histogram: &mut [u64; 256], haystack: &[u8]) {
+    if haystack.len() >= 1024 {
+        return integer_simd::chunk_histogram(haystack, histogram);
+    }
+
+    for &byte in haystack {
+        histogram[byte as usize] += 1;
+    }
+}
+
+/// Byte, line and character counts of a slice of bytes, as returned by
+/// [`text_stats`](fn.text_stats.html)
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct TextStats {
+    /// The number of bytes
+    pub bytes: usize,
+    /// The number of newline (`\n`) bytes
+    pub newlines: usize,
+    /// The number of UTF-8 encoded Unicode codepoints, as counted by
+    /// [`num_chars`](fn.num_chars.html)
+    pub chars: usize,
+}
+
+/// Count bytes, newlines and UTF-8 encoded Unicode codepoints in a slice of
+/// bytes in a single pass, fast
+///
+/// This gives the same result as calling [`count`](fn.count.html) with
+/// `b'\n'` and [`num_chars`](fn.num_chars.html), but reads the data only
+/// once. As with `num_chars`, the character count is only meaningful for
+/// well-formed UTF-8.
+///
+/// # Example
+///
+/// ```
+/// let stats = bytecount::text_stats("Grüße\naus\nKöln\n".as_bytes());
+/// assert_eq!(stats.bytes, 18);
+/// assert_eq!(stats.newlines, 3);
+/// assert_eq!(stats.chars, 15);
+/// ```
+pub fn text_stats(utf8_chars: &[u8]) -> TextStats {
+    let (newlines, chars) = chunk_text_stats(utf8_chars);
+    TextStats {
+        bytes: utf8_chars.len(),
+        newlines,
+        chars,
+    }
+}
+
+fn chunk_text_stats(utf8_chars: &[u8]) -> (usize, usize) {
+    simd_dispatch!(chunk_text_stats(utf8_chars), utf8_chars.len());
+
+    (naive_count(utf8_chars, b'\n'), naive_num_chars(utf8_chars))
+}
+
 /// Count the number of UTF-8 encoded Unicode codepoints in a slice of bytes, fast
 ///
 /// This function is safe to use on any byte array, valid UTF-8 or not,
@@ -131,55 +665,450 @@ pub fn count(haystack: &[u8], needle: u8) -> usize {
 /// assert_eq!(char_count, 4);
 /// ```
 pub fn num_chars(utf8_chars: &[u8]) -> usize {
-    if utf8_chars.len() >= 32 {
-        #[cfg(all(feature = "runtime-dispatch-simd", target_arch = "x86_64"))]
-        {
-            if is_x86_feature_detected!("avx2") {
-                unsafe {
-                    return simd::x86_avx2::chunk_num_chars(utf8_chars);
-                }
+    #[cfg(any(
+        all(
+            feature = "runtime-dispatch-simd",
+            any(target_arch = "x86", target_arch = "x86_64")
+        ),
+        all(feature = "cpuid-dispatch-simd", target_arch = "x86_64")
+    ))]
+    return cached_dispatch!(select_num_chars, fn(utf8_chars: &[u8]) -> usize);
+    #[cfg(not(any(
+        all(
+            feature = "runtime-dispatch-simd",
+            any(target_arch = "x86", target_arch = "x86_64")
+        ),
+        all(feature = "cpuid-dispatch-simd", target_arch = "x86_64")
+    )))]
+    select_num_chars()(utf8_chars)
+}
+
+/// Pick the variant of [`num_chars`](fn.num_chars.html) for this CPU
+fn select_num_chars() -> fn(&[u8]) -> usize {
+    simd_dispatch!(@cfg avx2 if has_x86_feature!("avx2") {
+        return num_chars_avx2;
+    });
+    simd_dispatch!(@cfg sse2 if has_x86_feature!("sse2") {
+        return num_chars_sse2;
+    });
+    num_chars_portable
+}
+
+simd_dispatch! { @cfg avx2
+    /// [`num_chars`](fn.num_chars.html) for x86 CPUs with AVX2
+    fn num_chars_avx2(utf8_chars: &[u8]) -> usize {
+        simd_dispatch!(@avx2 chunk_num_chars(utf8_chars), utf8_chars.len(), true);
+        simd_dispatch!(@sse2 chunk_num_chars(utf8_chars), utf8_chars.len(), true);
+        num_chars_portable(utf8_chars)
+    }
+}
+
+simd_dispatch! { @cfg sse2
+    /// [`num_chars`](fn.num_chars.html) for x86 CPUs with SSE2
+    fn num_chars_sse2(utf8_chars: &[u8]) -> usize {
+        simd_dispatch!(@sse2 chunk_num_chars(utf8_chars), utf8_chars.len(), true);
+        num_chars_portable(utf8_chars)
+    }
+}
+
+/// [`num_chars`](fn.num_chars.html) without x86 SIMD
+fn num_chars_portable(utf8_chars: &[u8]) -> usize {
+    simd_dispatch!(@portable chunk_num_chars(utf8_chars), utf8_chars.len(), swar);
+
+    naive_num_chars(utf8_chars)
+}
+
+/// Count the number of UTF-16 code units needed to encode the UTF-8
+/// encoded text in a slice of bytes, fast
+///
+/// This is the number of codepoints plus one for each codepoint outside
+/// the Basic Multilingual Plane, which takes a surrogate pair. As with
+/// [`num_chars`](fn.num_chars.html), the output is only meaningful for
+/// well-formed UTF-8.
+///
+/// # Example
+///
+/// ```
+/// let text = "I ❤ 🦀";
+/// assert_eq!(bytecount::utf16_len(text.as_bytes()), text.encode_utf16().count());
+/// assert_eq!(bytecount::utf16_len(text.as_bytes()), 6);
+/// ```
+pub fn utf16_len(utf8_chars: &[u8]) -> usize {
+    simd_dispatch!(chunk_utf16_len(utf8_chars), utf8_chars.len());
+
+    naive_utf16_len(utf8_chars)
+}
+
+/// Count the number of UTF-16 code units needed to encode the UTF-8
+/// encoded text in a slice of bytes, fast
+///
+/// This is the same as [`utf16_len`](fn.utf16_len.html), named to go
+/// with [`utf8_len_from_utf16`](fn.utf8_len_from_utf16.html) and
+/// [`utf8_len_from_latin1`](fn.utf8_len_from_latin1.html) when sizing
+/// transcoding buffers.
+///
+/// # Example
+///
+/// ```
+/// let text = "Grüße 🦀";
+/// let mut buffer = vec![0u16; bytecount::utf16_len_from_utf8(text.as_bytes())];
+/// for (unit, slot) in text.encode_utf16().zip(buffer.iter_mut()) {
+///     *slot = unit;
+/// }
+/// assert_eq!(String::from_utf16(&buffer).unwrap(), text);
+/// ```
+pub fn utf16_len_from_utf8(utf8_chars: &[u8]) -> usize {
+    utf16_len(utf8_chars)
+}
+
+/// Count the number of bytes needed to encode UTF-16 encoded text as
+/// UTF-8, fast
+///
+/// Code units below `0x80` take one byte, those below `0x800` two, each
+/// half of a surrogate pair two and all others three. This is only
+/// meaningful for well-formed UTF-16; unpaired surrogates are counted as
+/// two bytes, not as the three of a replacement character.
+///
+/// # Example
+///
+/// ```
+/// let text: Vec<u16> = "Grüße 🦀".encode_utf16().collect();
+/// assert_eq!(bytecount::utf8_len_from_utf16(&text), "Grüße 🦀".len());
+/// ```
+pub fn utf8_len_from_utf16(utf16: &[u16]) -> usize {
+    simd_dispatch!(chunk_utf8_len_from_utf16(utf16), utf16.len() * 2);
+
+    naive_utf8_len_from_utf16(utf16)
+}
+
+/// Count the number of Unicode codepoints in a slice of UTF-16 code
+/// units, fast
+///
+/// Every code unit except low surrogates starts a codepoint, just like
+/// [`num_chars`](fn.num_chars.html) skips UTF-8 continuation bytes. This
+/// is safe to use on any slice, but the output is only meaningful for
+/// well-formed UTF-16.
+///
+/// # Example
+///
+/// ```
+/// let text: Vec<u16> = "I ❤ 🦀".encode_utf16().collect();
+/// assert_eq!(bytecount::num_chars_utf16(&text), 5);
+/// ```
+pub fn num_chars_utf16(utf16: &[u16]) -> usize {
+    simd_dispatch!(chunk_num_chars_utf16(utf16), utf16.len() * 2);
+
+    naive_num_chars_utf16(utf16)
+}
+
+/// Count the number of bytes needed to encode Latin-1 (ISO 8859-1)
+/// encoded text as UTF-8, fast
+///
+/// Every byte from `0x80` on takes two bytes in UTF-8, all others one.
+///
+/// # Example
+///
+/// ```
+/// let latin1 = b"Gr\xfc\xdfe";
+/// assert_eq!(bytecount::utf8_len_from_latin1(latin1), "Grüße".len());
+/// ```
+pub fn utf8_len_from_latin1(latin1: &[u8]) -> usize {
+    latin1.len() + count_range(latin1, 0x80..=0xFF)
+}
+
+/// Convert a byte offset in UTF-8 encoded text to a line and UTF-16 column,
+/// fast
+///
+/// This is the position format of the Language Server Protocol: lines are
+/// separated by `b'\n'` and the column counts UTF-16 code units from the
+/// start of the line, both from zero. Returns `None` if `offset` is past
+/// the end of the slice or not on a character boundary.
+///
+/// # Example
+///
+/// ```
+/// let text = "let 🦀 = 1;\nlet x = 🦀;";
+/// let offset = text.rfind('🦀').unwrap();
+/// assert_eq!(bytecount::offset_to_utf16_position(text.as_bytes(), offset), Some((1, 8)));
+/// ```
+pub fn offset_to_utf16_position(utf8_chars: &[u8], offset: usize) -> Option<(usize, usize)> {
+    if let Some(&byte) = utf8_chars.get(offset) {
+        if (byte >> 6) == 0b10 {
+            return None;
+        }
+    }
+    let before = utf8_chars.get(..offset)?;
+    let (line, start) = match rcount_until(before, b'\n', 1) {
+        (0, _) => (0, 0),
+        (_, newline) => (count(&before[..newline], b'\n') + 1, newline + 1),
+    };
+    Some((line, utf16_len(&before[start..])))
+}
+
+/// Convert a line and UTF-16 column in UTF-8 encoded text to a byte offset,
+/// fast
+///
+/// This is the inverse of
+/// [`offset_to_utf16_position`](fn.offset_to_utf16_position.html). Returns
+/// `None` if there is no such line, if the column is past the end of the
+/// line or if it points between the two halves of a surrogate pair.
+///
+/// # Example
+///
+/// ```
+/// let text = "let 🦀 = 1;\nlet x = 🦀;";
+/// let offset = bytecount::utf16_position_to_offset(text.as_bytes(), 1, 8);
+/// assert_eq!(offset, text.rfind('🦀'));
+/// assert_eq!(bytecount::utf16_position_to_offset(text.as_bytes(), 0, 5), None);
+/// ```
+pub fn utf16_position_to_offset(utf8_chars: &[u8], line: usize, column: usize) -> Option<usize> {
+    let start = match line {
+        0 => 0,
+        _ => nth(utf8_chars, b'\n', line - 1)? + 1,
+    };
+    let text = &utf8_chars[start..];
+    let text = &text[..nth(text, b'\n', 0).unwrap_or(text.len())];
+
+    let mut units = column;
+    let mut offset = 0;
+    for block in text.chunks(CHAR_BLOCK) {
+        let block_units = utf16_len(block);
+        if block_units > units {
+            break;
+        }
+        units -= block_units;
+        offset += block.len();
+    }
+
+    for (i, &byte) in text[offset..].iter().enumerate() {
+        if (byte >> 6) != 0b10 {
+            if units == 0 {
+                return Some(start + offset + i);
             }
+            let width = if byte >= 0b1111_0000 { 2 } else { 1 };
+            if units < width {
+                return None;
+            }
+            units -= width;
         }
+    }
 
-        #[cfg(feature = "generic-simd")]
-        return simd::generic::chunk_num_chars(utf8_chars);
+    if units == 0 {
+        Some(start + text.len())
+    } else {
+        None
     }
+}
 
-    if utf8_chars.len() >= 16 {
-        #[cfg(all(
-            feature = "runtime-dispatch-simd",
-            any(target_arch = "x86", target_arch = "x86_64"),
-            not(feature = "generic-simd")
-        ))]
-        {
-            if is_x86_feature_detected!("sse2") {
-                unsafe {
-                    return simd::x86_sse2::chunk_num_chars(utf8_chars);
+/// Count the number of UTF-8 encoded Unicode codepoints in a slice of
+/// bytes, checking that it is valid UTF-8, fast
+///
+/// Unlike [`num_chars`](fn.num_chars.html), this fails on malformed input
+/// with the same error information as `core::str::from_utf8`. Valid input
+/// is validated and counted in a single SIMD pass; only invalid input is
+/// read again to find where the error is.
+///
+/// # Example
+///
+/// ```
+/// assert_eq!(bytecount::try_num_chars("Grüße".as_bytes()), Ok(5));
+///
+/// let error = bytecount::try_num_chars(b"Gr\xfc\xdfe").unwrap_err();
+/// assert_eq!(error.valid_up_to(), 2);
+/// assert_eq!(error.error_len(), Some(1));
+/// ```
+pub fn try_num_chars(utf8_chars: &[u8]) -> Result<usize, Utf8Error> {
+    match count_valid(utf8_chars) {
+        (chars, None) => Ok(chars),
+        (_, Some(error)) => Err(error),
+    }
+}
+
+/// Count the codepoints in the longest valid UTF-8 prefix of a slice of
+/// bytes, together with the error that ends that prefix, if any.
+pub(crate) fn count_valid(utf8_chars: &[u8]) -> (usize, Option<Utf8Error>) {
+    if let Some(chars) = chunk_try_num_chars(utf8_chars) {
+        return (chars, None);
+    }
+    // The kernels only tell whether the input is valid, so find the error
+    // with `core`, reading the input again
+    let error = str::from_utf8(utf8_chars).unwrap_err();
+    let valid_up_to = error.valid_up_to();
+    let chars = num_chars(&utf8_chars[..valid_up_to]);
+    (chars, Some(Utf8Error::new(valid_up_to, error.error_len())))
+}
+
+/// The number of codepoints in a slice of bytes if it is valid UTF-8
+fn chunk_try_num_chars(utf8_chars: &[u8]) -> Option<usize> {
+    simd_dispatch!(chunk_try_num_chars(utf8_chars), utf8_chars.len(), "ssse3", no_swar);
+
+    str::from_utf8(utf8_chars).ok().map(|_| num_chars(utf8_chars))
+}
+
+/// Count the number of Unicode codepoints that lossy decoding of a slice
+/// of bytes produces, fast
+///
+/// This agrees with `String::from_utf8_lossy(utf8_chars).chars().count()`:
+/// each maximal invalid sequence counts as one U+FFFD replacement
+/// character. Valid input is counted as fast as by
+/// [`num_chars`](fn.num_chars.html), plus validation.
+///
+/// # Example
+///
+/// ```
+/// let bytes = b"Gr\xfc\xdfe \xf0\x9f\xa6";
+/// assert_eq!(bytecount::num_chars_lossy(bytes), 7);
+/// assert_eq!(bytecount::num_chars_lossy(bytes), String::from_utf8_lossy(bytes).chars().count());
+/// ```
+pub fn num_chars_lossy(utf8_chars: &[u8]) -> usize {
+    let mut start = 0;
+    let mut chars = 0;
+    while start < utf8_chars.len() {
+        let end = utf8_chars.len().min(start + CHAR_BLOCK);
+        let block = &utf8_chars[start..end];
+        match count_valid(block) {
+            (valid, None) => {
+                chars += valid;
+                start = end;
+            }
+            (valid, Some(error)) => {
+                chars += valid;
+                start += error.valid_up_to();
+                match error.error_len() {
+                    Some(len) => {
+                        chars += 1;
+                        start += len;
+                    }
+                    None if end == utf8_chars.len() => {
+                        chars += 1;
+                        start = end;
+                    }
+                    // A sequence cut off by the end of the block; continue
+                    // with the next block from its start
+                    None => {}
                 }
             }
         }
-        #[cfg(all(
-            target_arch = "aarch64",
-            target_endian = "little",
-            not(feature = "generic-simd")
-        ))]
-        {
-            unsafe {
-                return simd::aarch64::chunk_num_chars(utf8_chars);
-            }
+    }
+    chars
+}
+
+/// Count the UTF-8 encoded Unicode codepoints in a slice of bytes by
+/// their encoded length, fast
+///
+/// Element `i` of the first four is the number of leading bytes of
+/// sequences of `i + 1` bytes, i.e. of ASCII bytes, `0xC0..=0xDF`,
+/// `0xE0..=0xEF` and `0xF0..=0xF7`. The last element counts the bytes that
+/// start no sequence: continuation bytes and the invalid bytes
+/// `0xF8..=0xFF`. All five come out of the same single pass and add up to
+/// `utf8_chars.len()`.
+///
+/// # Example
+///
+/// ```
+/// let text = "Grüße aus Köln! 🦀";
+/// let histogram = bytecount::utf8_length_histogram(text.as_bytes());
+/// assert_eq!(histogram, [13, 3, 0, 1, 6]);
+/// assert_eq!(histogram.iter().sum::<usize>(), text.len());
+/// ```
+pub fn utf8_length_histogram(utf8_chars: &[u8]) -> [usize; 5] {
+    let [ascii, below_two, below_three, below_four, below_invalid] =
+        count_below(utf8_chars, [0x80, 0xC0, 0xE0, 0xF0, 0xF8]);
+    [
+        ascii,
+        below_three - below_two,
+        below_four - below_three,
+        below_invalid - below_four,
+        below_two - ascii + utf8_chars.len() - below_invalid,
+    ]
+}
+
+// Count the bytes below each of several nonzero bounds.
+fn count_below<const N: usize>(haystack: &[u8], bounds: [u8; N]) -> [usize; N] {
+    simd_dispatch!(chunk_count_below(haystack, bounds), haystack.len());
+
+    bounds.map(|bound| haystack.iter().filter(|&&byte| byte < bound).count())
+}
+
+// The size of the blocks skipped with `num_chars` when looking for a
+// character; only the block containing it is scanned byte by byte.
+const CHAR_BLOCK: usize = 4096;
+
+/// Find the byte offset of the `n`th UTF-8 encoded Unicode codepoint in a
+/// slice of bytes, fast
+///
+/// `n` counts from zero. For `n` equal to the number of codepoints this
+/// returns the length of the slice, so the result can be used to truncate
+/// to `n` characters; larger `n` gives `None`. As with
+/// [`num_chars`](fn.num_chars.html), the result is only meaningful for
+/// well-formed UTF-8.
+///
+/// # Example
+///
+/// ```
+/// let text = "Grüße aus Köln";
+/// let offset = bytecount::char_to_byte_offset(text.as_bytes(), 5).unwrap();
+/// assert_eq!(&text[..offset], "Grüße");
+/// assert_eq!(bytecount::char_to_byte_offset(text.as_bytes(), 14), Some(text.len()));
+/// assert_eq!(bytecount::char_to_byte_offset(text.as_bytes(), 15), None);
+/// ```
+pub fn char_to_byte_offset(utf8_chars: &[u8], mut n: usize) -> Option<usize> {
+    let mut offset = 0;
+    for block in utf8_chars.chunks(CHAR_BLOCK) {
+        let chars = num_chars(block);
+        if chars > n {
+            break;
         }
+        n -= chars;
+        offset += block.len();
+    }
 
-        #[cfg(target_arch = "wasm32")]
-        {
-            unsafe {
-                return simd::wasm::chunk_num_chars(utf8_chars);
+    for (i, &byte) in utf8_chars[offset..].iter().enumerate() {
+        if (byte >> 6) != 0b10 {
+            if n == 0 {
+                return Some(offset + i);
             }
+            n -= 1;
         }
     }
 
-    if utf8_chars.len() >= mem::size_of::<usize>() {
-        return integer_simd::chunk_num_chars(utf8_chars);
+    if n == 0 {
+        Some(utf8_chars.len())
+    } else {
+        None
     }
+}
 
-    naive_num_chars(utf8_chars)
+/// Find the index of the UTF-8 encoded Unicode codepoint starting at a
+/// byte offset in a slice of bytes, fast
+///
+/// This is the inverse of [`char_to_byte_offset`](fn.char_to_byte_offset.html):
+/// it returns the number of codepoints before `offset`, or `None` if
+/// `offset` is past the end of the slice or not on a character boundary.
+///
+/// # Example
+///
+/// ```
+/// let text = "Grüße aus Köln";
+/// assert_eq!(bytecount::byte_to_char_offset(text.as_bytes(), 7), Some(5));
+/// assert_eq!(bytecount::byte_to_char_offset(text.as_bytes(), 3), None);
+/// assert_eq!(bytecount::byte_to_char_offset(text.as_bytes(), text.len()), Some(14));
+/// ```
+pub fn byte_to_char_offset(utf8_chars: &[u8], offset: usize) -> Option<usize> {
+    match utf8_chars.get(offset) {
+        Some(&byte) if (byte >> 6) == 0b10 => None,
+        _ => utf8_chars.get(..offset).map(num_chars),
+    }
+}
+
+// Call `f` with the position of every occurrence of `needle`, in order.
+#[cfg(feature = "runtime-dispatch-simd")]
+fn for_each_position<F: FnMut(usize)>(haystack: &[u8], needle: u8, mut f: F) {
+    simd_dispatch!(chunk_positions(haystack, needle, f), haystack.len());
+
+    haystack
+        .iter()
+        .enumerate()
+        .filter(|&(_, &byte)| byte == needle)
+        .for_each(|(i, _)| f(i))
 }
@@ -0,0 +1,75 @@
+use crate::for_each_position;
+
+/// The positions of all newlines in a slice of bytes, for mapping byte
+/// offsets to lines and columns
+///
+/// Lines are separated by `b'\n'`, which belongs to the line it ends, so a
+/// text ending in a newline has an empty last line, like in most editors.
+/// Lines and columns count from zero, and columns are byte offsets from
+/// the start of the line; use [`byte_to_char_offset`](fn.byte_to_char_offset.html)
+/// on the line to get a character column instead.
+///
+/// # Example
+///
+/// ```
+/// let text = b"first\nsecond\n\nfourth";
+/// let index = bytecount::LineIndex::new(text);
+/// assert_eq!(index.line_count(), 4);
+/// assert_eq!(index.line_start(1), Some(6));
+/// assert_eq!(index.line_of(9), Some(1));
+/// assert_eq!(index.line_col(16), Some((3, 2)));
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct LineIndex {
+    newlines: Vec<usize>,
+    len: usize,
+}
+
+impl LineIndex {
+    /// Build the index of a slice of bytes
+    pub fn new(haystack: &[u8]) -> Self {
+        let mut newlines = Vec::new();
+        for_each_position(haystack, b'\n', |i| newlines.push(i));
+        LineIndex {
+            newlines,
+            len: haystack.len(),
+        }
+    }
+
+    /// The number of lines, which is one more than the number of newlines
+    pub fn line_count(&self) -> usize {
+        self.newlines.len() + 1
+    }
+
+    /// The byte offset where `line` starts, or `None` if there is no such
+    /// line
+    pub fn line_start(&self, line: usize) -> Option<usize> {
+        match line {
+            0 => Some(0),
+            _ => self.newlines.get(line - 1).map(|&newline| newline + 1),
+        }
+    }
+
+    /// The line containing the byte at `offset`, or `None` if `offset` is
+    /// past the end of the indexed slice
+    ///
+    /// The end of the slice itself belongs to the last line.
+    pub fn line_of(&self, offset: usize) -> Option<usize> {
+        if offset > self.len {
+            return None;
+        }
+        Some(
+            self.newlines
+                .binary_search(&offset)
+                .unwrap_or_else(|line| line),
+        )
+    }
+
+    /// The line and column of the byte at `offset`, or `None` if `offset`
+    /// is past the end of the indexed slice
+    pub fn line_col(&self, offset: usize) -> Option<(usize, usize)> {
+        let line = self.line_of(offset)?;
+        let start = self.line_start(line)?;
+        Some((line, offset - start))
+    }
+}
@@ -1,3 +1,10 @@
+#[cfg(not(feature = "runtime-dispatch-simd"))]
+use core::{ops::RangeInclusive, str};
+#[cfg(feature = "runtime-dispatch-simd")]
+use std::{ops::RangeInclusive, str};
+
+use crate::{ByteSet, Utf8Error};
+
 /// Count up to `(2^32)-1` occurrences of a byte in a slice
 /// of bytes, simple
 ///
@@ -27,6 +34,187 @@ pub fn naive_count(utf8_chars: &[u8], needle: u8) -> usize {
         .fold(0, |n, c| n + (*c == needle) as usize)
 }
 
+/// Find the position of the `n`th occurrence (counting from zero) of a
+/// byte in a slice of bytes, simple
+///
+/// # Example
+///
+/// ```
+/// let s = b"This is yet another Text with spaces";
+/// assert_eq!(bytecount::naive_nth(s, b' ', 1), Some(7));
+/// ```
+pub fn naive_nth(haystack: &[u8], needle: u8, n: usize) -> Option<usize> {
+    haystack
+        .iter()
+        .enumerate()
+        .filter(|&(_, &byte)| byte == needle)
+        .nth(n)
+        .map(|(i, _)| i)
+}
+
+/// Find the position of the `n`th occurrence (counting from zero) of a
+/// byte in a slice of bytes, counting from the end, simple
+///
+/// # Example
+///
+/// ```
+/// let s = b"This is yet another Text with spaces";
+/// assert_eq!(bytecount::naive_nth_from_end(s, b' ', 1), Some(24));
+/// ```
+pub fn naive_nth_from_end(haystack: &[u8], needle: u8, n: usize) -> Option<usize> {
+    haystack
+        .iter()
+        .enumerate()
+        .rev()
+        .filter(|&(_, &byte)| byte == needle)
+        .nth(n)
+        .map(|(i, _)| i)
+}
+
+/// Count occurrences of a byte from the end of a slice of bytes, stopping
+/// once `limit` have been found, simple
+///
+/// # Example
+///
+/// ```
+/// let s = b"This is yet another Text with spaces";
+/// assert_eq!(bytecount::naive_rcount_until(s, b' ', 2), (2, 24));
+/// ```
+pub fn naive_rcount_until(haystack: &[u8], needle: u8, limit: usize) -> (usize, usize) {
+    if limit == 0 {
+        return (0, haystack.len());
+    }
+    let mut count = 0;
+    for (i, &byte) in haystack.iter().enumerate().rev() {
+        if byte == needle {
+            count += 1;
+            if count == limit {
+                return (count, i);
+            }
+        }
+    }
+    (count, 0)
+}
+
+/// Count occurrences of any of a set of bytes in a slice of bytes, simple
+///
+/// # Example
+///
+/// ```
+/// let s = b"This is yet another Text with spaces, and a comma";
+/// let number_of_separators = bytecount::naive_count_any(s, b" ,");
+/// assert_eq!(number_of_separators, 10);
+/// ```
+pub fn naive_count_any(haystack: &[u8], needles: &[u8]) -> usize {
+    haystack
+        .iter()
+        .filter(|byte| needles.contains(byte))
+        .count()
+}
+
+/// Count occurrences of each of several bytes in a slice of bytes, simple
+///
+/// # Example
+///
+/// ```
+/// let s = b"This is yet another Text with spaces, and a comma";
+/// let [spaces, commas] = bytecount::naive_count_many(s, [b' ', b',']);
+/// assert_eq!((spaces, commas), (9, 1));
+/// ```
+pub fn naive_count_many<const N: usize>(haystack: &[u8], needles: [u8; N]) -> [usize; N] {
+    needles.map(|needle| naive_count(haystack, needle))
+}
+
+/// Count bytes belonging to a [`ByteSet`](struct.ByteSet.html) in a slice
+/// of bytes, simple
+///
+/// # Example
+///
+/// ```
+/// let digits = bytecount::ByteSet::from_range(b'0'..=b'9');
+/// let s = b"Call 555-0199 or 555-0123";
+/// assert_eq!(bytecount::naive_count_set(s, &digits), 14);
+/// ```
+pub fn naive_count_set(haystack: &[u8], set: &ByteSet) -> usize {
+    haystack.iter().filter(|&&byte| set.contains(byte)).count()
+}
+
+/// Count non-overlapping occurrences of a byte string in a slice of bytes,
+/// simple
+///
+/// An empty needle matches `haystack.len() + 1` times.
+///
+/// # Example
+///
+/// ```
+/// assert_eq!(bytecount::naive_count_substring(b"aaaa", b"aa"), 2);
+/// ```
+pub fn naive_count_substring(haystack: &[u8], needle: &[u8]) -> usize {
+    if needle.is_empty() {
+        return haystack.len() + 1;
+    }
+    let mut count = 0;
+    let mut position = 0;
+    while position + needle.len() <= haystack.len() {
+        if haystack[position..].starts_with(needle) {
+            count += 1;
+            position += needle.len();
+        } else {
+            position += 1;
+        }
+    }
+    count
+}
+
+/// Count possibly overlapping occurrences of a byte string in a slice of
+/// bytes, simple
+///
+/// An empty needle matches `haystack.len() + 1` times.
+///
+/// # Example
+///
+/// ```
+/// assert_eq!(bytecount::naive_count_substring_overlapping(b"aaaa", b"aa"), 3);
+/// ```
+pub fn naive_count_substring_overlapping(haystack: &[u8], needle: &[u8]) -> usize {
+    if needle.is_empty() {
+        return haystack.len() + 1;
+    }
+    haystack
+        .windows(needle.len())
+        .filter(|&window| window == needle)
+        .count()
+}
+
+/// Count bytes within an inclusive range in a slice of bytes, simple
+///
+/// # Example
+///
+/// ```
+/// let s = b"Call 555-0199 or 555-0123";
+/// assert_eq!(bytecount::naive_count_range(s, b'0'..=b'9'), 14);
+/// ```
+pub fn naive_count_range(haystack: &[u8], range: RangeInclusive<u8>) -> usize {
+    haystack.iter().filter(|byte| range.contains(byte)).count()
+}
+
+/// Count occurrences of every byte value in a slice of bytes, simple
+///
+/// # Example
+///
+/// ```
+/// let histogram = bytecount::naive_histogram(b"abracadabra");
+/// assert_eq!(histogram[b'a' as usize], 5);
+/// assert_eq!(histogram[b'r' as usize], 2);
+/// ```
+pub fn naive_histogram(haystack: &[u8]) -> [usize; 256] {
+    let mut histogram = [0; 256];
+    for &byte in haystack {
+        histogram[byte as usize] += 1;
+    }
+    histogram
+}
+
 /// Count the number of UTF-8 encoded Unicode codepoints in a slice of bytes, simple
 ///
 /// This function is safe to use on any byte array, valid UTF-8 or not,
@@ -45,3 +233,183 @@ pub fn naive_num_chars(utf8_chars: &[u8]) -> usize {
         .filter(|&&byte| (byte >> 6) != 0b10)
         .count()
 }
+
+/// Count the number of UTF-16 code units needed to encode the UTF-8
+/// encoded text in a slice of bytes, simple
+///
+/// # Example
+///
+/// ```
+/// let text = "I ❤ 🦀";
+/// assert_eq!(bytecount::naive_utf16_len(text.as_bytes()), 6);
+/// ```
+pub fn naive_utf16_len(utf8_chars: &[u8]) -> usize {
+    utf8_chars
+        .iter()
+        .map(|&byte| match byte {
+            0b1000_0000..=0b1011_1111 => 0,
+            0b1111_0000..=0b1111_1111 => 2,
+            _ => 1,
+        })
+        .sum()
+}
+
+/// Count the number of UTF-16 code units needed to encode the UTF-8
+/// encoded text in a slice of bytes, simple
+///
+/// # Example
+///
+/// ```
+/// let text = "Grüße 🦀";
+/// assert_eq!(bytecount::naive_utf16_len_from_utf8(text.as_bytes()), 8);
+/// ```
+pub fn naive_utf16_len_from_utf8(utf8_chars: &[u8]) -> usize {
+    naive_utf16_len(utf8_chars)
+}
+
+/// Count the number of bytes needed to encode UTF-16 encoded text as
+/// UTF-8, simple
+///
+/// # Example
+///
+/// ```
+/// let text: Vec<u16> = "Grüße 🦀".encode_utf16().collect();
+/// assert_eq!(bytecount::naive_utf8_len_from_utf16(&text), 12);
+/// ```
+pub fn naive_utf8_len_from_utf16(utf16: &[u16]) -> usize {
+    utf16
+        .iter()
+        .map(|&unit| match unit {
+            0..=0x7F => 1,
+            0x80..=0x7FF | 0xD800..=0xDFFF => 2,
+            _ => 3,
+        })
+        .sum()
+}
+
+/// Count the number of Unicode codepoints in a slice of UTF-16 code
+/// units, simple
+///
+/// # Example
+///
+/// ```
+/// let text: Vec<u16> = "I ❤ 🦀".encode_utf16().collect();
+/// assert_eq!(bytecount::naive_num_chars_utf16(&text), 5);
+/// ```
+pub fn naive_num_chars_utf16(utf16: &[u16]) -> usize {
+    utf16
+        .iter()
+        .filter(|&&unit| (unit & 0xFC00) != 0xDC00)
+        .count()
+}
+
+/// Count the number of bytes needed to encode Latin-1 (ISO 8859-1)
+/// encoded text as UTF-8, simple
+///
+/// # Example
+///
+/// ```
+/// assert_eq!(bytecount::naive_utf8_len_from_latin1(b"Gr\xfc\xdfe"), 7);
+/// ```
+pub fn naive_utf8_len_from_latin1(latin1: &[u8]) -> usize {
+    latin1.iter().map(|&byte| if byte < 0x80 { 1 } else { 2 }).sum()
+}
+
+/// Count the number of UTF-8 encoded Unicode codepoints in a slice of
+/// bytes, checking that it is valid UTF-8, simple
+///
+/// # Example
+///
+/// ```
+/// assert_eq!(bytecount::naive_try_num_chars("メカジキ".as_bytes()), Ok(4));
+/// assert!(bytecount::naive_try_num_chars(b"\xff").is_err());
+/// ```
+pub fn naive_try_num_chars(utf8_chars: &[u8]) -> Result<usize, Utf8Error> {
+    str::from_utf8(utf8_chars)
+        .map(|text| text.chars().count())
+        .map_err(|error| Utf8Error::new(error.valid_up_to(), error.error_len()))
+}
+
+/// Count the number of Unicode codepoints that lossy decoding of a slice
+/// of bytes produces, simple
+///
+/// # Example
+///
+/// ```
+/// assert_eq!(bytecount::naive_num_chars_lossy(b"Gr\xfc\xdfe"), 5);
+/// ```
+pub fn naive_num_chars_lossy(utf8_chars: &[u8]) -> usize {
+    let mut chars = 0;
+    let mut rest = utf8_chars;
+    loop {
+        match str::from_utf8(rest) {
+            Ok(text) => return chars + text.chars().count(),
+            Err(error) => {
+                chars += naive_num_chars(&rest[..error.valid_up_to()]) + 1;
+                match error.error_len() {
+                    Some(len) => rest = &rest[error.valid_up_to() + len..],
+                    None => return chars,
+                }
+            }
+        }
+    }
+}
+
+/// Count the UTF-8 encoded Unicode codepoints in a slice of bytes by
+/// their encoded length, simple
+///
+/// # Example
+///
+/// ```
+/// let swordfish = "Swordfish: メカジキ";
+/// let histogram = bytecount::naive_utf8_length_histogram(swordfish.as_bytes());
+/// assert_eq!(histogram, [11, 0, 4, 0, 8]);
+/// ```
+pub fn naive_utf8_length_histogram(utf8_chars: &[u8]) -> [usize; 5] {
+    let mut histogram = [0; 5];
+    for &byte in utf8_chars {
+        match byte {
+            0x00..=0x7F => histogram[0] += 1,
+            0xC0..=0xDF => histogram[1] += 1,
+            0xE0..=0xEF => histogram[2] += 1,
+            0xF0..=0xF7 => histogram[3] += 1,
+            _ => histogram[4] += 1,
+        }
+    }
+    histogram
+}
+
+/// Find the byte offset of the `n`th UTF-8 encoded Unicode codepoint in a
+/// slice of bytes, simple
+///
+/// # Example
+///
+/// ```
+/// let swordfish = "メカジキ";
+/// assert_eq!(bytecount::naive_char_to_byte_offset(swordfish.as_bytes(), 2), Some(6));
+/// ```
+pub fn naive_char_to_byte_offset(utf8_chars: &[u8], n: usize) -> Option<usize> {
+    utf8_chars
+        .iter()
+        .enumerate()
+        .filter(|&(_, &byte)| (byte >> 6) != 0b10)
+        .map(|(i, _)| i)
+        .chain(Some(utf8_chars.len()))
+        .nth(n)
+}
+
+/// Find the index of the UTF-8 encoded Unicode codepoint starting at a
+/// byte offset in a slice of bytes, simple
+///
+/// # Example
+///
+/// ```
+/// let swordfish = "メカジキ";
+/// assert_eq!(bytecount::naive_byte_to_char_offset(swordfish.as_bytes(), 6), Some(2));
+/// ```
+pub fn naive_byte_to_char_offset(utf8_chars: &[u8], offset: usize) -> Option<usize> {
+    match utf8_chars.get(offset) {
+        Some(&byte) if (byte >> 6) == 0b10 => None,
+        _ => utf8_chars.get(..offset).map(naive_num_chars),
+    }
+}
@@ -0,0 +1,153 @@
+use std::thread;
+
+use crate::{char_to_byte_offset, count, nth, num_chars};
+
+/// The least number of bytes worth handing to a thread of its own; below
+/// that, spawning costs more than the thread saves
+const MIN_BYTES_PER_THREAD: usize = 1 << 20;
+
+/// Pieces start at multiples of this many bytes from the start of the
+/// slice, so no two threads read the same page
+const PIECE_ALIGN: usize = 4096;
+
+/// Split `haystack` into at most `threads` pieces, run `kernel` on each on
+/// its own scoped thread and sum the results
+fn par_sum<F: Fn(&[u8]) -> usize + Sync>(haystack: &[u8], threads: usize, kernel: F) -> usize {
+    let threads = threads.min(haystack.len() / MIN_BYTES_PER_THREAD);
+    if threads <= 1 {
+        return kernel(haystack);
+    }
+    let piece = (haystack.len() + threads - 1) / threads;
+    let piece = (piece + PIECE_ALIGN - 1) / PIECE_ALIGN * PIECE_ALIGN;
+    let (first, rest) = haystack.split_at(piece);
+    let kernel = &kernel;
+    thread::scope(|scope| {
+        // A piece whose thread cannot be spawned is counted here instead
+        let handles: Vec<_> = rest
+            .chunks(piece)
+            .map(|chunk| {
+                thread::Builder::new()
+                    .spawn_scoped(scope, move || kernel(chunk))
+                    .map_err(|_| chunk)
+            })
+            .collect();
+        let first = kernel(first);
+        handles
+            .into_iter()
+            .map(|handle| match handle {
+                Ok(handle) => handle.join().unwrap(),
+                Err(chunk) => kernel(chunk),
+            })
+            .fold(first, |total, count| total + count)
+    })
+}
+
+/// Count occurrences of a byte in a slice of bytes on up to `threads`
+/// threads, fast
+///
+/// Each thread counts a contiguous piece of at least 1 MiB with
+/// [`count`](fn.count.html), so smaller slices are counted on the current
+/// thread alone. This pays off for slices far larger than the CPU caches,
+/// where a single thread cannot use all of the memory bandwidth. Pieces
+/// whose thread cannot be spawned are counted on the current thread.
+///
+/// # Example
+///
+/// ```
+/// let haystack = vec![b'x'; 10_000_000];
+/// assert_eq!(bytecount::par_count(&haystack, b'x', 4), 10_000_000);
+/// ```
+pub fn par_count(haystack: &[u8], needle: u8, threads: usize) -> usize {
+    par_sum(haystack, threads, |piece| count(piece, needle))
+}
+
+/// Count the number of UTF-8 encoded Unicode codepoints in a slice of bytes
+/// on up to `threads` threads, fast
+///
+/// This splits the work like [`par_count`](fn.par_count.html), counting
+/// each piece with [`num_chars`](fn.num_chars.html). Pieces may start in
+/// the middle of a codepoint, which does not change the total.
+///
+/// # Example
+///
+/// ```
+/// let text = "Grüße, Jürgen ❤".repeat(1_000_000);
+/// assert_eq!(bytecount::par_num_chars(text.as_bytes(), 4), 15_000_000);
+/// ```
+pub fn par_num_chars(utf8_chars: &[u8], threads: usize) -> usize {
+    par_sum(utf8_chars, threads, num_chars)
+}
+
+/// Cut `haystack` into at most `n` pieces, moving each evenly spaced cut
+/// point forward to where `snap` says the next piece may start
+fn split_snapped<F: Fn(&[u8], usize) -> usize>(haystack: &[u8], n: usize, snap: F) -> Vec<&[u8]> {
+    let len = haystack.len();
+    // No piece is empty, so there are never more pieces than bytes
+    let n = n.max(1).min(len.max(1));
+    let mut pieces = Vec::with_capacity(n);
+    let mut start = 0;
+    for i in 1..n {
+        let ideal = (len as u128 * i as u128 / n as u128) as usize;
+        if ideal <= start {
+            continue;
+        }
+        let cut = snap(haystack, ideal);
+        if cut >= len {
+            break;
+        }
+        pieces.push(&haystack[start..cut]);
+        start = cut;
+    }
+    if start < len {
+        pieces.push(&haystack[start..]);
+    }
+    pieces
+}
+
+/// Split a slice of bytes into at most `n` pieces of roughly equal size,
+/// each ending with a newline except for the last
+///
+/// Every cut is moved forward to just after the next `b'\n'`, which is
+/// found with [`nth`](fn.nth.html), so no line is split between pieces.
+/// There are fewer than `n` pieces if the lines are too long to cut
+/// elsewhere, and none are empty, so an empty slice has no pieces at all.
+/// An `n` of zero is treated like one.
+///
+/// # Example
+///
+/// ```
+/// let text = b"one\ntwo\nthree\nfour\nfive\nsix\n";
+/// let pieces = bytecount::split_at_lines(text, 3);
+/// assert_eq!(pieces, [&b"one\ntwo\nthree\n"[..], b"four\n", b"five\nsix\n"]);
+/// ```
+pub fn split_at_lines(haystack: &[u8], n: usize) -> Vec<&[u8]> {
+    split_snapped(haystack, n, |haystack, ideal| {
+        nth(&haystack[ideal - 1..], b'\n', 0).map_or(haystack.len(), |i| ideal + i)
+    })
+}
+
+/// Split UTF-8 encoded text into at most `n` pieces of roughly equal size,
+/// without splitting any codepoint between pieces
+///
+/// Every cut is moved forward past any continuation bytes, using
+/// [`char_to_byte_offset`](fn.char_to_byte_offset.html), so each piece
+/// can be passed to [`num_chars`](fn.num_chars.html) or `str::from_utf8`
+/// on its own. As with [`split_at_lines`](fn.split_at_lines.html), no
+/// piece is empty and an `n` of zero is treated like one.
+///
+/// # Example
+///
+/// ```
+/// let text = "Grüße, Jürgen ❤";
+/// let pieces = bytecount::split_at_char_boundaries(text.as_bytes(), 4);
+/// assert_eq!(pieces.len(), 4);
+/// for piece in pieces {
+///     assert!(std::str::from_utf8(piece).is_ok());
+/// }
+/// ```
+pub fn split_at_char_boundaries(utf8_chars: &[u8], n: usize) -> Vec<&[u8]> {
+    split_snapped(utf8_chars, n, |utf8_chars, ideal| {
+        // The first codepoint of the rest always exists, if only as the end
+        ideal + char_to_byte_offset(&utf8_chars[ideal..], 0).unwrap_or(0)
+    })
+}
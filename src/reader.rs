@@ -0,0 +1,119 @@
+use std::io::{self, ErrorKind, Read};
+
+use crate::{count, num_chars};
+
+/// The buffer size used by [`count_reader`](fn.count_reader.html) and
+/// [`num_chars_reader`](fn.num_chars_reader.html)
+pub const DEFAULT_BUFFER_SIZE: usize = 64 * 1024;
+
+/// The alignment of the read buffer, which is also the widest load of any
+/// backend
+const ALIGN: usize = 64;
+
+/// The largest read buffer, beyond which bigger reads gain nothing
+const MAX_BUFFER_SIZE: usize = 16 * 1024 * 1024;
+
+/// Read `reader` to the end into an aligned buffer of at least
+/// `buffer_size` bytes, capped at `MAX_BUFFER_SIZE`, handing each read to `f`
+fn for_each_read<R: Read, F: FnMut(&[u8])>(
+    mut reader: R,
+    buffer_size: usize,
+    mut f: F,
+) -> io::Result<()> {
+    let len = (buffer_size.clamp(1, MAX_BUFFER_SIZE) + ALIGN - 1) / ALIGN * ALIGN;
+    let mut storage = vec![0; len + ALIGN];
+    let start = storage.as_ptr().align_offset(ALIGN).min(ALIGN);
+    let buffer = &mut storage[start..start + len];
+    loop {
+        match reader.read(buffer) {
+            Ok(0) => return Ok(()),
+            Ok(len) => f(&buffer[..len]),
+            Err(ref error) if error.kind() == ErrorKind::Interrupted => {}
+            Err(error) => return Err(error),
+        }
+    }
+}
+
+/// Count occurrences of a byte in everything a reader yields, fast
+///
+/// The reader is read to the end in chunks of
+/// [`DEFAULT_BUFFER_SIZE`](constant.DEFAULT_BUFFER_SIZE.html) bytes, which
+/// are counted with [`count`](fn.count.html). Reads that fail with
+/// `ErrorKind::Interrupted` are retried; any other error is returned.
+///
+/// # Example
+///
+/// ```
+/// let text: &[u8] = b"first\nsecond\nthird\n";
+/// assert_eq!(bytecount::count_reader(text, b'\n').unwrap(), 3);
+/// ```
+pub fn count_reader<R: Read>(reader: R, needle: u8) -> io::Result<u64> {
+    count_reader_with_buffer_size(reader, needle, DEFAULT_BUFFER_SIZE)
+}
+
+/// Count occurrences of a byte in everything a reader yields, reading
+/// chunks of at least `buffer_size` bytes, fast
+///
+/// The buffer size is capped at 16 MiB and rounded up to a multiple of 64
+/// bytes. Otherwise this works like [`count_reader`](fn.count_reader.html).
+///
+/// # Example
+///
+/// ```
+/// let text: &[u8] = b"first\nsecond\nthird\n";
+/// let newlines = bytecount::count_reader_with_buffer_size(text, b'\n', 4096).unwrap();
+/// assert_eq!(newlines, 3);
+/// ```
+pub fn count_reader_with_buffer_size<R: Read>(
+    reader: R,
+    needle: u8,
+    buffer_size: usize,
+) -> io::Result<u64> {
+    let mut total = 0;
+    for_each_read(reader, buffer_size, |chunk| {
+        total += count(chunk, needle) as u64
+    })?;
+    Ok(total)
+}
+
+/// Count the number of UTF-8 encoded Unicode codepoints in everything a
+/// reader yields, fast
+///
+/// Like [`num_chars`](fn.num_chars.html), this does not validate the input,
+/// so sequences split between two reads are still counted once. Reads that
+/// fail with `ErrorKind::Interrupted` are retried; any other error is
+/// returned.
+///
+/// # Example
+///
+/// ```
+/// let text = "Hello, world!\nGrüße\n";
+/// assert_eq!(bytecount::num_chars_reader(text.as_bytes()).unwrap(), 20);
+/// ```
+pub fn num_chars_reader<R: Read>(reader: R) -> io::Result<u64> {
+    num_chars_reader_with_buffer_size(reader, DEFAULT_BUFFER_SIZE)
+}
+
+/// Count the number of UTF-8 encoded Unicode codepoints in everything a
+/// reader yields, reading chunks of at least `buffer_size` bytes, fast
+///
+/// The buffer size is capped at 16 MiB and rounded up to a multiple of 64
+/// bytes. Otherwise this works like [`num_chars_reader`](fn.num_chars_reader.html).
+///
+/// # Example
+///
+/// ```
+/// let text = "Hello, world!\nGrüße\n";
+/// let chars = bytecount::num_chars_reader_with_buffer_size(text.as_bytes(), 4096).unwrap();
+/// assert_eq!(chars, 20);
+/// ```
+pub fn num_chars_reader_with_buffer_size<R: Read>(
+    reader: R,
+    buffer_size: usize,
+) -> io::Result<u64> {
+    let mut total = 0;
+    for_each_read(reader, buffer_size, |chunk| {
+        total += num_chars(chunk) as u64
+    })?;
+    Ok(total)
+}
@@ -1,8 +1,15 @@
 use core::arch::aarch64::{
-    uint8x16_t, uint8x16x4_t, vaddlvq_u8, vandq_u8, vceqq_u8, vdupq_n_u8, vld1q_u8, vld1q_u8_x4,
-    vsubq_u8,
+    uint16x8_t, uint8x16_t, uint8x16x4_t, vaddlvq_u16, vaddlvq_u8, vaddq_u16, vandq_u16, vandq_u8,
+    vceqq_u16, vceqq_u8, vcgeq_u8, vcltq_u16, vcltq_u8, vdupq_n_u16, vdupq_n_u8, veorq_u8,
+    vextq_u8, vget_lane_u64, vld1q_u16, vld1q_u8, vld1q_u8_x4, vmaxvq_u8, vorrq_u8, vqsubq_u8,
+    vqtbl1q_u8, vreinterpret_u64_u8, vreinterpretq_u16_u8, vshrn_n_u16, vshrq_n_u8, vsubq_u16,
+    vsubq_u8, vtstq_u8,
 };
 
+use crate::bits::nth_set_bit;
+use crate::substring::Matches;
+use crate::utf8::{UTF8_HIGH, UTF8_PREV_HIGH, UTF8_PREV_LOW};
+
 const MASK: [u8; 32] = [
     0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 255, 255, 255, 255, 255, 255, 255, 255, 255,
     255, 255, 255, 255, 255, 255, 255,
@@ -160,3 +167,864 @@ pub unsafe fn chunk_num_chars(utf8_chars: &[u8]) -> usize {
 
     utf8_chars.len() - count
 }
+
+// The error bits of each byte of `u8s` given the bytes before it, as laid
+// out in `crate::utf8`; any bit set means the input is not valid UTF-8.
+#[target_feature(enable = "neon")]
+unsafe fn utf8_errors(u8s: uint8x16_t, previous: uint8x16_t) -> uint8x16_t {
+    let prev1 = vextq_u8(previous, u8s, 15);
+    let prev2 = vextq_u8(previous, u8s, 14);
+    let prev3 = vextq_u8(previous, u8s, 13);
+    let errors = vandq_u8(
+        vandq_u8(
+            vqtbl1q_u8(u8x16_from_offset(&UTF8_PREV_HIGH, 0), vshrq_n_u8(prev1, 4)),
+            vqtbl1q_u8(u8x16_from_offset(&UTF8_PREV_LOW, 0), vandq_u8(prev1, vdupq_n_u8(0x0F))),
+        ),
+        vqtbl1q_u8(u8x16_from_offset(&UTF8_HIGH, 0), vshrq_n_u8(u8s, 4)),
+    );
+    // The third and fourth bytes of a sequence must be continuation bytes
+    let must_continue = vandq_u8(
+        vorrq_u8(
+            vqsubq_u8(prev2, vdupq_n_u8(0xE0 - 0x80)),
+            vqsubq_u8(prev3, vdupq_n_u8(0xF0 - 0x80)),
+        ),
+        vdupq_n_u8(0x80),
+    );
+    veorq_u8(errors, must_continue)
+}
+
+#[target_feature(enable = "neon")]
+pub unsafe fn chunk_try_num_chars(utf8_chars: &[u8]) -> Option<usize> {
+    assert!(utf8_chars.len() >= 16);
+
+    let mut offset = 0;
+    let mut count = 0;
+
+    let mut previous = vdupq_n_u8(0);
+    let mut errors = vdupq_n_u8(0);
+
+    // 4080
+    while utf8_chars.len() >= offset + 16 * 255 {
+        let mut counts = vdupq_n_u8(0);
+        for _ in 0..255 {
+            let u8s = u8x16_from_offset(utf8_chars, offset);
+            errors = vorrq_u8(errors, utf8_errors(u8s, previous));
+            counts = vsubq_u8(counts, is_following_utf8_byte(u8s));
+            previous = u8s;
+            offset += 16;
+        }
+        if vmaxvq_u8(errors) != 0 {
+            return None;
+        }
+        count += sum(counts);
+    }
+
+    // 16
+    let mut counts = vdupq_n_u8(0);
+    while utf8_chars.len() >= offset + 16 {
+        let u8s = u8x16_from_offset(utf8_chars, offset);
+        errors = vorrq_u8(errors, utf8_errors(u8s, previous));
+        counts = vsubq_u8(counts, is_following_utf8_byte(u8s));
+        previous = u8s;
+        offset += 16;
+    }
+    // The rest is padded with zeros, which also catches a sequence that the
+    // end of the input cuts short
+    let mut rest = [0; 16];
+    rest[..utf8_chars.len() - offset].copy_from_slice(&utf8_chars[offset..]);
+    let u8s = u8x16_from_offset(&rest, 0);
+    errors = vorrq_u8(errors, utf8_errors(u8s, previous));
+    counts = vsubq_u8(counts, is_following_utf8_byte(u8s));
+    if vmaxvq_u8(errors) != 0 {
+        return None;
+    }
+    count += sum(counts);
+
+    Some(utf8_chars.len() - count)
+}
+
+#[target_feature(enable = "neon")]
+unsafe fn vceqq_any_u8(u8s: uint8x16_t, needles: &[uint8x16_t]) -> uint8x16_t {
+    let mut matches = vdupq_n_u8(0);
+    for &needle in needles {
+        matches = vorrq_u8(matches, vceqq_u8(u8s, needle));
+    }
+    matches
+}
+
+#[target_feature(enable = "neon")]
+pub unsafe fn chunk_count_any(haystack: &[u8], needles: &[u8]) -> usize {
+    assert!(haystack.len() >= 16);
+    assert!(needles.len() <= 8);
+
+    let mut offset = 0;
+    let mut count = 0;
+
+    let mut splats = [vdupq_n_u8(0); 8];
+    for (splat, &needle) in splats.iter_mut().zip(needles) {
+        *splat = vdupq_n_u8(needle);
+    }
+    let needles = &splats[..needles.len()];
+
+    // 16320
+    while haystack.len() >= offset + 64 * 255 {
+        let (mut count1, mut count2, mut count3, mut count4) =
+            (vdupq_n_u8(0), vdupq_n_u8(0), vdupq_n_u8(0), vdupq_n_u8(0));
+        for _ in 0..255 {
+            let uint8x16x4_t(h1, h2, h3, h4) = u8x16_x4_from_offset(haystack, offset);
+            count1 = vsubq_u8(count1, vceqq_any_u8(h1, needles));
+            count2 = vsubq_u8(count2, vceqq_any_u8(h2, needles));
+            count3 = vsubq_u8(count3, vceqq_any_u8(h3, needles));
+            count4 = vsubq_u8(count4, vceqq_any_u8(h4, needles));
+            offset += 64;
+        }
+        count += sum4(count1, count2, count3, count4);
+    }
+
+    // 64
+    let (mut count1, mut count2, mut count3, mut count4) =
+        (vdupq_n_u8(0), vdupq_n_u8(0), vdupq_n_u8(0), vdupq_n_u8(0));
+    for _ in 0..(haystack.len() - offset) / 64 {
+        let uint8x16x4_t(h1, h2, h3, h4) = u8x16_x4_from_offset(haystack, offset);
+        count1 = vsubq_u8(count1, vceqq_any_u8(h1, needles));
+        count2 = vsubq_u8(count2, vceqq_any_u8(h2, needles));
+        count3 = vsubq_u8(count3, vceqq_any_u8(h3, needles));
+        count4 = vsubq_u8(count4, vceqq_any_u8(h4, needles));
+        offset += 64;
+    }
+    count += sum4(count1, count2, count3, count4);
+
+    let mut counts = vdupq_n_u8(0);
+    // 16
+    for i in 0..(haystack.len() - offset) / 16 {
+        counts = vsubq_u8(
+            counts,
+            vceqq_any_u8(u8x16_from_offset(haystack, offset + i * 16), needles),
+        );
+    }
+    if haystack.len() % 16 != 0 {
+        counts = vsubq_u8(
+            counts,
+            vandq_u8(
+                vceqq_any_u8(u8x16_from_offset(haystack, haystack.len() - 16), needles),
+                u8x16_from_offset(&MASK, haystack.len() % 16),
+            ),
+        );
+    }
+    count + sum(counts)
+}
+
+const BITS: [u8; 16] = [1, 2, 4, 8, 16, 32, 64, 128, 1, 2, 4, 8, 16, 32, 64, 128];
+
+#[target_feature(enable = "neon")]
+unsafe fn is_in_set(u8s: uint8x16_t, lower: uint8x16_t, upper: uint8x16_t) -> uint8x16_t {
+    // Out-of-range indices look up zero, so each table only yields a row
+    // for its half of the byte range.
+    let index = vandq_u8(u8s, vdupq_n_u8(0x8F));
+    let rows = vorrq_u8(
+        vqtbl1q_u8(lower, index),
+        vqtbl1q_u8(upper, veorq_u8(index, vdupq_n_u8(0x80))),
+    );
+    let bits = vqtbl1q_u8(u8x16_from_offset(&BITS, 0), vshrq_n_u8(u8s, 4));
+    vtstq_u8(rows, bits)
+}
+
+#[target_feature(enable = "neon")]
+pub unsafe fn chunk_count_set(haystack: &[u8], table: &[u8; 32]) -> usize {
+    assert!(haystack.len() >= 16);
+
+    let mut offset = 0;
+    let mut count = 0;
+
+    let lower = u8x16_from_offset(table, 0);
+    let upper = u8x16_from_offset(table, 16);
+
+    // 16320
+    while haystack.len() >= offset + 64 * 255 {
+        let (mut count1, mut count2, mut count3, mut count4) =
+            (vdupq_n_u8(0), vdupq_n_u8(0), vdupq_n_u8(0), vdupq_n_u8(0));
+        for _ in 0..255 {
+            let uint8x16x4_t(h1, h2, h3, h4) = u8x16_x4_from_offset(haystack, offset);
+            count1 = vsubq_u8(count1, is_in_set(h1, lower, upper));
+            count2 = vsubq_u8(count2, is_in_set(h2, lower, upper));
+            count3 = vsubq_u8(count3, is_in_set(h3, lower, upper));
+            count4 = vsubq_u8(count4, is_in_set(h4, lower, upper));
+            offset += 64;
+        }
+        count += sum4(count1, count2, count3, count4);
+    }
+
+    // 64
+    let (mut count1, mut count2, mut count3, mut count4) =
+        (vdupq_n_u8(0), vdupq_n_u8(0), vdupq_n_u8(0), vdupq_n_u8(0));
+    for _ in 0..(haystack.len() - offset) / 64 {
+        let uint8x16x4_t(h1, h2, h3, h4) = u8x16_x4_from_offset(haystack, offset);
+        count1 = vsubq_u8(count1, is_in_set(h1, lower, upper));
+        count2 = vsubq_u8(count2, is_in_set(h2, lower, upper));
+        count3 = vsubq_u8(count3, is_in_set(h3, lower, upper));
+        count4 = vsubq_u8(count4, is_in_set(h4, lower, upper));
+        offset += 64;
+    }
+    count += sum4(count1, count2, count3, count4);
+
+    let mut counts = vdupq_n_u8(0);
+    // 16
+    for i in 0..(haystack.len() - offset) / 16 {
+        counts = vsubq_u8(
+            counts,
+            is_in_set(u8x16_from_offset(haystack, offset + i * 16), lower, upper),
+        );
+    }
+    if haystack.len() % 16 != 0 {
+        counts = vsubq_u8(
+            counts,
+            vandq_u8(
+                is_in_set(u8x16_from_offset(haystack, haystack.len() - 16), lower, upper),
+                u8x16_from_offset(&MASK, haystack.len() % 16),
+            ),
+        );
+    }
+    count + sum(counts)
+}
+
+#[target_feature(enable = "neon")]
+unsafe fn is_in_range(u8s: uint8x16_t, lows: uint8x16_t, spans: uint8x16_t) -> uint8x16_t {
+    // `lo <= x <= hi` iff `x - lo <= hi - lo`, i.e. iff the saturating
+    // difference to the span is zero.
+    vceqq_u8(vqsubq_u8(vsubq_u8(u8s, lows), spans), vdupq_n_u8(0))
+}
+
+#[target_feature(enable = "neon")]
+pub unsafe fn chunk_count_range(haystack: &[u8], lo: u8, hi: u8) -> usize {
+    assert!(haystack.len() >= 16);
+    assert!(lo <= hi);
+
+    let mut offset = 0;
+    let mut count = 0;
+
+    let lows = vdupq_n_u8(lo);
+    let spans = vdupq_n_u8(hi - lo);
+
+    // 16320
+    while haystack.len() >= offset + 64 * 255 {
+        let (mut count1, mut count2, mut count3, mut count4) =
+            (vdupq_n_u8(0), vdupq_n_u8(0), vdupq_n_u8(0), vdupq_n_u8(0));
+        for _ in 0..255 {
+            let uint8x16x4_t(h1, h2, h3, h4) = u8x16_x4_from_offset(haystack, offset);
+            count1 = vsubq_u8(count1, is_in_range(h1, lows, spans));
+            count2 = vsubq_u8(count2, is_in_range(h2, lows, spans));
+            count3 = vsubq_u8(count3, is_in_range(h3, lows, spans));
+            count4 = vsubq_u8(count4, is_in_range(h4, lows, spans));
+            offset += 64;
+        }
+        count += sum4(count1, count2, count3, count4);
+    }
+
+    // 64
+    let (mut count1, mut count2, mut count3, mut count4) =
+        (vdupq_n_u8(0), vdupq_n_u8(0), vdupq_n_u8(0), vdupq_n_u8(0));
+    for _ in 0..(haystack.len() - offset) / 64 {
+        let uint8x16x4_t(h1, h2, h3, h4) = u8x16_x4_from_offset(haystack, offset);
+        count1 = vsubq_u8(count1, is_in_range(h1, lows, spans));
+        count2 = vsubq_u8(count2, is_in_range(h2, lows, spans));
+        count3 = vsubq_u8(count3, is_in_range(h3, lows, spans));
+        count4 = vsubq_u8(count4, is_in_range(h4, lows, spans));
+        offset += 64;
+    }
+    count += sum4(count1, count2, count3, count4);
+
+    let mut counts = vdupq_n_u8(0);
+    // 16
+    for i in 0..(haystack.len() - offset) / 16 {
+        counts = vsubq_u8(
+            counts,
+            is_in_range(u8x16_from_offset(haystack, offset + i * 16), lows, spans),
+        );
+    }
+    if haystack.len() % 16 != 0 {
+        counts = vsubq_u8(
+            counts,
+            vandq_u8(
+                is_in_range(u8x16_from_offset(haystack, haystack.len() - 16), lows, spans),
+                u8x16_from_offset(&MASK, haystack.len() % 16),
+            ),
+        );
+    }
+    count + sum(counts)
+}
+
+#[target_feature(enable = "neon")]
+unsafe fn count_each<const N: usize>(
+    counts: &mut [uint8x16_t; N],
+    u8s: uint8x16_t,
+    needles: &[uint8x16_t; N],
+) {
+    for (counts, &needles) in counts.iter_mut().zip(needles.iter()) {
+        *counts = vsubq_u8(*counts, vceqq_u8(u8s, needles));
+    }
+}
+
+#[target_feature(enable = "neon")]
+pub unsafe fn chunk_count_many<const N: usize>(haystack: &[u8], needles: [u8; N]) -> [usize; N] {
+    assert!(haystack.len() >= 16);
+
+    let mut offset = 0;
+    let mut count = [0; N];
+
+    let needles = needles.map(|needle| vdupq_n_u8(needle));
+
+    // 16320
+    while haystack.len() >= offset + 64 * 255 {
+        let (mut count1, mut count2, mut count3, mut count4) = (
+            [vdupq_n_u8(0); N],
+            [vdupq_n_u8(0); N],
+            [vdupq_n_u8(0); N],
+            [vdupq_n_u8(0); N],
+        );
+        for _ in 0..255 {
+            let uint8x16x4_t(h1, h2, h3, h4) = u8x16_x4_from_offset(haystack, offset);
+            count_each(&mut count1, h1, &needles);
+            count_each(&mut count2, h2, &needles);
+            count_each(&mut count3, h3, &needles);
+            count_each(&mut count4, h4, &needles);
+            offset += 64;
+        }
+        for i in 0..N {
+            count[i] += sum4(count1[i], count2[i], count3[i], count4[i]);
+        }
+    }
+
+    // 64
+    let (mut count1, mut count2, mut count3, mut count4) = (
+        [vdupq_n_u8(0); N],
+        [vdupq_n_u8(0); N],
+        [vdupq_n_u8(0); N],
+        [vdupq_n_u8(0); N],
+    );
+    for _ in 0..(haystack.len() - offset) / 64 {
+        let uint8x16x4_t(h1, h2, h3, h4) = u8x16_x4_from_offset(haystack, offset);
+        count_each(&mut count1, h1, &needles);
+        count_each(&mut count2, h2, &needles);
+        count_each(&mut count3, h3, &needles);
+        count_each(&mut count4, h4, &needles);
+        offset += 64;
+    }
+    for i in 0..N {
+        count[i] += sum4(count1[i], count2[i], count3[i], count4[i]);
+    }
+
+    let mut counts = [vdupq_n_u8(0); N];
+    // 16
+    for i in 0..(haystack.len() - offset) / 16 {
+        count_each(&mut counts, u8x16_from_offset(haystack, offset + i * 16), &needles);
+    }
+    if haystack.len() % 16 != 0 {
+        let mask = u8x16_from_offset(&MASK, haystack.len() % 16);
+        let u8s = u8x16_from_offset(haystack, haystack.len() - 16);
+        for (counts, &needles) in counts.iter_mut().zip(needles.iter()) {
+            *counts = vsubq_u8(*counts, vandq_u8(vceqq_u8(u8s, needles), mask));
+        }
+    }
+    for (count, &counts) in count.iter_mut().zip(counts.iter()) {
+        *count += sum(counts);
+    }
+
+    count
+}
+
+#[target_feature(enable = "neon")]
+pub unsafe fn chunk_text_stats(utf8_chars: &[u8]) -> (usize, usize) {
+    assert!(utf8_chars.len() >= 16);
+
+    let mut offset = 0;
+    let mut newlines = 0;
+    let mut following = 0;
+
+    let needles = vdupq_n_u8(b'\n');
+
+    // 16320
+    while utf8_chars.len() >= offset + 64 * 255 {
+        let (mut newline1, mut newline2, mut newline3, mut newline4) =
+            (vdupq_n_u8(0), vdupq_n_u8(0), vdupq_n_u8(0), vdupq_n_u8(0));
+        let (mut following1, mut following2, mut following3, mut following4) =
+            (vdupq_n_u8(0), vdupq_n_u8(0), vdupq_n_u8(0), vdupq_n_u8(0));
+        for _ in 0..255 {
+            let uint8x16x4_t(h1, h2, h3, h4) = u8x16_x4_from_offset(utf8_chars, offset);
+            newline1 = vsubq_u8(newline1, vceqq_u8(h1, needles));
+            newline2 = vsubq_u8(newline2, vceqq_u8(h2, needles));
+            newline3 = vsubq_u8(newline3, vceqq_u8(h3, needles));
+            newline4 = vsubq_u8(newline4, vceqq_u8(h4, needles));
+            following1 = vsubq_u8(following1, is_following_utf8_byte(h1));
+            following2 = vsubq_u8(following2, is_following_utf8_byte(h2));
+            following3 = vsubq_u8(following3, is_following_utf8_byte(h3));
+            following4 = vsubq_u8(following4, is_following_utf8_byte(h4));
+            offset += 64;
+        }
+        newlines += sum4(newline1, newline2, newline3, newline4);
+        following += sum4(following1, following2, following3, following4);
+    }
+
+    // 64
+    let (mut newline1, mut newline2, mut newline3, mut newline4) =
+        (vdupq_n_u8(0), vdupq_n_u8(0), vdupq_n_u8(0), vdupq_n_u8(0));
+    let (mut following1, mut following2, mut following3, mut following4) =
+        (vdupq_n_u8(0), vdupq_n_u8(0), vdupq_n_u8(0), vdupq_n_u8(0));
+    for _ in 0..(utf8_chars.len() - offset) / 64 {
+        let uint8x16x4_t(h1, h2, h3, h4) = u8x16_x4_from_offset(utf8_chars, offset);
+        newline1 = vsubq_u8(newline1, vceqq_u8(h1, needles));
+        newline2 = vsubq_u8(newline2, vceqq_u8(h2, needles));
+        newline3 = vsubq_u8(newline3, vceqq_u8(h3, needles));
+        newline4 = vsubq_u8(newline4, vceqq_u8(h4, needles));
+        following1 = vsubq_u8(following1, is_following_utf8_byte(h1));
+        following2 = vsubq_u8(following2, is_following_utf8_byte(h2));
+        following3 = vsubq_u8(following3, is_following_utf8_byte(h3));
+        following4 = vsubq_u8(following4, is_following_utf8_byte(h4));
+        offset += 64;
+    }
+    newlines += sum4(newline1, newline2, newline3, newline4);
+    following += sum4(following1, following2, following3, following4);
+
+    // 16
+    let mut newline_counts = vdupq_n_u8(0);
+    let mut following_counts = vdupq_n_u8(0);
+    for i in 0..(utf8_chars.len() - offset) / 16 {
+        let u8s = u8x16_from_offset(utf8_chars, offset + i * 16);
+        newline_counts = vsubq_u8(newline_counts, vceqq_u8(u8s, needles));
+        following_counts = vsubq_u8(following_counts, is_following_utf8_byte(u8s));
+    }
+    if utf8_chars.len() % 16 != 0 {
+        let u8s = u8x16_from_offset(utf8_chars, utf8_chars.len() - 16);
+        let mask = u8x16_from_offset(&MASK, utf8_chars.len() % 16);
+        newline_counts = vsubq_u8(newline_counts, vandq_u8(vceqq_u8(u8s, needles), mask));
+        following_counts = vsubq_u8(following_counts, vandq_u8(is_following_utf8_byte(u8s), mask));
+    }
+    newlines += sum(newline_counts);
+    following += sum(following_counts);
+
+    (newlines, utf8_chars.len() - following)
+}
+
+// NEON has no movemask; narrowing shifts instead pack each byte of the
+// comparison result into four bits of a u64.
+#[target_feature(enable = "neon")]
+unsafe fn nibble_mask(u8s: uint8x16_t) -> u64 {
+    vget_lane_u64::<0>(vreinterpret_u64_u8(vshrn_n_u16::<4>(vreinterpretq_u16_u8(u8s))))
+}
+
+#[target_feature(enable = "neon")]
+pub unsafe fn chunk_count_substring(haystack: &[u8], needle: &[u8], overlapping: bool) -> usize {
+    assert!(needle.len() >= 2);
+    assert!(haystack.len() >= needle.len() - 1 + 16);
+
+    let last = needle.len() - 1;
+    let mut offset = 0;
+    let mut matches = Matches::new(haystack, needle, overlapping);
+
+    let firsts = vdupq_n_u8(needle[0]);
+    let lasts = vdupq_n_u8(needle[last]);
+
+    // 16
+    while haystack.len() >= offset + last + 16 {
+        let candidates = vandq_u8(
+            vceqq_u8(u8x16_from_offset(haystack, offset), firsts),
+            vceqq_u8(u8x16_from_offset(haystack, offset + last), lasts),
+        );
+        let mut bits = nibble_mask(candidates) & 0x8888_8888_8888_8888;
+        while bits != 0 {
+            matches.check(offset + bits.trailing_zeros() as usize / 4);
+            bits &= bits - 1;
+        }
+        offset += 16;
+    }
+
+    matches.finish(offset)
+}
+
+// One bit per byte at bit `4 * i + 3`
+#[target_feature(enable = "neon")]
+unsafe fn match_bits(u8s: uint8x16_t, needles: uint8x16_t) -> u64 {
+    nibble_mask(vceqq_u8(u8s, needles)) & 0x8888_8888_8888_8888
+}
+
+#[target_feature(enable = "neon")]
+pub unsafe fn chunk_nth(haystack: &[u8], needle: u8, mut n: usize) -> Option<usize> {
+    assert!(haystack.len() >= 16);
+
+    let mut offset = 0;
+
+    let needles = vdupq_n_u8(needle);
+
+    // 16320: skip whole blocks that end before the nth occurrence
+    while haystack.len() >= offset + 64 * 255 {
+        let (mut count1, mut count2, mut count3, mut count4) =
+            (vdupq_n_u8(0), vdupq_n_u8(0), vdupq_n_u8(0), vdupq_n_u8(0));
+        for i in 0..255 {
+            let uint8x16x4_t(h1, h2, h3, h4) = u8x16_x4_from_offset(haystack, offset + i * 64);
+            count1 = vsubq_u8(count1, vceqq_u8(h1, needles));
+            count2 = vsubq_u8(count2, vceqq_u8(h2, needles));
+            count3 = vsubq_u8(count3, vceqq_u8(h3, needles));
+            count4 = vsubq_u8(count4, vceqq_u8(h4, needles));
+        }
+        let count = sum4(count1, count2, count3, count4);
+        if count > n {
+            break;
+        }
+        n -= count;
+        offset += 64 * 255;
+    }
+
+    // 16
+    while haystack.len() >= offset + 16 {
+        let bits = match_bits(u8x16_from_offset(haystack, offset), needles);
+        let count = bits.count_ones() as usize;
+        if count > n {
+            return Some(offset + nth_set_bit(bits, n) / 4);
+        }
+        n -= count;
+        offset += 16;
+    }
+    if haystack.len() != offset {
+        let bits = match_bits(u8x16_from_offset(haystack, haystack.len() - 16), needles)
+            >> (4 * (offset + 16 - haystack.len()));
+        if bits.count_ones() as usize > n {
+            return Some(offset + nth_set_bit(bits, n) / 4);
+        }
+    }
+
+    None
+}
+
+#[target_feature(enable = "neon")]
+pub unsafe fn chunk_rcount_until(haystack: &[u8], needle: u8, limit: usize) -> (usize, usize) {
+    assert!(haystack.len() >= 16 && limit > 0);
+
+    let mut count = 0;
+    let mut end = haystack.len();
+
+    let needles = vdupq_n_u8(needle);
+
+    // 16320: skip whole blocks that start after the last wanted occurrence
+    while end >= 64 * 255 {
+        let (mut count1, mut count2, mut count3, mut count4) =
+            (vdupq_n_u8(0), vdupq_n_u8(0), vdupq_n_u8(0), vdupq_n_u8(0));
+        for i in 1..=255 {
+            let uint8x16x4_t(h1, h2, h3, h4) = u8x16_x4_from_offset(haystack, end - i * 64);
+            count1 = vsubq_u8(count1, vceqq_u8(h1, needles));
+            count2 = vsubq_u8(count2, vceqq_u8(h2, needles));
+            count3 = vsubq_u8(count3, vceqq_u8(h3, needles));
+            count4 = vsubq_u8(count4, vceqq_u8(h4, needles));
+        }
+        let block = sum4(count1, count2, count3, count4);
+        if count + block >= limit {
+            break;
+        }
+        count += block;
+        end -= 64 * 255;
+    }
+
+    // 16
+    while end >= 16 {
+        let bits = match_bits(u8x16_from_offset(haystack, end - 16), needles);
+        let found = bits.count_ones() as usize;
+        if count + found >= limit {
+            return (limit, end - 16 + nth_set_bit(bits, count + found - limit) / 4);
+        }
+        count += found;
+        end -= 16;
+    }
+    if end != 0 {
+        let bits = match_bits(u8x16_from_offset(haystack, 0), needles) & ((1 << (4 * end)) - 1);
+        let found = bits.count_ones() as usize;
+        if count + found >= limit {
+            return (limit, nth_set_bit(bits, count + found - limit) / 4);
+        }
+        count += found;
+    }
+
+    (count, 0)
+}
+
+#[cfg(feature = "runtime-dispatch-simd")]
+#[target_feature(enable = "neon")]
+pub unsafe fn chunk_positions<F: FnMut(usize)>(haystack: &[u8], needle: u8, mut f: F) {
+    assert!(haystack.len() >= 16);
+
+    let mut offset = 0;
+
+    let needles = vdupq_n_u8(needle);
+
+    // 16
+    while haystack.len() >= offset + 16 {
+        let mut bits = match_bits(u8x16_from_offset(haystack, offset), needles);
+        while bits != 0 {
+            f(offset + bits.trailing_zeros() as usize / 4);
+            bits &= bits - 1;
+        }
+        offset += 16;
+    }
+    if haystack.len() != offset {
+        let mut bits = match_bits(u8x16_from_offset(haystack, haystack.len() - 16), needles)
+            >> (4 * (offset + 16 - haystack.len()));
+        while bits != 0 {
+            f(offset + bits.trailing_zeros() as usize / 4);
+            bits &= bits - 1;
+        }
+    }
+}
+
+#[target_feature(enable = "neon")]
+unsafe fn is_four_byte_leader(u8s: uint8x16_t) -> uint8x16_t {
+    vcgeq_u8(u8s, vdupq_n_u8(0b1111_0000))
+}
+
+#[target_feature(enable = "neon")]
+pub unsafe fn chunk_utf16_len(utf8_chars: &[u8]) -> usize {
+    assert!(utf8_chars.len() >= 16);
+
+    let mut offset = 0;
+    let mut fours = 0;
+    let mut following = 0;
+
+    // 16320
+    while utf8_chars.len() >= offset + 64 * 255 {
+        let (mut four1, mut four2, mut four3, mut four4) =
+            (vdupq_n_u8(0), vdupq_n_u8(0), vdupq_n_u8(0), vdupq_n_u8(0));
+        let (mut following1, mut following2, mut following3, mut following4) =
+            (vdupq_n_u8(0), vdupq_n_u8(0), vdupq_n_u8(0), vdupq_n_u8(0));
+        for _ in 0..255 {
+            let uint8x16x4_t(h1, h2, h3, h4) = u8x16_x4_from_offset(utf8_chars, offset);
+            four1 = vsubq_u8(four1, is_four_byte_leader(h1));
+            four2 = vsubq_u8(four2, is_four_byte_leader(h2));
+            four3 = vsubq_u8(four3, is_four_byte_leader(h3));
+            four4 = vsubq_u8(four4, is_four_byte_leader(h4));
+            following1 = vsubq_u8(following1, is_following_utf8_byte(h1));
+            following2 = vsubq_u8(following2, is_following_utf8_byte(h2));
+            following3 = vsubq_u8(following3, is_following_utf8_byte(h3));
+            following4 = vsubq_u8(following4, is_following_utf8_byte(h4));
+            offset += 64;
+        }
+        fours += sum4(four1, four2, four3, four4);
+        following += sum4(following1, following2, following3, following4);
+    }
+
+    // 64
+    let (mut four1, mut four2, mut four3, mut four4) =
+        (vdupq_n_u8(0), vdupq_n_u8(0), vdupq_n_u8(0), vdupq_n_u8(0));
+    let (mut following1, mut following2, mut following3, mut following4) =
+        (vdupq_n_u8(0), vdupq_n_u8(0), vdupq_n_u8(0), vdupq_n_u8(0));
+    for _ in 0..(utf8_chars.len() - offset) / 64 {
+        let uint8x16x4_t(h1, h2, h3, h4) = u8x16_x4_from_offset(utf8_chars, offset);
+        four1 = vsubq_u8(four1, is_four_byte_leader(h1));
+        four2 = vsubq_u8(four2, is_four_byte_leader(h2));
+        four3 = vsubq_u8(four3, is_four_byte_leader(h3));
+        four4 = vsubq_u8(four4, is_four_byte_leader(h4));
+        following1 = vsubq_u8(following1, is_following_utf8_byte(h1));
+        following2 = vsubq_u8(following2, is_following_utf8_byte(h2));
+        following3 = vsubq_u8(following3, is_following_utf8_byte(h3));
+        following4 = vsubq_u8(following4, is_following_utf8_byte(h4));
+        offset += 64;
+    }
+    fours += sum4(four1, four2, four3, four4);
+    following += sum4(following1, following2, following3, following4);
+
+    // 16
+    let mut four_counts = vdupq_n_u8(0);
+    let mut following_counts = vdupq_n_u8(0);
+    for i in 0..(utf8_chars.len() - offset) / 16 {
+        let u8s = u8x16_from_offset(utf8_chars, offset + i * 16);
+        four_counts = vsubq_u8(four_counts, is_four_byte_leader(u8s));
+        following_counts = vsubq_u8(following_counts, is_following_utf8_byte(u8s));
+    }
+    if utf8_chars.len() % 16 != 0 {
+        let u8s = u8x16_from_offset(utf8_chars, utf8_chars.len() - 16);
+        let mask = u8x16_from_offset(&MASK, utf8_chars.len() % 16);
+        four_counts = vsubq_u8(four_counts, vandq_u8(is_four_byte_leader(u8s), mask));
+        following_counts = vsubq_u8(following_counts, vandq_u8(is_following_utf8_byte(u8s), mask));
+    }
+    fours += sum(four_counts);
+    following += sum(following_counts);
+
+    utf8_chars.len() - following + fours
+}
+
+#[target_feature(enable = "neon")]
+unsafe fn u16x8_from_offset(slice: &[u16], offset: usize) -> uint16x8_t {
+    debug_assert!(
+        offset + 8 <= slice.len(),
+        "{} + 8 ≥ {}",
+        offset,
+        slice.len()
+    );
+    vld1q_u16(slice.as_ptr().add(offset))
+}
+
+#[target_feature(enable = "neon")]
+unsafe fn sum_u16(u16s: uint16x8_t) -> usize {
+    vaddlvq_u16(u16s) as usize
+}
+
+// The number of bytes short of three that a UTF-16 code unit takes in
+// UTF-8, as a negative count; a surrogate pair takes four bytes, two each.
+#[target_feature(enable = "neon")]
+unsafe fn utf8_deficit(u16s: uint16x8_t) -> uint16x8_t {
+    let below_0x80 = vcltq_u16(u16s, vdupq_n_u16(0x80));
+    let below_0x800 = vcltq_u16(u16s, vdupq_n_u16(0x800));
+    let surrogate = vceqq_u16(vandq_u16(u16s, vdupq_n_u16(0xF800)), vdupq_n_u16(0xD800));
+    vaddq_u16(vaddq_u16(below_0x80, below_0x800), surrogate)
+}
+
+#[target_feature(enable = "neon")]
+pub unsafe fn chunk_utf8_len_from_utf16(utf16: &[u16]) -> usize {
+    assert!(utf16.len() >= 8);
+
+    let mut offset = 0;
+    let mut deficit = 0;
+
+    // 65536
+    while utf16.len() >= offset + 8 * 8192 {
+        let mut counts = vdupq_n_u16(0);
+        for _ in 0..8192 {
+            counts = vsubq_u16(counts, utf8_deficit(u16x8_from_offset(utf16, offset)));
+            offset += 8;
+        }
+        deficit += sum_u16(counts);
+    }
+
+    // 8
+    let mut counts = vdupq_n_u16(0);
+    for i in 0..(utf16.len() - offset) / 8 {
+        counts = vsubq_u16(counts, utf8_deficit(u16x8_from_offset(utf16, offset + i * 8)));
+    }
+    if utf16.len() % 8 != 0 {
+        counts = vsubq_u16(
+            counts,
+            vandq_u16(
+                utf8_deficit(u16x8_from_offset(utf16, utf16.len() - 8)),
+                vreinterpretq_u16_u8(u8x16_from_offset(&MASK, 2 * (utf16.len() % 8))),
+            ),
+        );
+    }
+    deficit += sum_u16(counts);
+
+    3 * utf16.len() - deficit
+}
+
+#[target_feature(enable = "neon")]
+unsafe fn is_low_surrogate(u16s: uint16x8_t) -> uint16x8_t {
+    vceqq_u16(vandq_u16(u16s, vdupq_n_u16(0xFC00)), vdupq_n_u16(0xDC00))
+}
+
+#[target_feature(enable = "neon")]
+pub unsafe fn chunk_num_chars_utf16(utf16: &[u16]) -> usize {
+    assert!(utf16.len() >= 8);
+
+    let mut offset = 0;
+    let mut low_surrogates = 0;
+
+    // 65536
+    while utf16.len() >= offset + 8 * 8192 {
+        let mut counts = vdupq_n_u16(0);
+        for _ in 0..8192 {
+            counts = vsubq_u16(counts, is_low_surrogate(u16x8_from_offset(utf16, offset)));
+            offset += 8;
+        }
+        low_surrogates += sum_u16(counts);
+    }
+
+    // 8
+    let mut counts = vdupq_n_u16(0);
+    for i in 0..(utf16.len() - offset) / 8 {
+        counts = vsubq_u16(counts, is_low_surrogate(u16x8_from_offset(utf16, offset + i * 8)));
+    }
+    if utf16.len() % 8 != 0 {
+        counts = vsubq_u16(
+            counts,
+            vandq_u16(
+                is_low_surrogate(u16x8_from_offset(utf16, utf16.len() - 8)),
+                vreinterpretq_u16_u8(u8x16_from_offset(&MASK, 2 * (utf16.len() % 8))),
+            ),
+        );
+    }
+    low_surrogates += sum_u16(counts);
+
+    utf16.len() - low_surrogates
+}
+
+#[target_feature(enable = "neon")]
+unsafe fn count_below_each<const N: usize>(
+    counts: &mut [uint8x16_t; N],
+    u8s: uint8x16_t,
+    bounds: &[uint8x16_t; N],
+) {
+    for (counts, &bounds) in counts.iter_mut().zip(bounds.iter()) {
+        *counts = vsubq_u8(*counts, vcltq_u8(u8s, bounds));
+    }
+}
+
+#[target_feature(enable = "neon")]
+pub unsafe fn chunk_count_below<const N: usize>(haystack: &[u8], bounds: [u8; N]) -> [usize; N] {
+    assert!(haystack.len() >= 16);
+
+    let mut offset = 0;
+    let mut count = [0; N];
+
+    let bounds = bounds.map(|bound| vdupq_n_u8(bound));
+
+    // 16320
+    while haystack.len() >= offset + 64 * 255 {
+        let (mut count1, mut count2, mut count3, mut count4) = (
+            [vdupq_n_u8(0); N],
+            [vdupq_n_u8(0); N],
+            [vdupq_n_u8(0); N],
+            [vdupq_n_u8(0); N],
+        );
+        for _ in 0..255 {
+            let uint8x16x4_t(h1, h2, h3, h4) = u8x16_x4_from_offset(haystack, offset);
+            count_below_each(&mut count1, h1, &bounds);
+            count_below_each(&mut count2, h2, &bounds);
+            count_below_each(&mut count3, h3, &bounds);
+            count_below_each(&mut count4, h4, &bounds);
+            offset += 64;
+        }
+        for i in 0..N {
+            count[i] += sum4(count1[i], count2[i], count3[i], count4[i]);
+        }
+    }
+
+    // 64
+    let (mut count1, mut count2, mut count3, mut count4) = (
+        [vdupq_n_u8(0); N],
+        [vdupq_n_u8(0); N],
+        [vdupq_n_u8(0); N],
+        [vdupq_n_u8(0); N],
+    );
+    for _ in 0..(haystack.len() - offset) / 64 {
+        let uint8x16x4_t(h1, h2, h3, h4) = u8x16_x4_from_offset(haystack, offset);
+        count_below_each(&mut count1, h1, &bounds);
+        count_below_each(&mut count2, h2, &bounds);
+        count_below_each(&mut count3, h3, &bounds);
+        count_below_each(&mut count4, h4, &bounds);
+        offset += 64;
+    }
+    for i in 0..N {
+        count[i] += sum4(count1[i], count2[i], count3[i], count4[i]);
+    }
+
+    let mut counts = [vdupq_n_u8(0); N];
+    // 16
+    for i in 0..(haystack.len() - offset) / 16 {
+        count_below_each(&mut counts, u8x16_from_offset(haystack, offset + i * 16), &bounds);
+    }
+    if haystack.len() % 16 != 0 {
+        let mask = u8x16_from_offset(&MASK, haystack.len() % 16);
+        let u8s = u8x16_from_offset(haystack, haystack.len() - 16);
+        for (counts, &bounds) in counts.iter_mut().zip(bounds.iter()) {
+            *counts = vsubq_u8(*counts, vandq_u8(vcltq_u8(u8s, bounds), mask));
+        }
+    }
+    for (count, &counts) in count.iter_mut().zip(counts.iter()) {
+        *count += sum(counts);
+    }
+
+    count
+}
@@ -6,7 +6,16 @@ use core::{mem, simd};
 #[cfg(feature = "runtime-dispatch-simd")]
 use std::{mem, simd};
 
-use simd::{u8x32, u8x64, cmp::SimdPartialEq, num::SimdInt};
+use crate::bits::nth_set_bit;
+use crate::substring::Matches;
+use crate::utf8::{UTF8_HIGH, UTF8_PREV_HIGH, UTF8_PREV_LOW};
+
+use simd::{
+    cmp::{SimdPartialEq, SimdPartialOrd},
+    mask8x32, mask8x64,
+    num::{SimdInt, SimdUint},
+    u16x16, u8x32, u8x64,
+};
 
 const MASK: [u8; 64] = [
     0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
@@ -138,3 +147,850 @@ pub fn chunk_num_chars(utf8_chars: &[u8]) -> usize {
         count
     }
 }
+
+// The last `N` bytes of `previous` followed by all but the last `N` bytes
+// of `u8s`
+fn shift_in<const N: usize>(u8s: u8x32, previous: u8x32) -> u8x32 {
+    let first = u8x32::from_array(core::array::from_fn(|i| if i < N { 0xFF } else { 0 }));
+    (u8s.rotate_elements_right::<N>() & !first) | (previous.rotate_elements_right::<N>() & first)
+}
+
+// The error bits of each byte of `u8s` given the bytes before it, as laid
+// out in `crate::utf8`; any bit set means the input is not valid UTF-8.
+fn utf8_errors_x32(u8s: u8x32, previous: u8x32) -> u8x32 {
+    let prev1 = shift_in::<1>(u8s, previous);
+    let errors = table_x32(&UTF8_PREV_HIGH).swizzle_dyn(prev1 >> 4)
+        & table_x32(&UTF8_PREV_LOW).swizzle_dyn(prev1 & u8x32::splat(0x0F))
+        & table_x32(&UTF8_HIGH).swizzle_dyn(u8s >> 4);
+    // The third and fourth bytes of a sequence must be continuation bytes
+    let must_continue = (shift_in::<2>(u8s, previous).saturating_sub(u8x32::splat(0xE0 - 0x80))
+        | shift_in::<3>(u8s, previous).saturating_sub(u8x32::splat(0xF0 - 0x80)))
+        & u8x32::splat(0x80);
+    errors ^ must_continue
+}
+
+pub fn chunk_try_num_chars(utf8_chars: &[u8]) -> Option<usize> {
+    assert!(utf8_chars.len() >= 32);
+
+    unsafe {
+        let mut offset = 0;
+        let mut count = 0;
+
+        let mut previous = u8x32::splat(0);
+        let mut errors = u8x32::splat(0);
+
+        // 8160
+        while utf8_chars.len() >= offset + 32 * 255 {
+            let mut counts = u8x32::splat(0);
+            for _ in 0..255 {
+                let u8s = u8x32_from_offset(utf8_chars, offset);
+                errors |= utf8_errors_x32(u8s, previous);
+                counts -= is_leading_utf8_byte_x32(u8s);
+                previous = u8s;
+                offset += 32;
+            }
+            if errors.reduce_or() != 0 {
+                return None;
+            }
+            count += sum_x32(&counts);
+        }
+
+        // 32
+        let mut counts = u8x32::splat(0);
+        while utf8_chars.len() >= offset + 32 {
+            let u8s = u8x32_from_offset(utf8_chars, offset);
+            errors |= utf8_errors_x32(u8s, previous);
+            counts -= is_leading_utf8_byte_x32(u8s);
+            previous = u8s;
+            offset += 32;
+        }
+        // The rest is padded with zeros, which also catches a sequence that
+        // the end of the input cuts short. Each padding byte counts as a
+        // character.
+        let mut rest = [0; 32];
+        rest[..utf8_chars.len() - offset].copy_from_slice(&utf8_chars[offset..]);
+        let u8s = u8x32::from_array(rest);
+        errors |= utf8_errors_x32(u8s, previous);
+        counts -= is_leading_utf8_byte_x32(u8s);
+        if errors.reduce_or() != 0 {
+            return None;
+        }
+        count += sum_x32(&counts);
+
+        Some(count - (offset + 32 - utf8_chars.len()))
+    }
+}
+
+fn is_any_of_x64(u8s: u8x64, needles: &[u8x64]) -> u8x64 {
+    let mut matches = mask8x64::splat(false);
+    for &needle in needles {
+        matches |= u8s.simd_eq(needle);
+    }
+    matches.to_int().cast()
+}
+
+fn is_any_of_x32(u8s: u8x32, needles: &[u8x32]) -> u8x32 {
+    let mut matches = mask8x32::splat(false);
+    for &needle in needles {
+        matches |= u8s.simd_eq(needle);
+    }
+    matches.to_int().cast()
+}
+
+pub fn chunk_count_any(haystack: &[u8], needles: &[u8]) -> usize {
+    assert!(haystack.len() >= 32);
+    assert!(needles.len() <= 8);
+
+    unsafe {
+        let mut offset = 0;
+        let mut count = 0;
+
+        let mut splats_x64 = [u8x64::splat(0); 8];
+        let mut splats_x32 = [u8x32::splat(0); 8];
+        for (i, &needle) in needles.iter().enumerate() {
+            splats_x64[i] = u8x64::splat(needle);
+            splats_x32[i] = u8x32::splat(needle);
+        }
+        let needles_x64 = &splats_x64[..needles.len()];
+        let needles_x32 = &splats_x32[..needles.len()];
+
+        // 16320
+        while haystack.len() >= offset + 64 * 255 {
+            let mut counts = u8x64::splat(0);
+            for _ in 0..255 {
+                counts -= is_any_of_x64(u8x64_from_offset(haystack, offset), needles_x64);
+                offset += 64;
+            }
+            count += sum_x64(&counts);
+        }
+
+        // 8192
+        if haystack.len() >= offset + 64 * 128 {
+            let mut counts = u8x64::splat(0);
+            for _ in 0..128 {
+                counts -= is_any_of_x64(u8x64_from_offset(haystack, offset), needles_x64);
+                offset += 64;
+            }
+            count += sum_x64(&counts);
+        }
+
+        // 32
+        let mut counts = u8x32::splat(0);
+        for i in 0..(haystack.len() - offset) / 32 {
+            counts -= is_any_of_x32(u8x32_from_offset(haystack, offset + i * 32), needles_x32);
+        }
+        count += sum_x32(&counts);
+
+        // Straggler; need to reset counts because prior loop can run 255 times
+        counts = u8x32::splat(0);
+        if haystack.len() % 32 != 0 {
+            counts -= is_any_of_x32(u8x32_from_offset(haystack, haystack.len() - 32), needles_x32)
+                & u8x32_from_offset(&MASK, haystack.len() % 32);
+        }
+        count += sum_x32(&counts);
+
+        count
+    }
+}
+
+const BITS: [u8; 16] = [1, 2, 4, 8, 16, 32, 64, 128, 1, 2, 4, 8, 16, 32, 64, 128];
+
+// Widens a 16-entry table so it can be used with `swizzle_dyn` on wider
+// vectors; only indices below 16 are ever looked up.
+fn table_x64(table: &[u8]) -> u8x64 {
+    let mut wide = [0; 64];
+    wide[..16].copy_from_slice(&table[..16]);
+    u8x64::from_array(wide)
+}
+
+fn table_x32(table: &[u8]) -> u8x32 {
+    let mut wide = [0; 32];
+    wide[..16].copy_from_slice(&table[..16]);
+    u8x32::from_array(wide)
+}
+
+// Out-of-range indices swizzle in zero, so each table only yields a row
+// for its half of the byte range.
+fn is_in_set_x64(u8s: u8x64, lower: u8x64, upper: u8x64, bits: u8x64) -> u8x64 {
+    let index = u8s & u8x64::splat(0x8F);
+    let rows = lower.swizzle_dyn(index) | upper.swizzle_dyn(index ^ u8x64::splat(0x80));
+    (rows & bits.swizzle_dyn(u8s >> 4)).simd_ne(u8x64::splat(0)).to_int().cast()
+}
+
+fn is_in_set_x32(u8s: u8x32, lower: u8x32, upper: u8x32, bits: u8x32) -> u8x32 {
+    let index = u8s & u8x32::splat(0x8F);
+    let rows = lower.swizzle_dyn(index) | upper.swizzle_dyn(index ^ u8x32::splat(0x80));
+    (rows & bits.swizzle_dyn(u8s >> 4)).simd_ne(u8x32::splat(0)).to_int().cast()
+}
+
+pub fn chunk_count_set(haystack: &[u8], table: &[u8; 32]) -> usize {
+    assert!(haystack.len() >= 32);
+
+    unsafe {
+        let mut offset = 0;
+        let mut count = 0;
+
+        let (lower_x64, upper_x64, bits_x64) =
+            (table_x64(&table[..16]), table_x64(&table[16..]), table_x64(&BITS));
+
+        // 16320
+        while haystack.len() >= offset + 64 * 255 {
+            let mut counts = u8x64::splat(0);
+            for _ in 0..255 {
+                counts -= is_in_set_x64(
+                    u8x64_from_offset(haystack, offset),
+                    lower_x64,
+                    upper_x64,
+                    bits_x64,
+                );
+                offset += 64;
+            }
+            count += sum_x64(&counts);
+        }
+
+        // 8192
+        if haystack.len() >= offset + 64 * 128 {
+            let mut counts = u8x64::splat(0);
+            for _ in 0..128 {
+                counts -= is_in_set_x64(
+                    u8x64_from_offset(haystack, offset),
+                    lower_x64,
+                    upper_x64,
+                    bits_x64,
+                );
+                offset += 64;
+            }
+            count += sum_x64(&counts);
+        }
+
+        let (lower_x32, upper_x32, bits_x32) =
+            (table_x32(&table[..16]), table_x32(&table[16..]), table_x32(&BITS));
+
+        // 32
+        let mut counts = u8x32::splat(0);
+        for i in 0..(haystack.len() - offset) / 32 {
+            counts -= is_in_set_x32(
+                u8x32_from_offset(haystack, offset + i * 32),
+                lower_x32,
+                upper_x32,
+                bits_x32,
+            );
+        }
+        count += sum_x32(&counts);
+
+        // Straggler; need to reset counts because prior loop can run 255 times
+        counts = u8x32::splat(0);
+        if haystack.len() % 32 != 0 {
+            counts -= is_in_set_x32(
+                u8x32_from_offset(haystack, haystack.len() - 32),
+                lower_x32,
+                upper_x32,
+                bits_x32,
+            ) & u8x32_from_offset(&MASK, haystack.len() % 32);
+        }
+        count += sum_x32(&counts);
+
+        count
+    }
+}
+
+// `lo <= x <= hi` iff `x - lo <= hi - lo`, i.e. iff the saturating
+// difference to the span is zero.
+fn is_in_range_x64(u8s: u8x64, lows: u8x64, spans: u8x64) -> u8x64 {
+    (u8s - lows).saturating_sub(spans).simd_eq(u8x64::splat(0)).to_int().cast()
+}
+
+fn is_in_range_x32(u8s: u8x32, lows: u8x32, spans: u8x32) -> u8x32 {
+    (u8s - lows).saturating_sub(spans).simd_eq(u8x32::splat(0)).to_int().cast()
+}
+
+pub fn chunk_count_range(haystack: &[u8], lo: u8, hi: u8) -> usize {
+    assert!(haystack.len() >= 32);
+    assert!(lo <= hi);
+
+    unsafe {
+        let mut offset = 0;
+        let mut count = 0;
+
+        let (lows_x64, spans_x64) = (u8x64::splat(lo), u8x64::splat(hi - lo));
+
+        // 16320
+        while haystack.len() >= offset + 64 * 255 {
+            let mut counts = u8x64::splat(0);
+            for _ in 0..255 {
+                counts -=
+                    is_in_range_x64(u8x64_from_offset(haystack, offset), lows_x64, spans_x64);
+                offset += 64;
+            }
+            count += sum_x64(&counts);
+        }
+
+        // 8192
+        if haystack.len() >= offset + 64 * 128 {
+            let mut counts = u8x64::splat(0);
+            for _ in 0..128 {
+                counts -=
+                    is_in_range_x64(u8x64_from_offset(haystack, offset), lows_x64, spans_x64);
+                offset += 64;
+            }
+            count += sum_x64(&counts);
+        }
+
+        let (lows_x32, spans_x32) = (u8x32::splat(lo), u8x32::splat(hi - lo));
+
+        // 32
+        let mut counts = u8x32::splat(0);
+        for i in 0..(haystack.len() - offset) / 32 {
+            counts -=
+                is_in_range_x32(u8x32_from_offset(haystack, offset + i * 32), lows_x32, spans_x32);
+        }
+        count += sum_x32(&counts);
+
+        // Straggler; need to reset counts because prior loop can run 255 times
+        counts = u8x32::splat(0);
+        if haystack.len() % 32 != 0 {
+            counts -= is_in_range_x32(
+                u8x32_from_offset(haystack, haystack.len() - 32),
+                lows_x32,
+                spans_x32,
+            ) & u8x32_from_offset(&MASK, haystack.len() % 32);
+        }
+        count += sum_x32(&counts);
+
+        count
+    }
+}
+
+pub fn chunk_count_many<const N: usize>(haystack: &[u8], needles: [u8; N]) -> [usize; N] {
+    assert!(haystack.len() >= 32);
+
+    unsafe {
+        let mut offset = 0;
+        let mut count = [0; N];
+
+        let needles_x64 = needles.map(u8x64::splat);
+
+        // 16320
+        while haystack.len() >= offset + 64 * 255 {
+            let mut counts = [u8x64::splat(0); N];
+            for _ in 0..255 {
+                let u8s = u8x64_from_offset(haystack, offset);
+                for (counts, &needles) in counts.iter_mut().zip(needles_x64.iter()) {
+                    *counts -= u8s.simd_eq(needles).to_int().cast();
+                }
+                offset += 64;
+            }
+            for (count, counts) in count.iter_mut().zip(counts.iter()) {
+                *count += sum_x64(counts);
+            }
+        }
+
+        // 8192
+        if haystack.len() >= offset + 64 * 128 {
+            let mut counts = [u8x64::splat(0); N];
+            for _ in 0..128 {
+                let u8s = u8x64_from_offset(haystack, offset);
+                for (counts, &needles) in counts.iter_mut().zip(needles_x64.iter()) {
+                    *counts -= u8s.simd_eq(needles).to_int().cast();
+                }
+                offset += 64;
+            }
+            for (count, counts) in count.iter_mut().zip(counts.iter()) {
+                *count += sum_x64(counts);
+            }
+        }
+
+        let needles_x32 = needles.map(u8x32::splat);
+
+        // 32
+        let mut counts = [u8x32::splat(0); N];
+        for i in 0..(haystack.len() - offset) / 32 {
+            let u8s = u8x32_from_offset(haystack, offset + i * 32);
+            for (counts, &needles) in counts.iter_mut().zip(needles_x32.iter()) {
+                *counts -= u8s.simd_eq(needles).to_int().cast();
+            }
+        }
+        for (count, counts) in count.iter_mut().zip(counts.iter()) {
+            *count += sum_x32(counts);
+        }
+
+        // Straggler; need to reset counts because prior loop can run 255 times
+        if haystack.len() % 32 != 0 {
+            let mask = u8x32_from_offset(&MASK, haystack.len() % 32);
+            let u8s = u8x32_from_offset(haystack, haystack.len() - 32);
+            for (count, &needles) in count.iter_mut().zip(needles_x32.iter()) {
+                let counts = u8x32::splat(0) - (u8s.simd_eq(needles).to_int().cast() & mask);
+                *count += sum_x32(&counts);
+            }
+        }
+
+        count
+    }
+}
+
+pub fn chunk_text_stats(utf8_chars: &[u8]) -> (usize, usize) {
+    assert!(utf8_chars.len() >= 32);
+
+    unsafe {
+        let mut offset = 0;
+        let mut newlines = 0;
+        let mut chars = 0;
+
+        let needles_x64 = u8x64::splat(b'\n');
+
+        // 16320
+        while utf8_chars.len() >= offset + 64 * 255 {
+            let mut newline_counts = u8x64::splat(0);
+            let mut char_counts = u8x64::splat(0);
+            for _ in 0..255 {
+                let u8s = u8x64_from_offset(utf8_chars, offset);
+                newline_counts -= u8s.simd_eq(needles_x64).to_int().cast();
+                char_counts -= is_leading_utf8_byte_x64(u8s);
+                offset += 64;
+            }
+            newlines += sum_x64(&newline_counts);
+            chars += sum_x64(&char_counts);
+        }
+
+        // 8192
+        if utf8_chars.len() >= offset + 64 * 128 {
+            let mut newline_counts = u8x64::splat(0);
+            let mut char_counts = u8x64::splat(0);
+            for _ in 0..128 {
+                let u8s = u8x64_from_offset(utf8_chars, offset);
+                newline_counts -= u8s.simd_eq(needles_x64).to_int().cast();
+                char_counts -= is_leading_utf8_byte_x64(u8s);
+                offset += 64;
+            }
+            newlines += sum_x64(&newline_counts);
+            chars += sum_x64(&char_counts);
+        }
+
+        let needles_x32 = u8x32::splat(b'\n');
+
+        // 32
+        let mut newline_counts = u8x32::splat(0);
+        let mut char_counts = u8x32::splat(0);
+        for i in 0..(utf8_chars.len() - offset) / 32 {
+            let u8s = u8x32_from_offset(utf8_chars, offset + i * 32);
+            newline_counts -= u8s.simd_eq(needles_x32).to_int().cast();
+            char_counts -= is_leading_utf8_byte_x32(u8s);
+        }
+        newlines += sum_x32(&newline_counts);
+        chars += sum_x32(&char_counts);
+
+        // Straggler; need to reset counts because prior loop can run 255 times
+        newline_counts = u8x32::splat(0);
+        char_counts = u8x32::splat(0);
+        if utf8_chars.len() % 32 != 0 {
+            let u8s = u8x32_from_offset(utf8_chars, utf8_chars.len() - 32);
+            let mask = u8x32_from_offset(&MASK, utf8_chars.len() % 32);
+            newline_counts -= u8s.simd_eq(needles_x32).to_int().cast() & mask;
+            char_counts -= is_leading_utf8_byte_x32(u8s) & mask;
+        }
+        newlines += sum_x32(&newline_counts);
+        chars += sum_x32(&char_counts);
+
+        (newlines, chars)
+    }
+}
+
+pub fn chunk_count_substring(haystack: &[u8], needle: &[u8], overlapping: bool) -> usize {
+    assert!(needle.len() >= 2);
+    assert!(haystack.len() >= needle.len() - 1 + 32);
+
+    unsafe {
+        let last = needle.len() - 1;
+        let mut offset = 0;
+        let mut matches = Matches::new(haystack, needle, overlapping);
+
+        let firsts = u8x32::splat(needle[0]);
+        let lasts = u8x32::splat(needle[last]);
+
+        // 32
+        while haystack.len() >= offset + last + 32 {
+            let candidates = u8x32_from_offset(haystack, offset).simd_eq(firsts)
+                & u8x32_from_offset(haystack, offset + last).simd_eq(lasts);
+            let mut bits = candidates.to_bitmask();
+            while bits != 0 {
+                matches.check(offset + bits.trailing_zeros() as usize);
+                bits &= bits - 1;
+            }
+            offset += 32;
+        }
+
+        matches.finish(offset)
+    }
+}
+
+pub fn chunk_nth(haystack: &[u8], needle: u8, mut n: usize) -> Option<usize> {
+    assert!(haystack.len() >= 32);
+
+    unsafe {
+        let mut offset = 0;
+
+        let needles_x64 = u8x64::splat(needle);
+
+        // 16320: skip whole blocks that end before the nth occurrence
+        while haystack.len() >= offset + 64 * 255 {
+            let mut counts = u8x64::splat(0);
+            for i in 0..255 {
+                counts -= u8x64_from_offset(haystack, offset + i * 64)
+                    .simd_eq(needles_x64)
+                    .to_int()
+                    .cast();
+            }
+            let count = sum_x64(&counts);
+            if count > n {
+                break;
+            }
+            n -= count;
+            offset += 64 * 255;
+        }
+
+        let needles_x32 = u8x32::splat(needle);
+
+        // 32
+        while haystack.len() >= offset + 32 {
+            let bits = u8x32_from_offset(haystack, offset).simd_eq(needles_x32).to_bitmask();
+            let count = bits.count_ones() as usize;
+            if count > n {
+                return Some(offset + nth_set_bit(bits, n));
+            }
+            n -= count;
+            offset += 32;
+        }
+        if haystack.len() != offset {
+            let bits = u8x32_from_offset(haystack, haystack.len() - 32)
+                .simd_eq(needles_x32)
+                .to_bitmask()
+                >> (offset + 32 - haystack.len());
+            if bits.count_ones() as usize > n {
+                return Some(offset + nth_set_bit(bits, n));
+            }
+        }
+
+        None
+    }
+}
+
+pub fn chunk_rcount_until(haystack: &[u8], needle: u8, limit: usize) -> (usize, usize) {
+    assert!(haystack.len() >= 32 && limit > 0);
+
+    unsafe {
+        let mut count = 0;
+        let mut end = haystack.len();
+
+        let needles_x64 = u8x64::splat(needle);
+
+        // 16320: skip whole blocks that start after the last wanted occurrence
+        while end >= 64 * 255 {
+            let mut counts = u8x64::splat(0);
+            for i in 1..=255 {
+                counts -= u8x64_from_offset(haystack, end - i * 64)
+                    .simd_eq(needles_x64)
+                    .to_int()
+                    .cast();
+            }
+            let block = sum_x64(&counts);
+            if count + block >= limit {
+                break;
+            }
+            count += block;
+            end -= 64 * 255;
+        }
+
+        let needles_x32 = u8x32::splat(needle);
+
+        // 32
+        while end >= 32 {
+            let bits = u8x32_from_offset(haystack, end - 32).simd_eq(needles_x32).to_bitmask();
+            let found = bits.count_ones() as usize;
+            if count + found >= limit {
+                return (limit, end - 32 + nth_set_bit(bits, count + found - limit));
+            }
+            count += found;
+            end -= 32;
+        }
+        if end != 0 {
+            let bits = u8x32_from_offset(haystack, 0).simd_eq(needles_x32).to_bitmask()
+                & ((1 << end) - 1);
+            let found = bits.count_ones() as usize;
+            if count + found >= limit {
+                return (limit, nth_set_bit(bits, count + found - limit));
+            }
+            count += found;
+        }
+
+        (count, 0)
+    }
+}
+
+#[cfg(feature = "runtime-dispatch-simd")]
+pub fn chunk_positions<F: FnMut(usize)>(haystack: &[u8], needle: u8, mut f: F) {
+    assert!(haystack.len() >= 32);
+
+    unsafe {
+        let mut offset = 0;
+
+        let needles = u8x32::splat(needle);
+
+        // 32
+        while haystack.len() >= offset + 32 {
+            let mut bits = u8x32_from_offset(haystack, offset).simd_eq(needles).to_bitmask();
+            while bits != 0 {
+                f(offset + bits.trailing_zeros() as usize);
+                bits &= bits - 1;
+            }
+            offset += 32;
+        }
+        if haystack.len() != offset {
+            let mut bits = u8x32_from_offset(haystack, haystack.len() - 32)
+                .simd_eq(needles)
+                .to_bitmask()
+                >> (offset + 32 - haystack.len());
+            while bits != 0 {
+                f(offset + bits.trailing_zeros() as usize);
+                bits &= bits - 1;
+            }
+        }
+    }
+}
+
+fn is_four_byte_leader_x64(u8s: u8x64) -> u8x64 {
+    u8s.simd_ge(u8x64::splat(0b1111_0000)).to_int().cast()
+}
+
+fn is_four_byte_leader_x32(u8s: u8x32) -> u8x32 {
+    u8s.simd_ge(u8x32::splat(0b1111_0000)).to_int().cast()
+}
+
+pub fn chunk_utf16_len(utf8_chars: &[u8]) -> usize {
+    assert!(utf8_chars.len() >= 32);
+
+    unsafe {
+        let mut offset = 0;
+        let mut fours = 0;
+        let mut chars = 0;
+
+        // 16320
+        while utf8_chars.len() >= offset + 64 * 255 {
+            let mut four_counts = u8x64::splat(0);
+            let mut char_counts = u8x64::splat(0);
+            for _ in 0..255 {
+                let u8s = u8x64_from_offset(utf8_chars, offset);
+                four_counts -= is_four_byte_leader_x64(u8s);
+                char_counts -= is_leading_utf8_byte_x64(u8s);
+                offset += 64;
+            }
+            fours += sum_x64(&four_counts);
+            chars += sum_x64(&char_counts);
+        }
+
+        // 8192
+        if utf8_chars.len() >= offset + 64 * 128 {
+            let mut four_counts = u8x64::splat(0);
+            let mut char_counts = u8x64::splat(0);
+            for _ in 0..128 {
+                let u8s = u8x64_from_offset(utf8_chars, offset);
+                four_counts -= is_four_byte_leader_x64(u8s);
+                char_counts -= is_leading_utf8_byte_x64(u8s);
+                offset += 64;
+            }
+            fours += sum_x64(&four_counts);
+            chars += sum_x64(&char_counts);
+        }
+
+        // 32
+        let mut four_counts = u8x32::splat(0);
+        let mut char_counts = u8x32::splat(0);
+        for i in 0..(utf8_chars.len() - offset) / 32 {
+            let u8s = u8x32_from_offset(utf8_chars, offset + i * 32);
+            four_counts -= is_four_byte_leader_x32(u8s);
+            char_counts -= is_leading_utf8_byte_x32(u8s);
+        }
+        fours += sum_x32(&four_counts);
+        chars += sum_x32(&char_counts);
+
+        // Straggler; need to reset counts because prior loop can run 255 times
+        four_counts = u8x32::splat(0);
+        char_counts = u8x32::splat(0);
+        if utf8_chars.len() % 32 != 0 {
+            let u8s = u8x32_from_offset(utf8_chars, utf8_chars.len() - 32);
+            let mask = u8x32_from_offset(&MASK, utf8_chars.len() % 32);
+            four_counts -= is_four_byte_leader_x32(u8s) & mask;
+            char_counts -= is_leading_utf8_byte_x32(u8s) & mask;
+        }
+        fours += sum_x32(&four_counts);
+        chars += sum_x32(&char_counts);
+
+        chars + fours
+    }
+}
+
+unsafe fn u16x16_from_offset(slice: &[u16], offset: usize) -> u16x16 {
+    u16x16::from_slice(slice.get_unchecked(offset..))
+}
+
+fn sum_u16x16(u16s: u16x16) -> usize {
+    u16s.cast::<u32>().reduce_sum() as usize
+}
+
+// Selects the last `n` lanes, like `MASK` does for bytes.
+fn last_lanes_x16(n: usize) -> u16x16 {
+    const LANES: [u16; 16] = [0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15];
+    u16x16::from_array(LANES)
+        .simd_ge(u16x16::splat((16 - n) as u16))
+        .to_int()
+        .cast()
+}
+
+// The number of bytes short of three that a UTF-16 code unit takes in
+// UTF-8; a surrogate pair takes four bytes, two each.
+fn utf8_deficit_x16(u16s: u16x16) -> u16x16 {
+    let below_0x80: u16x16 = u16s.simd_lt(u16x16::splat(0x80)).to_int().cast();
+    let below_0x800: u16x16 = u16s.simd_lt(u16x16::splat(0x800)).to_int().cast();
+    let surrogate: u16x16 = (u16s & u16x16::splat(0xF800))
+        .simd_eq(u16x16::splat(0xD800))
+        .to_int()
+        .cast();
+    u16x16::splat(0) - below_0x80 - below_0x800 - surrogate
+}
+
+pub fn chunk_utf8_len_from_utf16(utf16: &[u16]) -> usize {
+    assert!(utf16.len() >= 16);
+
+    unsafe {
+        let mut offset = 0;
+        let mut deficit = 0;
+
+        // 131072
+        while utf16.len() >= offset + 16 * 8192 {
+            let mut counts = u16x16::splat(0);
+            for _ in 0..8192 {
+                counts += utf8_deficit_x16(u16x16_from_offset(utf16, offset));
+                offset += 16;
+            }
+            deficit += sum_u16x16(counts);
+        }
+
+        // 16
+        let mut counts = u16x16::splat(0);
+        for i in 0..(utf16.len() - offset) / 16 {
+            counts += utf8_deficit_x16(u16x16_from_offset(utf16, offset + i * 16));
+        }
+        if utf16.len() % 16 != 0 {
+            counts += utf8_deficit_x16(u16x16_from_offset(utf16, utf16.len() - 16))
+                & last_lanes_x16(utf16.len() % 16);
+        }
+        deficit += sum_u16x16(counts);
+
+        3 * utf16.len() - deficit
+    }
+}
+
+fn is_low_surrogate_x16(u16s: u16x16) -> u16x16 {
+    (u16s & u16x16::splat(0xFC00))
+        .simd_eq(u16x16::splat(0xDC00))
+        .to_int()
+        .cast()
+}
+
+pub fn chunk_num_chars_utf16(utf16: &[u16]) -> usize {
+    assert!(utf16.len() >= 16);
+
+    unsafe {
+        let mut offset = 0;
+        let mut low_surrogates = 0;
+
+        // 131072
+        while utf16.len() >= offset + 16 * 8192 {
+            let mut counts = u16x16::splat(0);
+            for _ in 0..8192 {
+                counts -= is_low_surrogate_x16(u16x16_from_offset(utf16, offset));
+                offset += 16;
+            }
+            low_surrogates += sum_u16x16(counts);
+        }
+
+        // 16
+        let mut counts = u16x16::splat(0);
+        for i in 0..(utf16.len() - offset) / 16 {
+            counts -= is_low_surrogate_x16(u16x16_from_offset(utf16, offset + i * 16));
+        }
+        if utf16.len() % 16 != 0 {
+            counts -= is_low_surrogate_x16(u16x16_from_offset(utf16, utf16.len() - 16))
+                & last_lanes_x16(utf16.len() % 16);
+        }
+        low_surrogates += sum_u16x16(counts);
+
+        utf16.len() - low_surrogates
+    }
+}
+
+pub fn chunk_count_below<const N: usize>(haystack: &[u8], bounds: [u8; N]) -> [usize; N] {
+    assert!(haystack.len() >= 32);
+
+    unsafe {
+        let mut offset = 0;
+        let mut count = [0; N];
+
+        let bounds_x64 = bounds.map(u8x64::splat);
+
+        // 16320
+        while haystack.len() >= offset + 64 * 255 {
+            let mut counts = [u8x64::splat(0); N];
+            for _ in 0..255 {
+                let u8s = u8x64_from_offset(haystack, offset);
+                for (counts, &bounds) in counts.iter_mut().zip(bounds_x64.iter()) {
+                    *counts -= u8s.simd_lt(bounds).to_int().cast();
+                }
+                offset += 64;
+            }
+            for (count, counts) in count.iter_mut().zip(counts.iter()) {
+                *count += sum_x64(counts);
+            }
+        }
+
+        // 8192
+        if haystack.len() >= offset + 64 * 128 {
+            let mut counts = [u8x64::splat(0); N];
+            for _ in 0..128 {
+                let u8s = u8x64_from_offset(haystack, offset);
+                for (counts, &bounds) in counts.iter_mut().zip(bounds_x64.iter()) {
+                    *counts -= u8s.simd_lt(bounds).to_int().cast();
+                }
+                offset += 64;
+            }
+            for (count, counts) in count.iter_mut().zip(counts.iter()) {
+                *count += sum_x64(counts);
+            }
+        }
+
+        let bounds_x32 = bounds.map(u8x32::splat);
+
+        // 32
+        let mut counts = [u8x32::splat(0); N];
+        for i in 0..(haystack.len() - offset) / 32 {
+            let u8s = u8x32_from_offset(haystack, offset + i * 32);
+            for (counts, &bounds) in counts.iter_mut().zip(bounds_x32.iter()) {
+                *counts -= u8s.simd_lt(bounds).to_int().cast();
+            }
+        }
+        for (count, counts) in count.iter_mut().zip(counts.iter()) {
+            *count += sum_x32(counts);
+        }
+
+        // Straggler; need to reset counts because prior loop can run 255 times
+        if haystack.len() % 32 != 0 {
+            let mask = u8x32_from_offset(&MASK, haystack.len() % 32);
+            let u8s = u8x32_from_offset(haystack, haystack.len() - 32);
+            for (count, &bounds) in count.iter_mut().zip(bounds_x32.iter()) {
+                let counts = u8x32::splat(0) - (u8s.simd_lt(bounds).to_int().cast() & mask);
+                *count += sum_x32(&counts);
+            }
+        }
+
+        count
+    }
+}
@@ -4,7 +4,7 @@ pub mod generic;
 // This is like generic, but written explicitly
 // because generic SIMD requires nightly.
 #[cfg(all(
-    feature = "runtime-dispatch-simd",
+    any(feature = "runtime-dispatch-simd", target_feature = "sse2"),
     any(target_arch = "x86", target_arch = "x86_64"),
     not(feature = "generic-simd")
 ))]
@@ -12,8 +12,16 @@ pub mod x86_sse2;
 
 // Modern x86 machines can do lots of fun stuff;
 // this is where the *real* optimizations go.
-// Runtime feature detection is not available with no_std.
-#[cfg(all(feature = "runtime-dispatch-simd", target_arch = "x86_64"))]
+// With no_std, this is only used if AVX2 is enabled at compile time or
+// detected with `cpuid-dispatch-simd`.
+#[cfg(all(
+    any(
+        feature = "runtime-dispatch-simd",
+        feature = "cpuid-dispatch-simd",
+        target_feature = "avx2"
+    ),
+    target_arch = "x86_64"
+))]
 pub mod x86_avx2;
 
 /// Modern ARM machines are also quite capable thanks to NEON
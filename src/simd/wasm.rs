@@ -1,5 +1,9 @@
 use core::arch::wasm32::*;
 
+use crate::bits::nth_set_bit;
+use crate::substring::Matches;
+use crate::utf8::{UTF8_HIGH, UTF8_PREV_HIGH, UTF8_PREV_LOW};
+
 const MASK: [u8; 32] = [
     0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 255, 255, 255, 255, 255, 255, 255, 255, 255,
     255, 255, 255, 255, 255, 255, 255,
@@ -27,7 +31,7 @@ unsafe fn u8x16x4_from_offset(slice: &[u8], offset: usize) -> (v128, v128, v128,
         slice.len()
     );
     (
-        v128_load(slice.as_ptr().add(offset + 0) as *const _),
+        v128_load(slice.as_ptr().add(offset) as *const _),
         v128_load(slice.as_ptr().add(offset + 16) as *const _),
         v128_load(slice.as_ptr().add(offset + 32) as *const _),
         v128_load(slice.as_ptr().add(offset + 48) as *const _),
@@ -206,3 +210,916 @@ pub unsafe fn chunk_num_chars(utf8_chars: &[u8]) -> usize {
 
     count
 }
+
+// The error bits of each byte of `u8s` given the bytes before it, as laid
+// out in `crate::utf8`; any bit set means the input is not valid UTF-8.
+#[target_feature(enable = "simd128")]
+unsafe fn utf8_errors(u8s: v128, previous: v128) -> v128 {
+    let prev1 = u8x16_shuffle::<15, 16, 17, 18, 19, 20, 21, 22, 23, 24, 25, 26, 27, 28, 29, 30>(
+        previous, u8s,
+    );
+    let prev2 = u8x16_shuffle::<14, 15, 16, 17, 18, 19, 20, 21, 22, 23, 24, 25, 26, 27, 28, 29>(
+        previous, u8s,
+    );
+    let prev3 = u8x16_shuffle::<13, 14, 15, 16, 17, 18, 19, 20, 21, 22, 23, 24, 25, 26, 27, 28>(
+        previous, u8s,
+    );
+    let errors = v128_and(
+        v128_and(
+            u8x16_swizzle(u8x16_from_offset(&UTF8_PREV_HIGH, 0), u8x16_shr(prev1, 4)),
+            u8x16_swizzle(
+                u8x16_from_offset(&UTF8_PREV_LOW, 0),
+                v128_and(prev1, u8x16_splat(0x0F)),
+            ),
+        ),
+        u8x16_swizzle(u8x16_from_offset(&UTF8_HIGH, 0), u8x16_shr(u8s, 4)),
+    );
+    // The third and fourth bytes of a sequence must be continuation bytes
+    let must_continue = v128_and(
+        v128_or(
+            u8x16_sub_sat(prev2, u8x16_splat(0xE0 - 0x80)),
+            u8x16_sub_sat(prev3, u8x16_splat(0xF0 - 0x80)),
+        ),
+        u8x16_splat(0x80),
+    );
+    v128_xor(errors, must_continue)
+}
+
+#[target_feature(enable = "simd128")]
+pub unsafe fn chunk_try_num_chars(utf8_chars: &[u8]) -> Option<usize> {
+    assert!(utf8_chars.len() >= 16);
+
+    let mut offset = 0;
+    let mut count = 0;
+
+    let mut previous = u8x16_splat(0);
+    let mut errors = u8x16_splat(0);
+
+    // 4080
+    while utf8_chars.len() >= offset + 16 * 255 {
+        let mut counts = u8x16_splat(0);
+        for _ in 0..255 {
+            let u8s = u8x16_from_offset(utf8_chars, offset);
+            errors = v128_or(errors, utf8_errors(u8s, previous));
+            counts = u8x16_sub(counts, is_leading_utf8_byte(u8s));
+            previous = u8s;
+            offset += 16;
+        }
+        if v128_any_true(errors) {
+            return None;
+        }
+        count += sum(counts);
+    }
+
+    // 16
+    let mut counts = u8x16_splat(0);
+    while utf8_chars.len() >= offset + 16 {
+        let u8s = u8x16_from_offset(utf8_chars, offset);
+        errors = v128_or(errors, utf8_errors(u8s, previous));
+        counts = u8x16_sub(counts, is_leading_utf8_byte(u8s));
+        previous = u8s;
+        offset += 16;
+    }
+    // The rest is padded with zeros, which also catches a sequence that the
+    // end of the input cuts short. Each padding byte counts as a character.
+    let mut rest = [0; 16];
+    rest[..utf8_chars.len() - offset].copy_from_slice(&utf8_chars[offset..]);
+    let u8s = u8x16_from_offset(&rest, 0);
+    errors = v128_or(errors, utf8_errors(u8s, previous));
+    counts = u8x16_sub(counts, is_leading_utf8_byte(u8s));
+    if v128_any_true(errors) {
+        return None;
+    }
+    count += sum(counts);
+
+    Some(count - (offset + 16 - utf8_chars.len()))
+}
+
+#[target_feature(enable = "simd128")]
+unsafe fn u8x16_eq_any(u8s: v128, needles: &[v128]) -> v128 {
+    let mut matches = u8x16_splat(0);
+    for &needle in needles {
+        matches = v128_or(matches, u8x16_eq(u8s, needle));
+    }
+    matches
+}
+
+#[target_feature(enable = "simd128")]
+pub unsafe fn chunk_count_any(haystack: &[u8], needles: &[u8]) -> usize {
+    assert!(haystack.len() >= 16);
+    assert!(needles.len() <= 8);
+
+    let mut splats = [u8x16_splat(0); 8];
+    for (splat, &needle) in splats.iter_mut().zip(needles) {
+        *splat = u8x16_splat(needle);
+    }
+    let needles = &splats[..needles.len()];
+    let mut count = 0;
+    let mut offset = 0;
+
+    while haystack.len() >= offset + 64 * 255 {
+        let (mut count1, mut count2, mut count3, mut count4) = (
+            u8x16_splat(0),
+            u8x16_splat(0),
+            u8x16_splat(0),
+            u8x16_splat(0),
+        );
+        for _ in 0..255 {
+            let (h1, h2, h3, h4) = u8x16x4_from_offset(haystack, offset);
+            count1 = u8x16_sub(count1, u8x16_eq_any(h1, needles));
+            count2 = u8x16_sub(count2, u8x16_eq_any(h2, needles));
+            count3 = u8x16_sub(count3, u8x16_eq_any(h3, needles));
+            count4 = u8x16_sub(count4, u8x16_eq_any(h4, needles));
+            offset += 64;
+        }
+        count += sum4(count1, count2, count3, count4);
+    }
+
+    // 64
+    let (mut count1, mut count2, mut count3, mut count4) = (
+        u8x16_splat(0),
+        u8x16_splat(0),
+        u8x16_splat(0),
+        u8x16_splat(0),
+    );
+    for _ in 0..(haystack.len() - offset) / 64 {
+        let (h1, h2, h3, h4) = u8x16x4_from_offset(haystack, offset);
+        count1 = u8x16_sub(count1, u8x16_eq_any(h1, needles));
+        count2 = u8x16_sub(count2, u8x16_eq_any(h2, needles));
+        count3 = u8x16_sub(count3, u8x16_eq_any(h3, needles));
+        count4 = u8x16_sub(count4, u8x16_eq_any(h4, needles));
+        offset += 64;
+    }
+    count += sum4(count1, count2, count3, count4);
+
+    let mut counts = u8x16_splat(0);
+    // 16
+    for i in 0..(haystack.len() - offset) / 16 {
+        counts = u8x16_sub(
+            counts,
+            u8x16_eq_any(u8x16_from_offset(haystack, offset + i * 16), needles),
+        );
+    }
+    if haystack.len() % 16 != 0 {
+        counts = u8x16_sub(
+            counts,
+            v128_and(
+                u8x16_eq_any(u8x16_from_offset(haystack, haystack.len() - 16), needles),
+                u8x16_from_offset(&MASK, haystack.len() % 16),
+            ),
+        );
+    }
+    count + sum(counts)
+}
+
+const BITS: [u8; 16] = [1, 2, 4, 8, 16, 32, 64, 128, 1, 2, 4, 8, 16, 32, 64, 128];
+
+#[target_feature(enable = "simd128")]
+unsafe fn is_in_set(u8s: v128, lower: v128, upper: v128) -> v128 {
+    // Out-of-range indices swizzle in zero, so each table only yields a
+    // row for its half of the byte range.
+    let index = v128_and(u8s, u8x16_splat(0x8F));
+    let rows = v128_or(
+        u8x16_swizzle(lower, index),
+        u8x16_swizzle(upper, v128_xor(index, u8x16_splat(0x80))),
+    );
+    let bits = u8x16_swizzle(u8x16_from_offset(&BITS, 0), u8x16_shr(u8s, 4));
+    u8x16_ne(v128_and(rows, bits), u8x16_splat(0))
+}
+
+#[target_feature(enable = "simd128")]
+pub unsafe fn chunk_count_set(haystack: &[u8], table: &[u8; 32]) -> usize {
+    assert!(haystack.len() >= 16);
+
+    let lower = u8x16_from_offset(table, 0);
+    let upper = u8x16_from_offset(table, 16);
+    let mut count = 0;
+    let mut offset = 0;
+
+    while haystack.len() >= offset + 64 * 255 {
+        let (mut count1, mut count2, mut count3, mut count4) = (
+            u8x16_splat(0),
+            u8x16_splat(0),
+            u8x16_splat(0),
+            u8x16_splat(0),
+        );
+        for _ in 0..255 {
+            let (h1, h2, h3, h4) = u8x16x4_from_offset(haystack, offset);
+            count1 = u8x16_sub(count1, is_in_set(h1, lower, upper));
+            count2 = u8x16_sub(count2, is_in_set(h2, lower, upper));
+            count3 = u8x16_sub(count3, is_in_set(h3, lower, upper));
+            count4 = u8x16_sub(count4, is_in_set(h4, lower, upper));
+            offset += 64;
+        }
+        count += sum4(count1, count2, count3, count4);
+    }
+
+    // 64
+    let (mut count1, mut count2, mut count3, mut count4) = (
+        u8x16_splat(0),
+        u8x16_splat(0),
+        u8x16_splat(0),
+        u8x16_splat(0),
+    );
+    for _ in 0..(haystack.len() - offset) / 64 {
+        let (h1, h2, h3, h4) = u8x16x4_from_offset(haystack, offset);
+        count1 = u8x16_sub(count1, is_in_set(h1, lower, upper));
+        count2 = u8x16_sub(count2, is_in_set(h2, lower, upper));
+        count3 = u8x16_sub(count3, is_in_set(h3, lower, upper));
+        count4 = u8x16_sub(count4, is_in_set(h4, lower, upper));
+        offset += 64;
+    }
+    count += sum4(count1, count2, count3, count4);
+
+    let mut counts = u8x16_splat(0);
+    // 16
+    for i in 0..(haystack.len() - offset) / 16 {
+        counts = u8x16_sub(
+            counts,
+            is_in_set(u8x16_from_offset(haystack, offset + i * 16), lower, upper),
+        );
+    }
+    if haystack.len() % 16 != 0 {
+        counts = u8x16_sub(
+            counts,
+            v128_and(
+                is_in_set(u8x16_from_offset(haystack, haystack.len() - 16), lower, upper),
+                u8x16_from_offset(&MASK, haystack.len() % 16),
+            ),
+        );
+    }
+    count + sum(counts)
+}
+
+#[target_feature(enable = "simd128")]
+unsafe fn is_in_range(u8s: v128, lows: v128, spans: v128) -> v128 {
+    // `lo <= x <= hi` iff `x - lo <= hi - lo`, i.e. iff the saturating
+    // difference to the span is zero.
+    u8x16_eq(u8x16_sub_sat(u8x16_sub(u8s, lows), spans), u8x16_splat(0))
+}
+
+#[target_feature(enable = "simd128")]
+pub unsafe fn chunk_count_range(haystack: &[u8], lo: u8, hi: u8) -> usize {
+    assert!(haystack.len() >= 16);
+    assert!(lo <= hi);
+
+    let lows = u8x16_splat(lo);
+    let spans = u8x16_splat(hi - lo);
+    let mut count = 0;
+    let mut offset = 0;
+
+    while haystack.len() >= offset + 64 * 255 {
+        let (mut count1, mut count2, mut count3, mut count4) = (
+            u8x16_splat(0),
+            u8x16_splat(0),
+            u8x16_splat(0),
+            u8x16_splat(0),
+        );
+        for _ in 0..255 {
+            let (h1, h2, h3, h4) = u8x16x4_from_offset(haystack, offset);
+            count1 = u8x16_sub(count1, is_in_range(h1, lows, spans));
+            count2 = u8x16_sub(count2, is_in_range(h2, lows, spans));
+            count3 = u8x16_sub(count3, is_in_range(h3, lows, spans));
+            count4 = u8x16_sub(count4, is_in_range(h4, lows, spans));
+            offset += 64;
+        }
+        count += sum4(count1, count2, count3, count4);
+    }
+
+    // 64
+    let (mut count1, mut count2, mut count3, mut count4) = (
+        u8x16_splat(0),
+        u8x16_splat(0),
+        u8x16_splat(0),
+        u8x16_splat(0),
+    );
+    for _ in 0..(haystack.len() - offset) / 64 {
+        let (h1, h2, h3, h4) = u8x16x4_from_offset(haystack, offset);
+        count1 = u8x16_sub(count1, is_in_range(h1, lows, spans));
+        count2 = u8x16_sub(count2, is_in_range(h2, lows, spans));
+        count3 = u8x16_sub(count3, is_in_range(h3, lows, spans));
+        count4 = u8x16_sub(count4, is_in_range(h4, lows, spans));
+        offset += 64;
+    }
+    count += sum4(count1, count2, count3, count4);
+
+    let mut counts = u8x16_splat(0);
+    // 16
+    for i in 0..(haystack.len() - offset) / 16 {
+        counts = u8x16_sub(
+            counts,
+            is_in_range(u8x16_from_offset(haystack, offset + i * 16), lows, spans),
+        );
+    }
+    if haystack.len() % 16 != 0 {
+        counts = u8x16_sub(
+            counts,
+            v128_and(
+                is_in_range(u8x16_from_offset(haystack, haystack.len() - 16), lows, spans),
+                u8x16_from_offset(&MASK, haystack.len() % 16),
+            ),
+        );
+    }
+    count + sum(counts)
+}
+
+#[target_feature(enable = "simd128")]
+unsafe fn count_each<const N: usize>(counts: &mut [v128; N], u8s: v128, needles: &[v128; N]) {
+    for (counts, &needles) in counts.iter_mut().zip(needles.iter()) {
+        *counts = u8x16_sub(*counts, u8x16_eq(u8s, needles));
+    }
+}
+
+#[target_feature(enable = "simd128")]
+pub unsafe fn chunk_count_many<const N: usize>(haystack: &[u8], needles: [u8; N]) -> [usize; N] {
+    assert!(haystack.len() >= 16);
+
+    let needles = needles.map(|needle| u8x16_splat(needle));
+    let mut count = [0; N];
+    let mut offset = 0;
+
+    while haystack.len() >= offset + 64 * 255 {
+        let (mut count1, mut count2, mut count3, mut count4) = (
+            [u8x16_splat(0); N],
+            [u8x16_splat(0); N],
+            [u8x16_splat(0); N],
+            [u8x16_splat(0); N],
+        );
+        for _ in 0..255 {
+            let (h1, h2, h3, h4) = u8x16x4_from_offset(haystack, offset);
+            count_each(&mut count1, h1, &needles);
+            count_each(&mut count2, h2, &needles);
+            count_each(&mut count3, h3, &needles);
+            count_each(&mut count4, h4, &needles);
+            offset += 64;
+        }
+        for i in 0..N {
+            count[i] += sum4(count1[i], count2[i], count3[i], count4[i]);
+        }
+    }
+
+    // 64
+    let (mut count1, mut count2, mut count3, mut count4) = (
+        [u8x16_splat(0); N],
+        [u8x16_splat(0); N],
+        [u8x16_splat(0); N],
+        [u8x16_splat(0); N],
+    );
+    for _ in 0..(haystack.len() - offset) / 64 {
+        let (h1, h2, h3, h4) = u8x16x4_from_offset(haystack, offset);
+        count_each(&mut count1, h1, &needles);
+        count_each(&mut count2, h2, &needles);
+        count_each(&mut count3, h3, &needles);
+        count_each(&mut count4, h4, &needles);
+        offset += 64;
+    }
+    for i in 0..N {
+        count[i] += sum4(count1[i], count2[i], count3[i], count4[i]);
+    }
+
+    let mut counts = [u8x16_splat(0); N];
+    // 16
+    for i in 0..(haystack.len() - offset) / 16 {
+        count_each(&mut counts, u8x16_from_offset(haystack, offset + i * 16), &needles);
+    }
+    if haystack.len() % 16 != 0 {
+        let mask = u8x16_from_offset(&MASK, haystack.len() % 16);
+        let u8s = u8x16_from_offset(haystack, haystack.len() - 16);
+        for (counts, &needles) in counts.iter_mut().zip(needles.iter()) {
+            *counts = u8x16_sub(*counts, v128_and(u8x16_eq(u8s, needles), mask));
+        }
+    }
+    for (count, &counts) in count.iter_mut().zip(counts.iter()) {
+        *count += sum(counts);
+    }
+
+    count
+}
+
+#[target_feature(enable = "simd128")]
+pub unsafe fn chunk_text_stats(utf8_chars: &[u8]) -> (usize, usize) {
+    assert!(utf8_chars.len() >= 16);
+
+    let needles = u8x16_splat(b'\n');
+    let mut offset = 0;
+    let mut newlines = 0;
+    let mut chars = 0;
+
+    // 16320
+    while utf8_chars.len() >= offset + 64 * 255 {
+        let (mut newline1, mut newline2, mut newline3, mut newline4) = (
+            u8x16_splat(0),
+            u8x16_splat(0),
+            u8x16_splat(0),
+            u8x16_splat(0),
+        );
+        let (mut char1, mut char2, mut char3, mut char4) = (
+            u8x16_splat(0),
+            u8x16_splat(0),
+            u8x16_splat(0),
+            u8x16_splat(0),
+        );
+        for _ in 0..255 {
+            let (h1, h2, h3, h4) = u8x16x4_from_offset(utf8_chars, offset);
+            newline1 = u8x16_sub(newline1, u8x16_eq(h1, needles));
+            newline2 = u8x16_sub(newline2, u8x16_eq(h2, needles));
+            newline3 = u8x16_sub(newline3, u8x16_eq(h3, needles));
+            newline4 = u8x16_sub(newline4, u8x16_eq(h4, needles));
+            char1 = u8x16_sub(char1, is_leading_utf8_byte(h1));
+            char2 = u8x16_sub(char2, is_leading_utf8_byte(h2));
+            char3 = u8x16_sub(char3, is_leading_utf8_byte(h3));
+            char4 = u8x16_sub(char4, is_leading_utf8_byte(h4));
+            offset += 64;
+        }
+        newlines += sum4(newline1, newline2, newline3, newline4);
+        chars += sum4(char1, char2, char3, char4);
+    }
+
+    // 64
+    let (mut newline1, mut newline2, mut newline3, mut newline4) = (
+        u8x16_splat(0),
+        u8x16_splat(0),
+        u8x16_splat(0),
+        u8x16_splat(0),
+    );
+    let (mut char1, mut char2, mut char3, mut char4) = (
+        u8x16_splat(0),
+        u8x16_splat(0),
+        u8x16_splat(0),
+        u8x16_splat(0),
+    );
+    for _ in 0..(utf8_chars.len() - offset) / 64 {
+        let (h1, h2, h3, h4) = u8x16x4_from_offset(utf8_chars, offset);
+        newline1 = u8x16_sub(newline1, u8x16_eq(h1, needles));
+        newline2 = u8x16_sub(newline2, u8x16_eq(h2, needles));
+        newline3 = u8x16_sub(newline3, u8x16_eq(h3, needles));
+        newline4 = u8x16_sub(newline4, u8x16_eq(h4, needles));
+        char1 = u8x16_sub(char1, is_leading_utf8_byte(h1));
+        char2 = u8x16_sub(char2, is_leading_utf8_byte(h2));
+        char3 = u8x16_sub(char3, is_leading_utf8_byte(h3));
+        char4 = u8x16_sub(char4, is_leading_utf8_byte(h4));
+        offset += 64;
+    }
+    newlines += sum4(newline1, newline2, newline3, newline4);
+    chars += sum4(char1, char2, char3, char4);
+
+    // 16
+    let mut newline_counts = u8x16_splat(0);
+    let mut char_counts = u8x16_splat(0);
+    for i in 0..(utf8_chars.len() - offset) / 16 {
+        let u8s = u8x16_from_offset(utf8_chars, offset + i * 16);
+        newline_counts = u8x16_sub(newline_counts, u8x16_eq(u8s, needles));
+        char_counts = u8x16_sub(char_counts, is_leading_utf8_byte(u8s));
+    }
+    if utf8_chars.len() % 16 != 0 {
+        let u8s = u8x16_from_offset(utf8_chars, utf8_chars.len() - 16);
+        let mask = u8x16_from_offset(&MASK, utf8_chars.len() % 16);
+        newline_counts = u8x16_sub(newline_counts, v128_and(u8x16_eq(u8s, needles), mask));
+        char_counts = u8x16_sub(char_counts, v128_and(is_leading_utf8_byte(u8s), mask));
+    }
+    newlines += sum(newline_counts);
+    chars += sum(char_counts);
+
+    (newlines, chars)
+}
+
+#[target_feature(enable = "simd128")]
+pub unsafe fn chunk_count_substring(haystack: &[u8], needle: &[u8], overlapping: bool) -> usize {
+    assert!(needle.len() >= 2);
+    assert!(haystack.len() >= needle.len() - 1 + 16);
+
+    let last = needle.len() - 1;
+    let mut offset = 0;
+    let mut matches = Matches::new(haystack, needle, overlapping);
+
+    let firsts = u8x16_splat(needle[0]);
+    let lasts = u8x16_splat(needle[last]);
+
+    // 16
+    while haystack.len() >= offset + last + 16 {
+        let candidates = v128_and(
+            u8x16_eq(u8x16_from_offset(haystack, offset), firsts),
+            u8x16_eq(u8x16_from_offset(haystack, offset + last), lasts),
+        );
+        let mut bits = u8x16_bitmask(candidates);
+        while bits != 0 {
+            matches.check(offset + bits.trailing_zeros() as usize);
+            bits &= bits - 1;
+        }
+        offset += 16;
+    }
+
+    matches.finish(offset)
+}
+
+#[target_feature(enable = "simd128")]
+unsafe fn match_bits(u8s: v128, needles: v128) -> u64 {
+    u8x16_bitmask(u8x16_eq(u8s, needles)) as u64
+}
+
+#[target_feature(enable = "simd128")]
+pub unsafe fn chunk_nth(haystack: &[u8], needle: u8, mut n: usize) -> Option<usize> {
+    assert!(haystack.len() >= 16);
+
+    let needles = u8x16_splat(needle);
+    let mut offset = 0;
+
+    // 16320: skip whole blocks that end before the nth occurrence
+    while haystack.len() >= offset + 64 * 255 {
+        let (mut count1, mut count2, mut count3, mut count4) = (
+            u8x16_splat(0),
+            u8x16_splat(0),
+            u8x16_splat(0),
+            u8x16_splat(0),
+        );
+        for i in 0..255 {
+            let (h1, h2, h3, h4) = u8x16x4_from_offset(haystack, offset + i * 64);
+            count1 = u8x16_sub(count1, u8x16_eq(h1, needles));
+            count2 = u8x16_sub(count2, u8x16_eq(h2, needles));
+            count3 = u8x16_sub(count3, u8x16_eq(h3, needles));
+            count4 = u8x16_sub(count4, u8x16_eq(h4, needles));
+        }
+        let count = sum4(count1, count2, count3, count4);
+        if count > n {
+            break;
+        }
+        n -= count;
+        offset += 64 * 255;
+    }
+
+    // 16
+    while haystack.len() >= offset + 16 {
+        let bits = match_bits(u8x16_from_offset(haystack, offset), needles);
+        let count = bits.count_ones() as usize;
+        if count > n {
+            return Some(offset + nth_set_bit(bits, n));
+        }
+        n -= count;
+        offset += 16;
+    }
+    if haystack.len() != offset {
+        let bits = match_bits(u8x16_from_offset(haystack, haystack.len() - 16), needles)
+            >> (offset + 16 - haystack.len());
+        if bits.count_ones() as usize > n {
+            return Some(offset + nth_set_bit(bits, n));
+        }
+    }
+
+    None
+}
+
+#[target_feature(enable = "simd128")]
+pub unsafe fn chunk_rcount_until(haystack: &[u8], needle: u8, limit: usize) -> (usize, usize) {
+    assert!(haystack.len() >= 16 && limit > 0);
+
+    let mut count = 0;
+    let mut end = haystack.len();
+
+    let needles = u8x16_splat(needle);
+
+    // 16320: skip whole blocks that start after the last wanted occurrence
+    while end >= 64 * 255 {
+        let (mut count1, mut count2, mut count3, mut count4) = (
+            u8x16_splat(0),
+            u8x16_splat(0),
+            u8x16_splat(0),
+            u8x16_splat(0),
+        );
+        for i in 1..=255 {
+            let (h1, h2, h3, h4) = u8x16x4_from_offset(haystack, end - i * 64);
+            count1 = u8x16_sub(count1, u8x16_eq(h1, needles));
+            count2 = u8x16_sub(count2, u8x16_eq(h2, needles));
+            count3 = u8x16_sub(count3, u8x16_eq(h3, needles));
+            count4 = u8x16_sub(count4, u8x16_eq(h4, needles));
+        }
+        let block = sum4(count1, count2, count3, count4);
+        if count + block >= limit {
+            break;
+        }
+        count += block;
+        end -= 64 * 255;
+    }
+
+    // 16
+    while end >= 16 {
+        let bits = match_bits(u8x16_from_offset(haystack, end - 16), needles);
+        let found = bits.count_ones() as usize;
+        if count + found >= limit {
+            return (limit, end - 16 + nth_set_bit(bits, count + found - limit));
+        }
+        count += found;
+        end -= 16;
+    }
+    if end != 0 {
+        let bits = match_bits(u8x16_from_offset(haystack, 0), needles) & ((1 << (end)) - 1);
+        let found = bits.count_ones() as usize;
+        if count + found >= limit {
+            return (limit, nth_set_bit(bits, count + found - limit));
+        }
+        count += found;
+    }
+
+    (count, 0)
+}
+
+#[cfg(feature = "runtime-dispatch-simd")]
+#[target_feature(enable = "simd128")]
+pub unsafe fn chunk_positions<F: FnMut(usize)>(haystack: &[u8], needle: u8, mut f: F) {
+    assert!(haystack.len() >= 16);
+
+    let mut offset = 0;
+
+    let needles = u8x16_splat(needle);
+
+    // 16
+    while haystack.len() >= offset + 16 {
+        let mut bits = match_bits(u8x16_from_offset(haystack, offset), needles);
+        while bits != 0 {
+            f(offset + bits.trailing_zeros() as usize);
+            bits &= bits - 1;
+        }
+        offset += 16;
+    }
+    if haystack.len() != offset {
+        let mut bits = match_bits(u8x16_from_offset(haystack, haystack.len() - 16), needles)
+            >> (offset + 16 - haystack.len());
+        while bits != 0 {
+            f(offset + bits.trailing_zeros() as usize);
+            bits &= bits - 1;
+        }
+    }
+}
+
+#[target_feature(enable = "simd128")]
+unsafe fn is_four_byte_leader(u8s: v128) -> v128 {
+    u8x16_ge(u8s, u8x16_splat(0b1111_0000))
+}
+
+#[target_feature(enable = "simd128")]
+pub unsafe fn chunk_utf16_len(utf8_chars: &[u8]) -> usize {
+    assert!(utf8_chars.len() >= 16);
+
+    let mut offset = 0;
+    let mut fours = 0;
+    let mut chars = 0;
+
+    // 16320
+    while utf8_chars.len() >= offset + 64 * 255 {
+        let (mut four1, mut four2, mut four3, mut four4) = (
+            u8x16_splat(0),
+            u8x16_splat(0),
+            u8x16_splat(0),
+            u8x16_splat(0),
+        );
+        let (mut char1, mut char2, mut char3, mut char4) = (
+            u8x16_splat(0),
+            u8x16_splat(0),
+            u8x16_splat(0),
+            u8x16_splat(0),
+        );
+        for _ in 0..255 {
+            let (h1, h2, h3, h4) = u8x16x4_from_offset(utf8_chars, offset);
+            four1 = u8x16_sub(four1, is_four_byte_leader(h1));
+            four2 = u8x16_sub(four2, is_four_byte_leader(h2));
+            four3 = u8x16_sub(four3, is_four_byte_leader(h3));
+            four4 = u8x16_sub(four4, is_four_byte_leader(h4));
+            char1 = u8x16_sub(char1, is_leading_utf8_byte(h1));
+            char2 = u8x16_sub(char2, is_leading_utf8_byte(h2));
+            char3 = u8x16_sub(char3, is_leading_utf8_byte(h3));
+            char4 = u8x16_sub(char4, is_leading_utf8_byte(h4));
+            offset += 64;
+        }
+        fours += sum4(four1, four2, four3, four4);
+        chars += sum4(char1, char2, char3, char4);
+    }
+
+    // 64
+    let (mut four1, mut four2, mut four3, mut four4) = (
+        u8x16_splat(0),
+        u8x16_splat(0),
+        u8x16_splat(0),
+        u8x16_splat(0),
+    );
+    let (mut char1, mut char2, mut char3, mut char4) = (
+        u8x16_splat(0),
+        u8x16_splat(0),
+        u8x16_splat(0),
+        u8x16_splat(0),
+    );
+    for _ in 0..(utf8_chars.len() - offset) / 64 {
+        let (h1, h2, h3, h4) = u8x16x4_from_offset(utf8_chars, offset);
+        four1 = u8x16_sub(four1, is_four_byte_leader(h1));
+        four2 = u8x16_sub(four2, is_four_byte_leader(h2));
+        four3 = u8x16_sub(four3, is_four_byte_leader(h3));
+        four4 = u8x16_sub(four4, is_four_byte_leader(h4));
+        char1 = u8x16_sub(char1, is_leading_utf8_byte(h1));
+        char2 = u8x16_sub(char2, is_leading_utf8_byte(h2));
+        char3 = u8x16_sub(char3, is_leading_utf8_byte(h3));
+        char4 = u8x16_sub(char4, is_leading_utf8_byte(h4));
+        offset += 64;
+    }
+    fours += sum4(four1, four2, four3, four4);
+    chars += sum4(char1, char2, char3, char4);
+
+    // 16
+    let mut four_counts = u8x16_splat(0);
+    let mut char_counts = u8x16_splat(0);
+    for i in 0..(utf8_chars.len() - offset) / 16 {
+        let u8s = u8x16_from_offset(utf8_chars, offset + i * 16);
+        four_counts = u8x16_sub(four_counts, is_four_byte_leader(u8s));
+        char_counts = u8x16_sub(char_counts, is_leading_utf8_byte(u8s));
+    }
+    if utf8_chars.len() % 16 != 0 {
+        let u8s = u8x16_from_offset(utf8_chars, utf8_chars.len() - 16);
+        let mask = u8x16_from_offset(&MASK, utf8_chars.len() % 16);
+        four_counts = u8x16_sub(four_counts, v128_and(is_four_byte_leader(u8s), mask));
+        char_counts = u8x16_sub(char_counts, v128_and(is_leading_utf8_byte(u8s), mask));
+    }
+    fours += sum(four_counts);
+    chars += sum(char_counts);
+
+    chars + fours
+}
+
+#[target_feature(enable = "simd128")]
+unsafe fn u16x8_from_offset(slice: &[u16], offset: usize) -> v128 {
+    debug_assert!(
+        offset + 8 <= slice.len(),
+        "{} + 8 ≥ {}",
+        offset,
+        slice.len()
+    );
+    v128_load(slice.as_ptr().add(offset) as *const _)
+}
+
+#[target_feature(enable = "simd128")]
+unsafe fn sum_u16(u16s: v128) -> usize {
+    let u32s = u32x4_extadd_pairwise_u16x8(u16s);
+    let (u1, u2, u3, u4) = (
+        u32x4_extract_lane::<0>(u32s),
+        u32x4_extract_lane::<1>(u32s),
+        u32x4_extract_lane::<2>(u32s),
+        u32x4_extract_lane::<3>(u32s),
+    );
+    ((u1 + u2) + (u3 + u4)) as usize
+}
+
+// The number of bytes short of three that a UTF-16 code unit takes in
+// UTF-8, as a negative count; a surrogate pair takes four bytes, two each.
+#[target_feature(enable = "simd128")]
+unsafe fn utf8_deficit(u16s: v128) -> v128 {
+    let below_0x80 = u16x8_lt(u16s, u16x8_splat(0x80));
+    let below_0x800 = u16x8_lt(u16s, u16x8_splat(0x800));
+    let surrogate = u16x8_eq(v128_and(u16s, u16x8_splat(0xF800)), u16x8_splat(0xD800));
+    u16x8_add(u16x8_add(below_0x80, below_0x800), surrogate)
+}
+
+#[target_feature(enable = "simd128")]
+pub unsafe fn chunk_utf8_len_from_utf16(utf16: &[u16]) -> usize {
+    assert!(utf16.len() >= 8);
+
+    let mut offset = 0;
+    let mut deficit = 0;
+
+    // 65536
+    while utf16.len() >= offset + 8 * 8192 {
+        let mut counts = u16x8_splat(0);
+        for _ in 0..8192 {
+            counts = u16x8_sub(counts, utf8_deficit(u16x8_from_offset(utf16, offset)));
+            offset += 8;
+        }
+        deficit += sum_u16(counts);
+    }
+
+    // 8
+    let mut counts = u16x8_splat(0);
+    for i in 0..(utf16.len() - offset) / 8 {
+        counts = u16x8_sub(counts, utf8_deficit(u16x8_from_offset(utf16, offset + i * 8)));
+    }
+    if utf16.len() % 8 != 0 {
+        counts = u16x8_sub(
+            counts,
+            v128_and(
+                utf8_deficit(u16x8_from_offset(utf16, utf16.len() - 8)),
+                u8x16_from_offset(&MASK, 2 * (utf16.len() % 8)),
+            ),
+        );
+    }
+    deficit += sum_u16(counts);
+
+    3 * utf16.len() - deficit
+}
+
+#[target_feature(enable = "simd128")]
+unsafe fn is_low_surrogate(u16s: v128) -> v128 {
+    u16x8_eq(v128_and(u16s, u16x8_splat(0xFC00)), u16x8_splat(0xDC00))
+}
+
+#[target_feature(enable = "simd128")]
+pub unsafe fn chunk_num_chars_utf16(utf16: &[u16]) -> usize {
+    assert!(utf16.len() >= 8);
+
+    let mut offset = 0;
+    let mut low_surrogates = 0;
+
+    // 65536
+    while utf16.len() >= offset + 8 * 8192 {
+        let mut counts = u16x8_splat(0);
+        for _ in 0..8192 {
+            counts = u16x8_sub(counts, is_low_surrogate(u16x8_from_offset(utf16, offset)));
+            offset += 8;
+        }
+        low_surrogates += sum_u16(counts);
+    }
+
+    // 8
+    let mut counts = u16x8_splat(0);
+    for i in 0..(utf16.len() - offset) / 8 {
+        counts = u16x8_sub(counts, is_low_surrogate(u16x8_from_offset(utf16, offset + i * 8)));
+    }
+    if utf16.len() % 8 != 0 {
+        counts = u16x8_sub(
+            counts,
+            v128_and(
+                is_low_surrogate(u16x8_from_offset(utf16, utf16.len() - 8)),
+                u8x16_from_offset(&MASK, 2 * (utf16.len() % 8)),
+            ),
+        );
+    }
+    low_surrogates += sum_u16(counts);
+
+    utf16.len() - low_surrogates
+}
+
+#[target_feature(enable = "simd128")]
+unsafe fn count_below_each<const N: usize>(counts: &mut [v128; N], u8s: v128, bounds: &[v128; N]) {
+    for (counts, &bounds) in counts.iter_mut().zip(bounds.iter()) {
+        *counts = u8x16_sub(*counts, u8x16_lt(u8s, bounds));
+    }
+}
+
+#[target_feature(enable = "simd128")]
+pub unsafe fn chunk_count_below<const N: usize>(haystack: &[u8], bounds: [u8; N]) -> [usize; N] {
+    assert!(haystack.len() >= 16);
+
+    let bounds = bounds.map(|bound| u8x16_splat(bound));
+    let mut count = [0; N];
+    let mut offset = 0;
+
+    while haystack.len() >= offset + 64 * 255 {
+        let (mut count1, mut count2, mut count3, mut count4) = (
+            [u8x16_splat(0); N],
+            [u8x16_splat(0); N],
+            [u8x16_splat(0); N],
+            [u8x16_splat(0); N],
+        );
+        for _ in 0..255 {
+            let (h1, h2, h3, h4) = u8x16x4_from_offset(haystack, offset);
+            count_below_each(&mut count1, h1, &bounds);
+            count_below_each(&mut count2, h2, &bounds);
+            count_below_each(&mut count3, h3, &bounds);
+            count_below_each(&mut count4, h4, &bounds);
+            offset += 64;
+        }
+        for i in 0..N {
+            count[i] += sum4(count1[i], count2[i], count3[i], count4[i]);
+        }
+    }
+
+    // 64
+    let (mut count1, mut count2, mut count3, mut count4) = (
+        [u8x16_splat(0); N],
+        [u8x16_splat(0); N],
+        [u8x16_splat(0); N],
+        [u8x16_splat(0); N],
+    );
+    for _ in 0..(haystack.len() - offset) / 64 {
+        let (h1, h2, h3, h4) = u8x16x4_from_offset(haystack, offset);
+        count_below_each(&mut count1, h1, &bounds);
+        count_below_each(&mut count2, h2, &bounds);
+        count_below_each(&mut count3, h3, &bounds);
+        count_below_each(&mut count4, h4, &bounds);
+        offset += 64;
+    }
+    for i in 0..N {
+        count[i] += sum4(count1[i], count2[i], count3[i], count4[i]);
+    }
+
+    let mut counts = [u8x16_splat(0); N];
+    // 16
+    for i in 0..(haystack.len() - offset) / 16 {
+        count_below_each(&mut counts, u8x16_from_offset(haystack, offset + i * 16), &bounds);
+    }
+    if haystack.len() % 16 != 0 {
+        let mask = u8x16_from_offset(&MASK, haystack.len() % 16);
+        let u8s = u8x16_from_offset(haystack, haystack.len() - 16);
+        for (counts, &bounds) in counts.iter_mut().zip(bounds.iter()) {
+            *counts = u8x16_sub(*counts, v128_and(u8x16_lt(u8s, bounds), mask));
+        }
+    }
+    for (count, &counts) in count.iter_mut().zip(counts.iter()) {
+        *count += sum(counts);
+    }
+
+    count
+}
@@ -1,6 +1,19 @@
-use std::arch::x86_64::{
-    __m256i, _mm256_and_si256, _mm256_cmpeq_epi8, _mm256_extract_epi64, _mm256_loadu_si256,
-    _mm256_sad_epu8, _mm256_set1_epi8, _mm256_setzero_si256, _mm256_sub_epi8, _mm256_xor_si256,
+use crate::bits::nth_set_bit;
+use crate::substring::Matches;
+use crate::utf8::{UTF8_HIGH, UTF8_PREV_HIGH, UTF8_PREV_LOW};
+
+#[cfg(not(feature = "runtime-dispatch-simd"))]
+use core::arch;
+#[cfg(feature = "runtime-dispatch-simd")]
+use std::arch;
+
+use arch::x86_64::{
+    __m256i, _mm256_add_epi16, _mm256_alignr_epi8, _mm256_and_si256, _mm256_broadcastsi128_si256,
+    _mm256_cmpeq_epi16, _mm256_cmpeq_epi8, _mm256_extract_epi64, _mm256_loadu_si256,
+    _mm256_max_epu8, _mm256_min_epu8, _mm256_movemask_epi8, _mm256_or_si256,
+    _mm256_permute2x128_si256, _mm256_sad_epu8, _mm256_set1_epi16, _mm256_set1_epi8,
+    _mm256_setzero_si256, _mm256_shuffle_epi8, _mm256_srli_epi16, _mm256_sub_epi16,
+    _mm256_sub_epi8, _mm256_subs_epu8, _mm256_testz_si256, _mm256_xor_si256, _mm_loadu_si128,
 };
 
 #[target_feature(enable = "avx2")]
@@ -153,3 +166,860 @@ pub unsafe fn chunk_num_chars(utf8_chars: &[u8]) -> usize {
 
     count
 }
+
+// The error bits of each byte of `u8s` given the bytes before it, as laid
+// out in `crate::utf8`; any bit set means the input is not valid UTF-8.
+#[target_feature(enable = "avx2")]
+unsafe fn utf8_errors(u8s: __m256i, previous: __m256i) -> __m256i {
+    let low_nibbles = _mm256_set1_epu8(0x0F);
+    // `alignr` shifts within each 128-bit lane, so line up the high lane of
+    // `previous` and the low lane of `u8s` to shift in from
+    let shifted = _mm256_permute2x128_si256(previous, u8s, 0x21);
+    let prev1 = _mm256_alignr_epi8(u8s, shifted, 15);
+    let prev2 = _mm256_alignr_epi8(u8s, shifted, 14);
+    let prev3 = _mm256_alignr_epi8(u8s, shifted, 13);
+    let errors = _mm256_and_si256(
+        _mm256_and_si256(
+            _mm256_shuffle_epi8(
+                mm256_broadcast_from_offset(&UTF8_PREV_HIGH, 0),
+                _mm256_and_si256(_mm256_srli_epi16(prev1, 4), low_nibbles),
+            ),
+            _mm256_shuffle_epi8(
+                mm256_broadcast_from_offset(&UTF8_PREV_LOW, 0),
+                _mm256_and_si256(prev1, low_nibbles),
+            ),
+        ),
+        _mm256_shuffle_epi8(
+            mm256_broadcast_from_offset(&UTF8_HIGH, 0),
+            _mm256_and_si256(_mm256_srli_epi16(u8s, 4), low_nibbles),
+        ),
+    );
+    // The third and fourth bytes of a sequence must be continuation bytes
+    let must_continue = _mm256_and_si256(
+        _mm256_or_si256(
+            _mm256_subs_epu8(prev2, _mm256_set1_epu8(0xE0 - 0x80)),
+            _mm256_subs_epu8(prev3, _mm256_set1_epu8(0xF0 - 0x80)),
+        ),
+        _mm256_set1_epu8(0x80),
+    );
+    _mm256_xor_si256(errors, must_continue)
+}
+
+#[target_feature(enable = "avx2")]
+unsafe fn any_set(u8s: __m256i) -> bool {
+    _mm256_testz_si256(u8s, u8s) == 0
+}
+
+#[target_feature(enable = "avx2")]
+pub unsafe fn chunk_try_num_chars(utf8_chars: &[u8]) -> Option<usize> {
+    assert!(utf8_chars.len() >= 32);
+
+    let mut offset = 0;
+    let mut count = 0;
+
+    let mut previous = _mm256_setzero_si256();
+    let mut errors = _mm256_setzero_si256();
+
+    // 8160
+    while utf8_chars.len() >= offset + 32 * 255 {
+        let mut counts = _mm256_setzero_si256();
+        for _ in 0..255 {
+            let u8s = mm256_from_offset(utf8_chars, offset);
+            errors = _mm256_or_si256(errors, utf8_errors(u8s, previous));
+            counts = _mm256_sub_epi8(counts, is_leading_utf8_byte(u8s));
+            previous = u8s;
+            offset += 32;
+        }
+        if any_set(errors) {
+            return None;
+        }
+        count += sum(&counts);
+    }
+
+    // 32
+    let mut counts = _mm256_setzero_si256();
+    while utf8_chars.len() >= offset + 32 {
+        let u8s = mm256_from_offset(utf8_chars, offset);
+        errors = _mm256_or_si256(errors, utf8_errors(u8s, previous));
+        counts = _mm256_sub_epi8(counts, is_leading_utf8_byte(u8s));
+        previous = u8s;
+        offset += 32;
+    }
+    // The rest is padded with zeros, which also catches a sequence that the
+    // end of the input cuts short. Each padding byte counts as a character.
+    let mut rest = [0; 32];
+    rest[..utf8_chars.len() - offset].copy_from_slice(&utf8_chars[offset..]);
+    let u8s = mm256_from_offset(&rest, 0);
+    errors = _mm256_or_si256(errors, utf8_errors(u8s, previous));
+    counts = _mm256_sub_epi8(counts, is_leading_utf8_byte(u8s));
+    if any_set(errors) {
+        return None;
+    }
+    count += sum(&counts);
+
+    Some(count - (offset + 32 - utf8_chars.len()))
+}
+
+#[target_feature(enable = "avx2")]
+unsafe fn mm256_cmpeq_any_epi8(u8s: __m256i, needles: &[__m256i]) -> __m256i {
+    let mut matches = _mm256_setzero_si256();
+    for &needle in needles {
+        matches = _mm256_or_si256(matches, _mm256_cmpeq_epi8(u8s, needle));
+    }
+    matches
+}
+
+#[target_feature(enable = "avx2")]
+pub unsafe fn chunk_count_any(haystack: &[u8], needles: &[u8]) -> usize {
+    assert!(haystack.len() >= 32);
+    assert!(needles.len() <= 8);
+
+    let mut offset = 0;
+    let mut count = 0;
+
+    let mut splats = [_mm256_setzero_si256(); 8];
+    for (splat, &needle) in splats.iter_mut().zip(needles) {
+        *splat = _mm256_set1_epu8(needle);
+    }
+    let needles = &splats[..needles.len()];
+
+    // 8160
+    while haystack.len() >= offset + 32 * 255 {
+        let mut counts = _mm256_setzero_si256();
+        for _ in 0..255 {
+            counts = _mm256_sub_epi8(
+                counts,
+                mm256_cmpeq_any_epi8(mm256_from_offset(haystack, offset), needles),
+            );
+            offset += 32;
+        }
+        count += sum(&counts);
+    }
+
+    // 4096
+    if haystack.len() >= offset + 32 * 128 {
+        let mut counts = _mm256_setzero_si256();
+        for _ in 0..128 {
+            counts = _mm256_sub_epi8(
+                counts,
+                mm256_cmpeq_any_epi8(mm256_from_offset(haystack, offset), needles),
+            );
+            offset += 32;
+        }
+        count += sum(&counts);
+    }
+
+    // 32
+    let mut counts = _mm256_setzero_si256();
+    for i in 0..(haystack.len() - offset) / 32 {
+        counts = _mm256_sub_epi8(
+            counts,
+            mm256_cmpeq_any_epi8(mm256_from_offset(haystack, offset + i * 32), needles),
+        );
+    }
+    if haystack.len() % 32 != 0 {
+        counts = _mm256_sub_epi8(
+            counts,
+            _mm256_and_si256(
+                mm256_cmpeq_any_epi8(mm256_from_offset(haystack, haystack.len() - 32), needles),
+                mm256_from_offset(&MASK, haystack.len() % 32),
+            ),
+        );
+    }
+    count += sum(&counts);
+
+    count
+}
+
+const BITS: [u8; 32] = [
+    1, 2, 4, 8, 16, 32, 64, 128, 1, 2, 4, 8, 16, 32, 64, 128, 1, 2, 4, 8, 16, 32, 64, 128, 1, 2, 4,
+    8, 16, 32, 64, 128,
+];
+
+#[target_feature(enable = "avx2")]
+unsafe fn mm256_broadcast_from_offset(slice: &[u8], offset: usize) -> __m256i {
+    _mm256_broadcastsi128_si256(_mm_loadu_si128(slice.as_ptr().add(offset) as *const _))
+}
+
+#[target_feature(enable = "avx2")]
+unsafe fn is_in_set(u8s: __m256i, lower: __m256i, upper: __m256i) -> __m256i {
+    // Indices with the top bit set shuffle in zero, so each table only
+    // yields a row for its half of the byte range.
+    let index = _mm256_and_si256(u8s, _mm256_set1_epu8(0x8F));
+    let rows = _mm256_or_si256(
+        _mm256_shuffle_epi8(lower, index),
+        _mm256_shuffle_epi8(upper, _mm256_xor_si256(index, _mm256_set1_epu8(0x80))),
+    );
+    let high_nibbles = _mm256_and_si256(_mm256_srli_epi16(u8s, 4), _mm256_set1_epu8(0x0F));
+    let bits = _mm256_shuffle_epi8(mm256_from_offset(&BITS, 0), high_nibbles);
+    mm256_cmpneq_epi8(_mm256_and_si256(rows, bits), _mm256_setzero_si256())
+}
+
+#[target_feature(enable = "avx2")]
+pub unsafe fn chunk_count_set(haystack: &[u8], table: &[u8; 32]) -> usize {
+    assert!(haystack.len() >= 32);
+
+    let mut offset = 0;
+    let mut count = 0;
+
+    // The shuffles work within 128-bit lanes, so both lanes get both tables.
+    let lower = mm256_broadcast_from_offset(table, 0);
+    let upper = mm256_broadcast_from_offset(table, 16);
+
+    // 8160
+    while haystack.len() >= offset + 32 * 255 {
+        let mut counts = _mm256_setzero_si256();
+        for _ in 0..255 {
+            counts = _mm256_sub_epi8(
+                counts,
+                is_in_set(mm256_from_offset(haystack, offset), lower, upper),
+            );
+            offset += 32;
+        }
+        count += sum(&counts);
+    }
+
+    // 4096
+    if haystack.len() >= offset + 32 * 128 {
+        let mut counts = _mm256_setzero_si256();
+        for _ in 0..128 {
+            counts = _mm256_sub_epi8(
+                counts,
+                is_in_set(mm256_from_offset(haystack, offset), lower, upper),
+            );
+            offset += 32;
+        }
+        count += sum(&counts);
+    }
+
+    // 32
+    let mut counts = _mm256_setzero_si256();
+    for i in 0..(haystack.len() - offset) / 32 {
+        counts = _mm256_sub_epi8(
+            counts,
+            is_in_set(mm256_from_offset(haystack, offset + i * 32), lower, upper),
+        );
+    }
+    if haystack.len() % 32 != 0 {
+        counts = _mm256_sub_epi8(
+            counts,
+            _mm256_and_si256(
+                is_in_set(mm256_from_offset(haystack, haystack.len() - 32), lower, upper),
+                mm256_from_offset(&MASK, haystack.len() % 32),
+            ),
+        );
+    }
+    count += sum(&counts);
+
+    count
+}
+
+#[target_feature(enable = "avx2")]
+unsafe fn is_in_range(u8s: __m256i, lows: __m256i, spans: __m256i) -> __m256i {
+    // `lo <= x <= hi` iff `x - lo <= hi - lo`, and AVX2 has no unsigned
+    // compare, so check that the saturating difference to the span is zero.
+    _mm256_cmpeq_epi8(
+        _mm256_subs_epu8(_mm256_sub_epi8(u8s, lows), spans),
+        _mm256_setzero_si256(),
+    )
+}
+
+#[target_feature(enable = "avx2")]
+pub unsafe fn chunk_count_range(haystack: &[u8], lo: u8, hi: u8) -> usize {
+    assert!(haystack.len() >= 32);
+    assert!(lo <= hi);
+
+    let mut offset = 0;
+    let mut count = 0;
+
+    let lows = _mm256_set1_epu8(lo);
+    let spans = _mm256_set1_epu8(hi - lo);
+
+    // 8160
+    while haystack.len() >= offset + 32 * 255 {
+        let mut counts = _mm256_setzero_si256();
+        for _ in 0..255 {
+            counts = _mm256_sub_epi8(
+                counts,
+                is_in_range(mm256_from_offset(haystack, offset), lows, spans),
+            );
+            offset += 32;
+        }
+        count += sum(&counts);
+    }
+
+    // 4096
+    if haystack.len() >= offset + 32 * 128 {
+        let mut counts = _mm256_setzero_si256();
+        for _ in 0..128 {
+            counts = _mm256_sub_epi8(
+                counts,
+                is_in_range(mm256_from_offset(haystack, offset), lows, spans),
+            );
+            offset += 32;
+        }
+        count += sum(&counts);
+    }
+
+    // 32
+    let mut counts = _mm256_setzero_si256();
+    for i in 0..(haystack.len() - offset) / 32 {
+        counts = _mm256_sub_epi8(
+            counts,
+            is_in_range(mm256_from_offset(haystack, offset + i * 32), lows, spans),
+        );
+    }
+    if haystack.len() % 32 != 0 {
+        counts = _mm256_sub_epi8(
+            counts,
+            _mm256_and_si256(
+                is_in_range(mm256_from_offset(haystack, haystack.len() - 32), lows, spans),
+                mm256_from_offset(&MASK, haystack.len() % 32),
+            ),
+        );
+    }
+    count += sum(&counts);
+
+    count
+}
+
+#[target_feature(enable = "avx2")]
+unsafe fn count_each<const N: usize>(
+    counts: &mut [__m256i; N],
+    u8s: __m256i,
+    needles: &[__m256i; N],
+) {
+    for (counts, &needles) in counts.iter_mut().zip(needles.iter()) {
+        *counts = _mm256_sub_epi8(*counts, _mm256_cmpeq_epi8(u8s, needles));
+    }
+}
+
+#[target_feature(enable = "avx2")]
+unsafe fn sum_each<const N: usize>(count: &mut [usize; N], counts: &[__m256i; N]) {
+    for (count, counts) in count.iter_mut().zip(counts.iter()) {
+        *count += sum(counts);
+    }
+}
+
+#[target_feature(enable = "avx2")]
+pub unsafe fn chunk_count_many<const N: usize>(haystack: &[u8], needles: [u8; N]) -> [usize; N] {
+    assert!(haystack.len() >= 32);
+
+    let mut offset = 0;
+    let mut count = [0; N];
+
+    let mut splats = [_mm256_setzero_si256(); N];
+    for (splat, &needle) in splats.iter_mut().zip(needles.iter()) {
+        *splat = _mm256_set1_epu8(needle);
+    }
+    let needles = &splats;
+
+    // 8160
+    while haystack.len() >= offset + 32 * 255 {
+        let mut counts = [_mm256_setzero_si256(); N];
+        for _ in 0..255 {
+            count_each(&mut counts, mm256_from_offset(haystack, offset), needles);
+            offset += 32;
+        }
+        sum_each(&mut count, &counts);
+    }
+
+    // 4096
+    if haystack.len() >= offset + 32 * 128 {
+        let mut counts = [_mm256_setzero_si256(); N];
+        for _ in 0..128 {
+            count_each(&mut counts, mm256_from_offset(haystack, offset), needles);
+            offset += 32;
+        }
+        sum_each(&mut count, &counts);
+    }
+
+    // 32
+    let mut counts = [_mm256_setzero_si256(); N];
+    for i in 0..(haystack.len() - offset) / 32 {
+        count_each(&mut counts, mm256_from_offset(haystack, offset + i * 32), needles);
+    }
+    if haystack.len() % 32 != 0 {
+        let mask = mm256_from_offset(&MASK, haystack.len() % 32);
+        let u8s = mm256_from_offset(haystack, haystack.len() - 32);
+        for (counts, &needles) in counts.iter_mut().zip(needles.iter()) {
+            *counts = _mm256_sub_epi8(
+                *counts,
+                _mm256_and_si256(_mm256_cmpeq_epi8(u8s, needles), mask),
+            );
+        }
+    }
+    sum_each(&mut count, &counts);
+
+    count
+}
+
+#[target_feature(enable = "avx2")]
+pub unsafe fn chunk_text_stats(utf8_chars: &[u8]) -> (usize, usize) {
+    assert!(utf8_chars.len() >= 32);
+
+    let mut offset = 0;
+    let mut newlines = 0;
+    let mut chars = 0;
+
+    let needles = _mm256_set1_epu8(b'\n');
+
+    // 8160
+    while utf8_chars.len() >= offset + 32 * 255 {
+        let mut newline_counts = _mm256_setzero_si256();
+        let mut char_counts = _mm256_setzero_si256();
+        for _ in 0..255 {
+            let u8s = mm256_from_offset(utf8_chars, offset);
+            newline_counts = _mm256_sub_epi8(newline_counts, _mm256_cmpeq_epi8(u8s, needles));
+            char_counts = _mm256_sub_epi8(char_counts, is_leading_utf8_byte(u8s));
+            offset += 32;
+        }
+        newlines += sum(&newline_counts);
+        chars += sum(&char_counts);
+    }
+
+    // 4096
+    if utf8_chars.len() >= offset + 32 * 128 {
+        let mut newline_counts = _mm256_setzero_si256();
+        let mut char_counts = _mm256_setzero_si256();
+        for _ in 0..128 {
+            let u8s = mm256_from_offset(utf8_chars, offset);
+            newline_counts = _mm256_sub_epi8(newline_counts, _mm256_cmpeq_epi8(u8s, needles));
+            char_counts = _mm256_sub_epi8(char_counts, is_leading_utf8_byte(u8s));
+            offset += 32;
+        }
+        newlines += sum(&newline_counts);
+        chars += sum(&char_counts);
+    }
+
+    // 32
+    let mut newline_counts = _mm256_setzero_si256();
+    let mut char_counts = _mm256_setzero_si256();
+    for i in 0..(utf8_chars.len() - offset) / 32 {
+        let u8s = mm256_from_offset(utf8_chars, offset + i * 32);
+        newline_counts = _mm256_sub_epi8(newline_counts, _mm256_cmpeq_epi8(u8s, needles));
+        char_counts = _mm256_sub_epi8(char_counts, is_leading_utf8_byte(u8s));
+    }
+    if utf8_chars.len() % 32 != 0 {
+        let u8s = mm256_from_offset(utf8_chars, utf8_chars.len() - 32);
+        let mask = mm256_from_offset(&MASK, utf8_chars.len() % 32);
+        newline_counts = _mm256_sub_epi8(
+            newline_counts,
+            _mm256_and_si256(_mm256_cmpeq_epi8(u8s, needles), mask),
+        );
+        char_counts = _mm256_sub_epi8(
+            char_counts,
+            _mm256_and_si256(is_leading_utf8_byte(u8s), mask),
+        );
+    }
+    newlines += sum(&newline_counts);
+    chars += sum(&char_counts);
+
+    (newlines, chars)
+}
+
+#[target_feature(enable = "avx2")]
+pub unsafe fn chunk_count_substring(haystack: &[u8], needle: &[u8], overlapping: bool) -> usize {
+    assert!(needle.len() >= 2);
+    assert!(haystack.len() >= needle.len() - 1 + 32);
+
+    let last = needle.len() - 1;
+    let mut offset = 0;
+    let mut matches = Matches::new(haystack, needle, overlapping);
+
+    let firsts = _mm256_set1_epu8(needle[0]);
+    let lasts = _mm256_set1_epu8(needle[last]);
+
+    // 32
+    while haystack.len() >= offset + last + 32 {
+        let candidates = _mm256_and_si256(
+            _mm256_cmpeq_epi8(mm256_from_offset(haystack, offset), firsts),
+            _mm256_cmpeq_epi8(mm256_from_offset(haystack, offset + last), lasts),
+        );
+        let mut bits = _mm256_movemask_epi8(candidates) as u32;
+        while bits != 0 {
+            matches.check(offset + bits.trailing_zeros() as usize);
+            bits &= bits - 1;
+        }
+        offset += 32;
+    }
+
+    matches.finish(offset)
+}
+
+#[target_feature(enable = "avx2")]
+unsafe fn match_bits(u8s: __m256i, needles: __m256i) -> u64 {
+    _mm256_movemask_epi8(_mm256_cmpeq_epi8(u8s, needles)) as u32 as u64
+}
+
+#[target_feature(enable = "avx2")]
+pub unsafe fn chunk_nth(haystack: &[u8], needle: u8, mut n: usize) -> Option<usize> {
+    assert!(haystack.len() >= 32);
+
+    let mut offset = 0;
+
+    let needles = _mm256_set1_epu8(needle);
+
+    // 8160: skip whole blocks that end before the nth occurrence
+    while haystack.len() >= offset + 32 * 255 {
+        let mut counts = _mm256_setzero_si256();
+        for i in 0..255 {
+            counts = _mm256_sub_epi8(
+                counts,
+                _mm256_cmpeq_epi8(mm256_from_offset(haystack, offset + i * 32), needles),
+            );
+        }
+        let count = sum(&counts);
+        if count > n {
+            break;
+        }
+        n -= count;
+        offset += 32 * 255;
+    }
+
+    // 32
+    while haystack.len() >= offset + 32 {
+        let bits = match_bits(mm256_from_offset(haystack, offset), needles);
+        let count = bits.count_ones() as usize;
+        if count > n {
+            return Some(offset + nth_set_bit(bits, n));
+        }
+        n -= count;
+        offset += 32;
+    }
+    if haystack.len() != offset {
+        let bits = match_bits(mm256_from_offset(haystack, haystack.len() - 32), needles)
+            >> (offset + 32 - haystack.len());
+        if bits.count_ones() as usize > n {
+            return Some(offset + nth_set_bit(bits, n));
+        }
+    }
+
+    None
+}
+
+#[target_feature(enable = "avx2")]
+pub unsafe fn chunk_rcount_until(haystack: &[u8], needle: u8, limit: usize) -> (usize, usize) {
+    assert!(haystack.len() >= 32 && limit > 0);
+
+    let mut count = 0;
+    let mut end = haystack.len();
+
+    let needles = _mm256_set1_epu8(needle);
+
+    // 8160: skip whole blocks that start after the last wanted occurrence
+    while end >= 32 * 255 {
+        let mut counts = _mm256_setzero_si256();
+        for i in 1..=255 {
+            counts = _mm256_sub_epi8(
+                counts,
+                _mm256_cmpeq_epi8(mm256_from_offset(haystack, end - i * 32), needles),
+            );
+        }
+        let block = sum(&counts);
+        if count + block >= limit {
+            break;
+        }
+        count += block;
+        end -= 32 * 255;
+    }
+
+    // 32
+    while end >= 32 {
+        let bits = match_bits(mm256_from_offset(haystack, end - 32), needles);
+        let found = bits.count_ones() as usize;
+        if count + found >= limit {
+            return (limit, end - 32 + nth_set_bit(bits, count + found - limit));
+        }
+        count += found;
+        end -= 32;
+    }
+    if end != 0 {
+        let bits = match_bits(mm256_from_offset(haystack, 0), needles) & ((1 << (end)) - 1);
+        let found = bits.count_ones() as usize;
+        if count + found >= limit {
+            return (limit, nth_set_bit(bits, count + found - limit));
+        }
+        count += found;
+    }
+
+    (count, 0)
+}
+
+#[cfg(feature = "runtime-dispatch-simd")]
+#[target_feature(enable = "avx2")]
+pub unsafe fn chunk_positions<F: FnMut(usize)>(haystack: &[u8], needle: u8, mut f: F) {
+    assert!(haystack.len() >= 32);
+
+    let mut offset = 0;
+
+    let needles = _mm256_set1_epu8(needle);
+
+    // 32
+    while haystack.len() >= offset + 32 {
+        let mut bits = match_bits(mm256_from_offset(haystack, offset), needles);
+        while bits != 0 {
+            f(offset + bits.trailing_zeros() as usize);
+            bits &= bits - 1;
+        }
+        offset += 32;
+    }
+    if haystack.len() != offset {
+        let mut bits = match_bits(mm256_from_offset(haystack, haystack.len() - 32), needles)
+            >> (offset + 32 - haystack.len());
+        while bits != 0 {
+            f(offset + bits.trailing_zeros() as usize);
+            bits &= bits - 1;
+        }
+    }
+}
+
+#[target_feature(enable = "avx2")]
+unsafe fn is_four_byte_leader(u8s: __m256i) -> __m256i {
+    _mm256_cmpeq_epi8(_mm256_max_epu8(u8s, _mm256_set1_epu8(0b1111_0000)), u8s)
+}
+
+#[target_feature(enable = "avx2")]
+pub unsafe fn chunk_utf16_len(utf8_chars: &[u8]) -> usize {
+    assert!(utf8_chars.len() >= 32);
+
+    let mut offset = 0;
+    let mut fours = 0;
+    let mut chars = 0;
+
+    // 8160
+    while utf8_chars.len() >= offset + 32 * 255 {
+        let mut four_counts = _mm256_setzero_si256();
+        let mut char_counts = _mm256_setzero_si256();
+        for _ in 0..255 {
+            let u8s = mm256_from_offset(utf8_chars, offset);
+            four_counts = _mm256_sub_epi8(four_counts, is_four_byte_leader(u8s));
+            char_counts = _mm256_sub_epi8(char_counts, is_leading_utf8_byte(u8s));
+            offset += 32;
+        }
+        fours += sum(&four_counts);
+        chars += sum(&char_counts);
+    }
+
+    // 4096
+    if utf8_chars.len() >= offset + 32 * 128 {
+        let mut four_counts = _mm256_setzero_si256();
+        let mut char_counts = _mm256_setzero_si256();
+        for _ in 0..128 {
+            let u8s = mm256_from_offset(utf8_chars, offset);
+            four_counts = _mm256_sub_epi8(four_counts, is_four_byte_leader(u8s));
+            char_counts = _mm256_sub_epi8(char_counts, is_leading_utf8_byte(u8s));
+            offset += 32;
+        }
+        fours += sum(&four_counts);
+        chars += sum(&char_counts);
+    }
+
+    // 32
+    let mut four_counts = _mm256_setzero_si256();
+    let mut char_counts = _mm256_setzero_si256();
+    for i in 0..(utf8_chars.len() - offset) / 32 {
+        let u8s = mm256_from_offset(utf8_chars, offset + i * 32);
+        four_counts = _mm256_sub_epi8(four_counts, is_four_byte_leader(u8s));
+        char_counts = _mm256_sub_epi8(char_counts, is_leading_utf8_byte(u8s));
+    }
+    if utf8_chars.len() % 32 != 0 {
+        let u8s = mm256_from_offset(utf8_chars, utf8_chars.len() - 32);
+        let mask = mm256_from_offset(&MASK, utf8_chars.len() % 32);
+        four_counts = _mm256_sub_epi8(
+            four_counts,
+            _mm256_and_si256(is_four_byte_leader(u8s), mask),
+        );
+        char_counts = _mm256_sub_epi8(
+            char_counts,
+            _mm256_and_si256(is_leading_utf8_byte(u8s), mask),
+        );
+    }
+    fours += sum(&four_counts);
+    chars += sum(&char_counts);
+
+    chars + fours
+}
+
+#[target_feature(enable = "avx2")]
+unsafe fn mm256_from_offset_u16(slice: &[u16], offset: usize) -> __m256i {
+    _mm256_loadu_si256(slice.as_ptr().add(offset) as *const _)
+}
+
+#[target_feature(enable = "avx2")]
+unsafe fn sum_u16(u16s: &__m256i) -> usize {
+    sum(&_mm256_and_si256(*u16s, _mm256_set1_epi16(0xFF)))
+        + (sum(&_mm256_srli_epi16(*u16s, 8)) << 8)
+}
+
+// The number of bytes short of three that a UTF-16 code unit takes in
+// UTF-8, as a negative count; a surrogate pair takes four bytes, two each.
+#[target_feature(enable = "avx2")]
+unsafe fn utf8_deficit(u16s: __m256i) -> __m256i {
+    let below_0x80 = _mm256_cmpeq_epi16(
+        _mm256_and_si256(u16s, _mm256_set1_epi16(0xFF80u16 as i16)),
+        _mm256_setzero_si256(),
+    );
+    let masked = _mm256_and_si256(u16s, _mm256_set1_epi16(0xF800u16 as i16));
+    let below_0x800 = _mm256_cmpeq_epi16(masked, _mm256_setzero_si256());
+    let surrogate = _mm256_cmpeq_epi16(masked, _mm256_set1_epi16(0xD800u16 as i16));
+    _mm256_add_epi16(_mm256_add_epi16(below_0x80, below_0x800), surrogate)
+}
+
+#[target_feature(enable = "avx2")]
+pub unsafe fn chunk_utf8_len_from_utf16(utf16: &[u16]) -> usize {
+    assert!(utf16.len() >= 16);
+
+    let mut offset = 0;
+    let mut deficit = 0;
+
+    // 131072
+    while utf16.len() >= offset + 16 * 8192 {
+        let mut counts = _mm256_setzero_si256();
+        for _ in 0..8192 {
+            counts = _mm256_sub_epi16(counts, utf8_deficit(mm256_from_offset_u16(utf16, offset)));
+            offset += 16;
+        }
+        deficit += sum_u16(&counts);
+    }
+
+    // 16
+    let mut counts = _mm256_setzero_si256();
+    for i in 0..(utf16.len() - offset) / 16 {
+        counts = _mm256_sub_epi16(
+            counts,
+            utf8_deficit(mm256_from_offset_u16(utf16, offset + i * 16)),
+        );
+    }
+    if utf16.len() % 16 != 0 {
+        counts = _mm256_sub_epi16(
+            counts,
+            _mm256_and_si256(
+                utf8_deficit(mm256_from_offset_u16(utf16, utf16.len() - 16)),
+                mm256_from_offset(&MASK, 2 * (utf16.len() % 16)),
+            ),
+        );
+    }
+    deficit += sum_u16(&counts);
+
+    3 * utf16.len() - deficit
+}
+
+#[target_feature(enable = "avx2")]
+unsafe fn is_low_surrogate(u16s: __m256i) -> __m256i {
+    _mm256_cmpeq_epi16(
+        _mm256_and_si256(u16s, _mm256_set1_epi16(0xFC00u16 as i16)),
+        _mm256_set1_epi16(0xDC00u16 as i16),
+    )
+}
+
+#[target_feature(enable = "avx2")]
+pub unsafe fn chunk_num_chars_utf16(utf16: &[u16]) -> usize {
+    assert!(utf16.len() >= 16);
+
+    let mut offset = 0;
+    let mut low_surrogates = 0;
+
+    // 131072
+    while utf16.len() >= offset + 16 * 8192 {
+        let mut counts = _mm256_setzero_si256();
+        for _ in 0..8192 {
+            counts = _mm256_sub_epi16(
+                counts,
+                is_low_surrogate(mm256_from_offset_u16(utf16, offset)),
+            );
+            offset += 16;
+        }
+        low_surrogates += sum_u16(&counts);
+    }
+
+    // 16
+    let mut counts = _mm256_setzero_si256();
+    for i in 0..(utf16.len() - offset) / 16 {
+        counts = _mm256_sub_epi16(
+            counts,
+            is_low_surrogate(mm256_from_offset_u16(utf16, offset + i * 16)),
+        );
+    }
+    if utf16.len() % 16 != 0 {
+        counts = _mm256_sub_epi16(
+            counts,
+            _mm256_and_si256(
+                is_low_surrogate(mm256_from_offset_u16(utf16, utf16.len() - 16)),
+                mm256_from_offset(&MASK, 2 * (utf16.len() % 16)),
+            ),
+        );
+    }
+    low_surrogates += sum_u16(&counts);
+
+    utf16.len() - low_surrogates
+}
+
+#[target_feature(enable = "avx2")]
+unsafe fn is_at_most(u8s: __m256i, maxima: __m256i) -> __m256i {
+    _mm256_cmpeq_epi8(_mm256_min_epu8(u8s, maxima), u8s)
+}
+
+#[target_feature(enable = "avx2")]
+unsafe fn count_at_most_each<const N: usize>(
+    counts: &mut [__m256i; N],
+    u8s: __m256i,
+    maxima: &[__m256i; N],
+) {
+    for (counts, &maxima) in counts.iter_mut().zip(maxima.iter()) {
+        *counts = _mm256_sub_epi8(*counts, is_at_most(u8s, maxima));
+    }
+}
+
+#[target_feature(enable = "avx2")]
+pub unsafe fn chunk_count_below<const N: usize>(haystack: &[u8], bounds: [u8; N]) -> [usize; N] {
+    assert!(haystack.len() >= 32);
+
+    let mut offset = 0;
+    let mut count = [0; N];
+
+    let mut splats = [_mm256_setzero_si256(); N];
+    for (splat, &bound) in splats.iter_mut().zip(bounds.iter()) {
+        *splat = _mm256_set1_epu8(bound - 1);
+    }
+    let maxima = &splats;
+
+    // 8160
+    while haystack.len() >= offset + 32 * 255 {
+        let mut counts = [_mm256_setzero_si256(); N];
+        for _ in 0..255 {
+            count_at_most_each(&mut counts, mm256_from_offset(haystack, offset), maxima);
+            offset += 32;
+        }
+        sum_each(&mut count, &counts);
+    }
+
+    // 4096
+    if haystack.len() >= offset + 32 * 128 {
+        let mut counts = [_mm256_setzero_si256(); N];
+        for _ in 0..128 {
+            count_at_most_each(&mut counts, mm256_from_offset(haystack, offset), maxima);
+            offset += 32;
+        }
+        sum_each(&mut count, &counts);
+    }
+
+    // 32
+    let mut counts = [_mm256_setzero_si256(); N];
+    for i in 0..(haystack.len() - offset) / 32 {
+        count_at_most_each(&mut counts, mm256_from_offset(haystack, offset + i * 32), maxima);
+    }
+    if haystack.len() % 32 != 0 {
+        let mask = mm256_from_offset(&MASK, haystack.len() % 32);
+        let u8s = mm256_from_offset(haystack, haystack.len() - 32);
+        for (counts, &maxima) in counts.iter_mut().zip(maxima.iter()) {
+            *counts = _mm256_sub_epi8(
+                *counts,
+                _mm256_and_si256(is_at_most(u8s, maxima), mask),
+            );
+        }
+    }
+    sum_each(&mut count, &counts);
+
+    count
+}
@@ -1,30 +1,63 @@
+use crate::bits::nth_set_bit;
+use crate::substring::Matches;
+use crate::utf8::{UTF8_HIGH, UTF8_PREV_HIGH, UTF8_PREV_LOW};
+
+#[cfg(not(feature = "runtime-dispatch-simd"))]
+use core::arch;
+#[cfg(feature = "runtime-dispatch-simd")]
+use std::arch;
+
 #[cfg(target_arch = "x86")]
-use std::arch::x86::{
+use arch::x86::{
     __m128i,
+    _mm_add_epi16,
+    _mm_alignr_epi8,
     _mm_and_si128,
+    _mm_cmpeq_epi16,
     _mm_cmpeq_epi8,
     _mm_cvtsi128_si32,
     _mm_loadu_si128,
+    _mm_max_epu8,
+    _mm_min_epu8,
+    _mm_movemask_epi8,
+    _mm_or_si128,
     _mm_sad_epu8,
+    _mm_set1_epi16,
     _mm_set1_epi8,
     _mm_setzero_si128,
     _mm_shuffle_epi32,
+    _mm_shuffle_epi8,
+    _mm_srli_epi16,
+    _mm_sub_epi16,
     _mm_sub_epi8,
+    _mm_subs_epu8,
     _mm_xor_si128,
 };
 
 #[cfg(target_arch = "x86_64")]
-use std::arch::x86_64::{
+use arch::x86_64::{
     __m128i,
+    _mm_add_epi16,
+    _mm_alignr_epi8,
     _mm_and_si128,
+    _mm_cmpeq_epi16,
     _mm_cmpeq_epi8,
     _mm_cvtsi128_si32,
     _mm_loadu_si128,
+    _mm_max_epu8,
+    _mm_min_epu8,
+    _mm_movemask_epi8,
+    _mm_or_si128,
     _mm_sad_epu8,
+    _mm_set1_epi16,
     _mm_set1_epi8,
     _mm_setzero_si128,
     _mm_shuffle_epi32,
+    _mm_shuffle_epi8,
+    _mm_srli_epi16,
+    _mm_sub_epi16,
     _mm_sub_epi8,
+    _mm_subs_epu8,
     _mm_xor_si128,
 };
 
@@ -45,7 +78,7 @@ const MASK: [u8; 32] = [
 
 #[target_feature(enable = "sse2")]
 unsafe fn mm_from_offset(slice: &[u8], offset: usize) -> __m128i {
-    _mm_loadu_si128(slice.as_ptr().offset(offset as isize) as *const _)
+    _mm_loadu_si128(slice.as_ptr().add(offset) as *const _)
 }
 
 #[target_feature(enable = "sse2")]
@@ -171,3 +204,824 @@ pub unsafe fn chunk_num_chars(utf8_chars: &[u8]) -> usize {
 
     count
 }
+
+// The error bits of each byte of `u8s` given the bytes before it, as laid
+// out in `crate::utf8`; any bit set means the input is not valid UTF-8.
+#[target_feature(enable = "ssse3")]
+unsafe fn utf8_errors(u8s: __m128i, previous: __m128i) -> __m128i {
+    let low_nibbles = _mm_set1_epu8(0x0F);
+    let prev1 = _mm_alignr_epi8(u8s, previous, 15);
+    let prev2 = _mm_alignr_epi8(u8s, previous, 14);
+    let prev3 = _mm_alignr_epi8(u8s, previous, 13);
+    let errors = _mm_and_si128(
+        _mm_and_si128(
+            _mm_shuffle_epi8(
+                mm_from_offset(&UTF8_PREV_HIGH, 0),
+                _mm_and_si128(_mm_srli_epi16(prev1, 4), low_nibbles),
+            ),
+            _mm_shuffle_epi8(mm_from_offset(&UTF8_PREV_LOW, 0), _mm_and_si128(prev1, low_nibbles)),
+        ),
+        _mm_shuffle_epi8(
+            mm_from_offset(&UTF8_HIGH, 0),
+            _mm_and_si128(_mm_srli_epi16(u8s, 4), low_nibbles),
+        ),
+    );
+    // The third and fourth bytes of a sequence must be continuation bytes
+    let must_continue = _mm_and_si128(
+        _mm_or_si128(
+            _mm_subs_epu8(prev2, _mm_set1_epu8(0xE0 - 0x80)),
+            _mm_subs_epu8(prev3, _mm_set1_epu8(0xF0 - 0x80)),
+        ),
+        _mm_set1_epu8(0x80),
+    );
+    _mm_xor_si128(errors, must_continue)
+}
+
+#[target_feature(enable = "sse2")]
+unsafe fn any_set(u8s: __m128i) -> bool {
+    _mm_movemask_epi8(_mm_cmpeq_epi8(u8s, _mm_setzero_si128())) != 0xFFFF
+}
+
+#[target_feature(enable = "ssse3")]
+pub unsafe fn chunk_try_num_chars(utf8_chars: &[u8]) -> Option<usize> {
+    assert!(utf8_chars.len() >= 16);
+
+    let mut offset = 0;
+    let mut count = 0;
+
+    let mut previous = _mm_setzero_si128();
+    let mut errors = _mm_setzero_si128();
+
+    // 4080
+    while utf8_chars.len() >= offset + 16 * 255 {
+        let mut counts = _mm_setzero_si128();
+        for _ in 0..255 {
+            let u8s = mm_from_offset(utf8_chars, offset);
+            errors = _mm_or_si128(errors, utf8_errors(u8s, previous));
+            counts = _mm_sub_epi8(counts, is_leading_utf8_byte(u8s));
+            previous = u8s;
+            offset += 16;
+        }
+        if any_set(errors) {
+            return None;
+        }
+        count += sum(&counts);
+    }
+
+    // 16
+    let mut counts = _mm_setzero_si128();
+    while utf8_chars.len() >= offset + 16 {
+        let u8s = mm_from_offset(utf8_chars, offset);
+        errors = _mm_or_si128(errors, utf8_errors(u8s, previous));
+        counts = _mm_sub_epi8(counts, is_leading_utf8_byte(u8s));
+        previous = u8s;
+        offset += 16;
+    }
+    // The rest is padded with zeros, which also catches a sequence that the
+    // end of the input cuts short. Each padding byte counts as a character.
+    let mut rest = [0; 16];
+    rest[..utf8_chars.len() - offset].copy_from_slice(&utf8_chars[offset..]);
+    let u8s = mm_from_offset(&rest, 0);
+    errors = _mm_or_si128(errors, utf8_errors(u8s, previous));
+    counts = _mm_sub_epi8(counts, is_leading_utf8_byte(u8s));
+    if any_set(errors) {
+        return None;
+    }
+    count += sum(&counts);
+
+    Some(count - (offset + 16 - utf8_chars.len()))
+}
+
+#[target_feature(enable = "sse2")]
+unsafe fn mm_cmpeq_any_epi8(u8s: __m128i, needles: &[__m128i]) -> __m128i {
+    let mut matches = _mm_setzero_si128();
+    for &needle in needles {
+        matches = _mm_or_si128(matches, _mm_cmpeq_epi8(u8s, needle));
+    }
+    matches
+}
+
+#[target_feature(enable = "sse2")]
+pub unsafe fn chunk_count_any(haystack: &[u8], needles: &[u8]) -> usize {
+    assert!(haystack.len() >= 16);
+    assert!(needles.len() <= 8);
+
+    let mut offset = 0;
+    let mut count = 0;
+
+    let mut splats = [_mm_setzero_si128(); 8];
+    for (splat, &needle) in splats.iter_mut().zip(needles) {
+        *splat = _mm_set1_epu8(needle);
+    }
+    let needles = &splats[..needles.len()];
+
+    // 4080
+    while haystack.len() >= offset + 16 * 255 {
+        let mut counts = _mm_setzero_si128();
+        for _ in 0..255 {
+            counts = _mm_sub_epi8(
+                counts,
+                mm_cmpeq_any_epi8(mm_from_offset(haystack, offset), needles)
+            );
+            offset += 16;
+        }
+        count += sum(&counts);
+    }
+
+    // 2048
+    if haystack.len() >= offset + 16 * 128 {
+        let mut counts = _mm_setzero_si128();
+        for _ in 0..128 {
+            counts = _mm_sub_epi8(
+                counts,
+                mm_cmpeq_any_epi8(mm_from_offset(haystack, offset), needles)
+            );
+            offset += 16;
+        }
+        count += sum(&counts);
+    }
+
+    // 16
+    let mut counts = _mm_setzero_si128();
+    for i in 0..(haystack.len() - offset) / 16 {
+        counts = _mm_sub_epi8(
+            counts,
+            mm_cmpeq_any_epi8(mm_from_offset(haystack, offset + i * 16), needles)
+        );
+    }
+    if haystack.len() % 16 != 0 {
+        counts = _mm_sub_epi8(
+            counts,
+            _mm_and_si128(
+                mm_cmpeq_any_epi8(mm_from_offset(haystack, haystack.len() - 16), needles),
+                                  mm_from_offset(&MASK, haystack.len() % 16)
+            )
+        );
+    }
+    count += sum(&counts);
+
+    count
+}
+
+const BITS: [u8; 16] = [1, 2, 4, 8, 16, 32, 64, 128, 1, 2, 4, 8, 16, 32, 64, 128];
+
+// pshufb is not part of SSE2, so the set lookups need SSSE3.
+#[target_feature(enable = "ssse3")]
+unsafe fn is_in_set(u8s: __m128i, lower: __m128i, upper: __m128i) -> __m128i {
+    // Indices with the top bit set shuffle in zero, so each table only
+    // yields a row for its half of the byte range.
+    let index = _mm_and_si128(u8s, _mm_set1_epu8(0x8F));
+    let rows = _mm_or_si128(
+        _mm_shuffle_epi8(lower, index),
+        _mm_shuffle_epi8(upper, _mm_xor_si128(index, _mm_set1_epu8(0x80))),
+    );
+    let high_nibbles = _mm_and_si128(_mm_srli_epi16(u8s, 4), _mm_set1_epu8(0x0F));
+    let bits = _mm_shuffle_epi8(mm_from_offset(&BITS, 0), high_nibbles);
+    mm_cmpneq_epi8(_mm_and_si128(rows, bits), _mm_setzero_si128())
+}
+
+#[target_feature(enable = "ssse3")]
+pub unsafe fn chunk_count_set(haystack: &[u8], table: &[u8; 32]) -> usize {
+    assert!(haystack.len() >= 16);
+
+    let mut offset = 0;
+    let mut count = 0;
+
+    let lower = mm_from_offset(table, 0);
+    let upper = mm_from_offset(table, 16);
+
+    // 4080
+    while haystack.len() >= offset + 16 * 255 {
+        let mut counts = _mm_setzero_si128();
+        for _ in 0..255 {
+            counts = _mm_sub_epi8(
+                counts,
+                is_in_set(mm_from_offset(haystack, offset), lower, upper)
+            );
+            offset += 16;
+        }
+        count += sum(&counts);
+    }
+
+    // 2048
+    if haystack.len() >= offset + 16 * 128 {
+        let mut counts = _mm_setzero_si128();
+        for _ in 0..128 {
+            counts = _mm_sub_epi8(
+                counts,
+                is_in_set(mm_from_offset(haystack, offset), lower, upper)
+            );
+            offset += 16;
+        }
+        count += sum(&counts);
+    }
+
+    // 16
+    let mut counts = _mm_setzero_si128();
+    for i in 0..(haystack.len() - offset) / 16 {
+        counts = _mm_sub_epi8(
+            counts,
+            is_in_set(mm_from_offset(haystack, offset + i * 16), lower, upper)
+        );
+    }
+    if haystack.len() % 16 != 0 {
+        counts = _mm_sub_epi8(
+            counts,
+            _mm_and_si128(
+                is_in_set(mm_from_offset(haystack, haystack.len() - 16), lower, upper),
+                          mm_from_offset(&MASK, haystack.len() % 16)
+            )
+        );
+    }
+    count += sum(&counts);
+
+    count
+}
+
+#[target_feature(enable = "sse2")]
+unsafe fn is_in_range(u8s: __m128i, lows: __m128i, spans: __m128i) -> __m128i {
+    // `lo <= x <= hi` iff `x - lo <= hi - lo`, and SSE2 has no unsigned
+    // compare, so check that the saturating difference to the span is zero.
+    _mm_cmpeq_epi8(_mm_subs_epu8(_mm_sub_epi8(u8s, lows), spans), _mm_setzero_si128())
+}
+
+#[target_feature(enable = "sse2")]
+pub unsafe fn chunk_count_range(haystack: &[u8], lo: u8, hi: u8) -> usize {
+    assert!(haystack.len() >= 16);
+    assert!(lo <= hi);
+
+    let mut offset = 0;
+    let mut count = 0;
+
+    let lows = _mm_set1_epu8(lo);
+    let spans = _mm_set1_epu8(hi - lo);
+
+    // 4080
+    while haystack.len() >= offset + 16 * 255 {
+        let mut counts = _mm_setzero_si128();
+        for _ in 0..255 {
+            counts = _mm_sub_epi8(
+                counts,
+                is_in_range(mm_from_offset(haystack, offset), lows, spans)
+            );
+            offset += 16;
+        }
+        count += sum(&counts);
+    }
+
+    // 2048
+    if haystack.len() >= offset + 16 * 128 {
+        let mut counts = _mm_setzero_si128();
+        for _ in 0..128 {
+            counts = _mm_sub_epi8(
+                counts,
+                is_in_range(mm_from_offset(haystack, offset), lows, spans)
+            );
+            offset += 16;
+        }
+        count += sum(&counts);
+    }
+
+    // 16
+    let mut counts = _mm_setzero_si128();
+    for i in 0..(haystack.len() - offset) / 16 {
+        counts = _mm_sub_epi8(
+            counts,
+            is_in_range(mm_from_offset(haystack, offset + i * 16), lows, spans)
+        );
+    }
+    if haystack.len() % 16 != 0 {
+        counts = _mm_sub_epi8(
+            counts,
+            _mm_and_si128(
+                is_in_range(mm_from_offset(haystack, haystack.len() - 16), lows, spans),
+                            mm_from_offset(&MASK, haystack.len() % 16)
+            )
+        );
+    }
+    count += sum(&counts);
+
+    count
+}
+
+#[target_feature(enable = "sse2")]
+unsafe fn count_each<const N: usize>(
+    counts: &mut [__m128i; N],
+    u8s: __m128i,
+    needles: &[__m128i; N],
+) {
+    for (counts, &needles) in counts.iter_mut().zip(needles.iter()) {
+        *counts = _mm_sub_epi8(*counts, _mm_cmpeq_epi8(u8s, needles));
+    }
+}
+
+#[target_feature(enable = "sse2")]
+unsafe fn sum_each<const N: usize>(count: &mut [usize; N], counts: &[__m128i; N]) {
+    for (count, counts) in count.iter_mut().zip(counts.iter()) {
+        *count += sum(counts);
+    }
+}
+
+#[target_feature(enable = "sse2")]
+pub unsafe fn chunk_count_many<const N: usize>(haystack: &[u8], needles: [u8; N]) -> [usize; N] {
+    assert!(haystack.len() >= 16);
+
+    let mut offset = 0;
+    let mut count = [0; N];
+
+    let mut splats = [_mm_setzero_si128(); N];
+    for (splat, &needle) in splats.iter_mut().zip(needles.iter()) {
+        *splat = _mm_set1_epu8(needle);
+    }
+    let needles = &splats;
+
+    // 4080
+    while haystack.len() >= offset + 16 * 255 {
+        let mut counts = [_mm_setzero_si128(); N];
+        for _ in 0..255 {
+            count_each(&mut counts, mm_from_offset(haystack, offset), needles);
+            offset += 16;
+        }
+        sum_each(&mut count, &counts);
+    }
+
+    // 2048
+    if haystack.len() >= offset + 16 * 128 {
+        let mut counts = [_mm_setzero_si128(); N];
+        for _ in 0..128 {
+            count_each(&mut counts, mm_from_offset(haystack, offset), needles);
+            offset += 16;
+        }
+        sum_each(&mut count, &counts);
+    }
+
+    // 16
+    let mut counts = [_mm_setzero_si128(); N];
+    for i in 0..(haystack.len() - offset) / 16 {
+        count_each(&mut counts, mm_from_offset(haystack, offset + i * 16), needles);
+    }
+    if haystack.len() % 16 != 0 {
+        let mask = mm_from_offset(&MASK, haystack.len() % 16);
+        let u8s = mm_from_offset(haystack, haystack.len() - 16);
+        for (counts, &needles) in counts.iter_mut().zip(needles.iter()) {
+            *counts = _mm_sub_epi8(*counts, _mm_and_si128(_mm_cmpeq_epi8(u8s, needles), mask));
+        }
+    }
+    sum_each(&mut count, &counts);
+
+    count
+}
+
+#[target_feature(enable = "sse2")]
+pub unsafe fn chunk_text_stats(utf8_chars: &[u8]) -> (usize, usize) {
+    assert!(utf8_chars.len() >= 16);
+
+    let mut offset = 0;
+    let mut newlines = 0;
+    let mut chars = 0;
+
+    let needles = _mm_set1_epu8(b'\n');
+
+    // 4080
+    while utf8_chars.len() >= offset + 16 * 255 {
+        let mut newline_counts = _mm_setzero_si128();
+        let mut char_counts = _mm_setzero_si128();
+        for _ in 0..255 {
+            let u8s = mm_from_offset(utf8_chars, offset);
+            newline_counts = _mm_sub_epi8(newline_counts, _mm_cmpeq_epi8(u8s, needles));
+            char_counts = _mm_sub_epi8(char_counts, is_leading_utf8_byte(u8s));
+            offset += 16;
+        }
+        newlines += sum(&newline_counts);
+        chars += sum(&char_counts);
+    }
+
+    // 2048
+    if utf8_chars.len() >= offset + 16 * 128 {
+        let mut newline_counts = _mm_setzero_si128();
+        let mut char_counts = _mm_setzero_si128();
+        for _ in 0..128 {
+            let u8s = mm_from_offset(utf8_chars, offset);
+            newline_counts = _mm_sub_epi8(newline_counts, _mm_cmpeq_epi8(u8s, needles));
+            char_counts = _mm_sub_epi8(char_counts, is_leading_utf8_byte(u8s));
+            offset += 16;
+        }
+        newlines += sum(&newline_counts);
+        chars += sum(&char_counts);
+    }
+
+    // 16
+    let mut newline_counts = _mm_setzero_si128();
+    let mut char_counts = _mm_setzero_si128();
+    for i in 0..(utf8_chars.len() - offset) / 16 {
+        let u8s = mm_from_offset(utf8_chars, offset + i * 16);
+        newline_counts = _mm_sub_epi8(newline_counts, _mm_cmpeq_epi8(u8s, needles));
+        char_counts = _mm_sub_epi8(char_counts, is_leading_utf8_byte(u8s));
+    }
+    if utf8_chars.len() % 16 != 0 {
+        let u8s = mm_from_offset(utf8_chars, utf8_chars.len() - 16);
+        let mask = mm_from_offset(&MASK, utf8_chars.len() % 16);
+        newline_counts = _mm_sub_epi8(
+            newline_counts,
+            _mm_and_si128(_mm_cmpeq_epi8(u8s, needles), mask),
+        );
+        char_counts = _mm_sub_epi8(char_counts, _mm_and_si128(is_leading_utf8_byte(u8s), mask));
+    }
+    newlines += sum(&newline_counts);
+    chars += sum(&char_counts);
+
+    (newlines, chars)
+}
+
+#[target_feature(enable = "sse2")]
+pub unsafe fn chunk_count_substring(haystack: &[u8], needle: &[u8], overlapping: bool) -> usize {
+    assert!(needle.len() >= 2);
+    assert!(haystack.len() >= needle.len() - 1 + 16);
+
+    let last = needle.len() - 1;
+    let mut offset = 0;
+    let mut matches = Matches::new(haystack, needle, overlapping);
+
+    let firsts = _mm_set1_epu8(needle[0]);
+    let lasts = _mm_set1_epu8(needle[last]);
+
+    // 16
+    while haystack.len() >= offset + last + 16 {
+        let candidates = _mm_and_si128(
+            _mm_cmpeq_epi8(mm_from_offset(haystack, offset), firsts),
+            _mm_cmpeq_epi8(mm_from_offset(haystack, offset + last), lasts),
+        );
+        let mut bits = _mm_movemask_epi8(candidates) as u32;
+        while bits != 0 {
+            matches.check(offset + bits.trailing_zeros() as usize);
+            bits &= bits - 1;
+        }
+        offset += 16;
+    }
+
+    matches.finish(offset)
+}
+
+#[target_feature(enable = "sse2")]
+unsafe fn match_bits(u8s: __m128i, needles: __m128i) -> u64 {
+    _mm_movemask_epi8(_mm_cmpeq_epi8(u8s, needles)) as u32 as u64
+}
+
+#[target_feature(enable = "sse2")]
+pub unsafe fn chunk_nth(haystack: &[u8], needle: u8, mut n: usize) -> Option<usize> {
+    assert!(haystack.len() >= 16);
+
+    let mut offset = 0;
+
+    let needles = _mm_set1_epu8(needle);
+
+    // 4080: skip whole blocks that end before the nth occurrence
+    while haystack.len() >= offset + 16 * 255 {
+        let mut counts = _mm_setzero_si128();
+        for i in 0..255 {
+            counts = _mm_sub_epi8(
+                counts,
+                _mm_cmpeq_epi8(mm_from_offset(haystack, offset + i * 16), needles)
+            );
+        }
+        let count = sum(&counts);
+        if count > n {
+            break;
+        }
+        n -= count;
+        offset += 16 * 255;
+    }
+
+    // 16
+    while haystack.len() >= offset + 16 {
+        let bits = match_bits(mm_from_offset(haystack, offset), needles);
+        let count = bits.count_ones() as usize;
+        if count > n {
+            return Some(offset + nth_set_bit(bits, n));
+        }
+        n -= count;
+        offset += 16;
+    }
+    if haystack.len() != offset {
+        let bits = match_bits(mm_from_offset(haystack, haystack.len() - 16), needles)
+            >> (offset + 16 - haystack.len());
+        if bits.count_ones() as usize > n {
+            return Some(offset + nth_set_bit(bits, n));
+        }
+    }
+
+    None
+}
+
+#[target_feature(enable = "sse2")]
+pub unsafe fn chunk_rcount_until(haystack: &[u8], needle: u8, limit: usize) -> (usize, usize) {
+    assert!(haystack.len() >= 16 && limit > 0);
+
+    let mut count = 0;
+    let mut end = haystack.len();
+
+    let needles = _mm_set1_epu8(needle);
+
+    // 4080: skip whole blocks that start after the last wanted occurrence
+    while end >= 16 * 255 {
+        let mut counts = _mm_setzero_si128();
+        for i in 1..=255 {
+            counts = _mm_sub_epi8(
+                counts,
+                _mm_cmpeq_epi8(mm_from_offset(haystack, end - i * 16), needles),
+            );
+        }
+        let block = sum(&counts);
+        if count + block >= limit {
+            break;
+        }
+        count += block;
+        end -= 16 * 255;
+    }
+
+    // 16
+    while end >= 16 {
+        let bits = match_bits(mm_from_offset(haystack, end - 16), needles);
+        let found = bits.count_ones() as usize;
+        if count + found >= limit {
+            return (limit, end - 16 + nth_set_bit(bits, count + found - limit));
+        }
+        count += found;
+        end -= 16;
+    }
+    if end != 0 {
+        let bits = match_bits(mm_from_offset(haystack, 0), needles) & ((1 << (end)) - 1);
+        let found = bits.count_ones() as usize;
+        if count + found >= limit {
+            return (limit, nth_set_bit(bits, count + found - limit));
+        }
+        count += found;
+    }
+
+    (count, 0)
+}
+
+#[cfg(feature = "runtime-dispatch-simd")]
+#[target_feature(enable = "sse2")]
+pub unsafe fn chunk_positions<F: FnMut(usize)>(haystack: &[u8], needle: u8, mut f: F) {
+    assert!(haystack.len() >= 16);
+
+    let mut offset = 0;
+
+    let needles = _mm_set1_epu8(needle);
+
+    // 16
+    while haystack.len() >= offset + 16 {
+        let mut bits = match_bits(mm_from_offset(haystack, offset), needles);
+        while bits != 0 {
+            f(offset + bits.trailing_zeros() as usize);
+            bits &= bits - 1;
+        }
+        offset += 16;
+    }
+    if haystack.len() != offset {
+        let mut bits = match_bits(mm_from_offset(haystack, haystack.len() - 16), needles)
+            >> (offset + 16 - haystack.len());
+        while bits != 0 {
+            f(offset + bits.trailing_zeros() as usize);
+            bits &= bits - 1;
+        }
+    }
+}
+
+#[target_feature(enable = "sse2")]
+unsafe fn is_four_byte_leader(u8s: __m128i) -> __m128i {
+    _mm_cmpeq_epi8(_mm_max_epu8(u8s, _mm_set1_epu8(0b1111_0000)), u8s)
+}
+
+#[target_feature(enable = "sse2")]
+pub unsafe fn chunk_utf16_len(utf8_chars: &[u8]) -> usize {
+    assert!(utf8_chars.len() >= 16);
+
+    let mut offset = 0;
+    let mut fours = 0;
+    let mut chars = 0;
+
+    // 4080
+    while utf8_chars.len() >= offset + 16 * 255 {
+        let mut four_counts = _mm_setzero_si128();
+        let mut char_counts = _mm_setzero_si128();
+        for _ in 0..255 {
+            let u8s = mm_from_offset(utf8_chars, offset);
+            four_counts = _mm_sub_epi8(four_counts, is_four_byte_leader(u8s));
+            char_counts = _mm_sub_epi8(char_counts, is_leading_utf8_byte(u8s));
+            offset += 16;
+        }
+        fours += sum(&four_counts);
+        chars += sum(&char_counts);
+    }
+
+    // 2048
+    if utf8_chars.len() >= offset + 16 * 128 {
+        let mut four_counts = _mm_setzero_si128();
+        let mut char_counts = _mm_setzero_si128();
+        for _ in 0..128 {
+            let u8s = mm_from_offset(utf8_chars, offset);
+            four_counts = _mm_sub_epi8(four_counts, is_four_byte_leader(u8s));
+            char_counts = _mm_sub_epi8(char_counts, is_leading_utf8_byte(u8s));
+            offset += 16;
+        }
+        fours += sum(&four_counts);
+        chars += sum(&char_counts);
+    }
+
+    // 16
+    let mut four_counts = _mm_setzero_si128();
+    let mut char_counts = _mm_setzero_si128();
+    for i in 0..(utf8_chars.len() - offset) / 16 {
+        let u8s = mm_from_offset(utf8_chars, offset + i * 16);
+        four_counts = _mm_sub_epi8(four_counts, is_four_byte_leader(u8s));
+        char_counts = _mm_sub_epi8(char_counts, is_leading_utf8_byte(u8s));
+    }
+    if utf8_chars.len() % 16 != 0 {
+        let u8s = mm_from_offset(utf8_chars, utf8_chars.len() - 16);
+        let mask = mm_from_offset(&MASK, utf8_chars.len() % 16);
+        four_counts = _mm_sub_epi8(
+            four_counts,
+            _mm_and_si128(is_four_byte_leader(u8s), mask),
+        );
+        char_counts = _mm_sub_epi8(char_counts, _mm_and_si128(is_leading_utf8_byte(u8s), mask));
+    }
+    fours += sum(&four_counts);
+    chars += sum(&char_counts);
+
+    chars + fours
+}
+
+#[target_feature(enable = "sse2")]
+unsafe fn mm_from_offset_u16(slice: &[u16], offset: usize) -> __m128i {
+    _mm_loadu_si128(slice.as_ptr().add(offset) as *const _)
+}
+
+#[target_feature(enable = "sse2")]
+unsafe fn sum_u16(u16s: &__m128i) -> usize {
+    sum(&_mm_and_si128(*u16s, _mm_set1_epi16(0xFF))) + (sum(&_mm_srli_epi16(*u16s, 8)) << 8)
+}
+
+// The number of bytes short of three that a UTF-16 code unit takes in
+// UTF-8, as a negative count; a surrogate pair takes four bytes, two each.
+#[target_feature(enable = "sse2")]
+unsafe fn utf8_deficit(u16s: __m128i) -> __m128i {
+    let below_0x80 = _mm_cmpeq_epi16(
+        _mm_and_si128(u16s, _mm_set1_epi16(0xFF80u16 as i16)),
+        _mm_setzero_si128(),
+    );
+    let masked = _mm_and_si128(u16s, _mm_set1_epi16(0xF800u16 as i16));
+    let below_0x800 = _mm_cmpeq_epi16(masked, _mm_setzero_si128());
+    let surrogate = _mm_cmpeq_epi16(masked, _mm_set1_epi16(0xD800u16 as i16));
+    _mm_add_epi16(_mm_add_epi16(below_0x80, below_0x800), surrogate)
+}
+
+#[target_feature(enable = "sse2")]
+pub unsafe fn chunk_utf8_len_from_utf16(utf16: &[u16]) -> usize {
+    assert!(utf16.len() >= 8);
+
+    let mut offset = 0;
+    let mut deficit = 0;
+
+    // 65536
+    while utf16.len() >= offset + 8 * 8192 {
+        let mut counts = _mm_setzero_si128();
+        for _ in 0..8192 {
+            counts = _mm_sub_epi16(counts, utf8_deficit(mm_from_offset_u16(utf16, offset)));
+            offset += 8;
+        }
+        deficit += sum_u16(&counts);
+    }
+
+    // 8
+    let mut counts = _mm_setzero_si128();
+    for i in 0..(utf16.len() - offset) / 8 {
+        counts = _mm_sub_epi16(counts, utf8_deficit(mm_from_offset_u16(utf16, offset + i * 8)));
+    }
+    if utf16.len() % 8 != 0 {
+        counts = _mm_sub_epi16(
+            counts,
+            _mm_and_si128(
+                utf8_deficit(mm_from_offset_u16(utf16, utf16.len() - 8)),
+                mm_from_offset(&MASK, 2 * (utf16.len() % 8)),
+            ),
+        );
+    }
+    deficit += sum_u16(&counts);
+
+    3 * utf16.len() - deficit
+}
+
+#[target_feature(enable = "sse2")]
+unsafe fn is_low_surrogate(u16s: __m128i) -> __m128i {
+    _mm_cmpeq_epi16(
+        _mm_and_si128(u16s, _mm_set1_epi16(0xFC00u16 as i16)),
+        _mm_set1_epi16(0xDC00u16 as i16),
+    )
+}
+
+#[target_feature(enable = "sse2")]
+pub unsafe fn chunk_num_chars_utf16(utf16: &[u16]) -> usize {
+    assert!(utf16.len() >= 8);
+
+    let mut offset = 0;
+    let mut low_surrogates = 0;
+
+    // 65536
+    while utf16.len() >= offset + 8 * 8192 {
+        let mut counts = _mm_setzero_si128();
+        for _ in 0..8192 {
+            counts = _mm_sub_epi16(counts, is_low_surrogate(mm_from_offset_u16(utf16, offset)));
+            offset += 8;
+        }
+        low_surrogates += sum_u16(&counts);
+    }
+
+    // 8
+    let mut counts = _mm_setzero_si128();
+    for i in 0..(utf16.len() - offset) / 8 {
+        counts = _mm_sub_epi16(
+            counts,
+            is_low_surrogate(mm_from_offset_u16(utf16, offset + i * 8)),
+        );
+    }
+    if utf16.len() % 8 != 0 {
+        counts = _mm_sub_epi16(
+            counts,
+            _mm_and_si128(
+                is_low_surrogate(mm_from_offset_u16(utf16, utf16.len() - 8)),
+                mm_from_offset(&MASK, 2 * (utf16.len() % 8)),
+            ),
+        );
+    }
+    low_surrogates += sum_u16(&counts);
+
+    utf16.len() - low_surrogates
+}
+
+#[target_feature(enable = "sse2")]
+unsafe fn is_at_most(u8s: __m128i, maxima: __m128i) -> __m128i {
+    _mm_cmpeq_epi8(_mm_min_epu8(u8s, maxima), u8s)
+}
+
+#[target_feature(enable = "sse2")]
+unsafe fn count_at_most_each<const N: usize>(
+    counts: &mut [__m128i; N],
+    u8s: __m128i,
+    maxima: &[__m128i; N],
+) {
+    for (counts, &maxima) in counts.iter_mut().zip(maxima.iter()) {
+        *counts = _mm_sub_epi8(*counts, is_at_most(u8s, maxima));
+    }
+}
+
+#[target_feature(enable = "sse2")]
+pub unsafe fn chunk_count_below<const N: usize>(haystack: &[u8], bounds: [u8; N]) -> [usize; N] {
+    assert!(haystack.len() >= 16);
+
+    let mut offset = 0;
+    let mut count = [0; N];
+
+    let mut splats = [_mm_setzero_si128(); N];
+    for (splat, &bound) in splats.iter_mut().zip(bounds.iter()) {
+        *splat = _mm_set1_epu8(bound - 1);
+    }
+    let maxima = &splats;
+
+    // 4080
+    while haystack.len() >= offset + 16 * 255 {
+        let mut counts = [_mm_setzero_si128(); N];
+        for _ in 0..255 {
+            count_at_most_each(&mut counts, mm_from_offset(haystack, offset), maxima);
+            offset += 16;
+        }
+        sum_each(&mut count, &counts);
+    }
+
+    // 2048
+    if haystack.len() >= offset + 16 * 128 {
+        let mut counts = [_mm_setzero_si128(); N];
+        for _ in 0..128 {
+            count_at_most_each(&mut counts, mm_from_offset(haystack, offset), maxima);
+            offset += 16;
+        }
+        sum_each(&mut count, &counts);
+    }
+
+    // 16
+    let mut counts = [_mm_setzero_si128(); N];
+    for i in 0..(haystack.len() - offset) / 16 {
+        count_at_most_each(&mut counts, mm_from_offset(haystack, offset + i * 16), maxima);
+    }
+    if haystack.len() % 16 != 0 {
+        let mask = mm_from_offset(&MASK, haystack.len() % 16);
+        let u8s = mm_from_offset(haystack, haystack.len() - 16);
+        for (counts, &maxima) in counts.iter_mut().zip(maxima.iter()) {
+            *counts = _mm_sub_epi8(*counts, _mm_and_si128(is_at_most(u8s, maxima), mask));
+        }
+    }
+    sum_each(&mut count, &counts);
+
+    count
+}
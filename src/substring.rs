@@ -0,0 +1,53 @@
+/// Verifies candidate positions of a needle and counts the matches.
+///
+/// The SIMD code paths only find positions where the first and last byte
+/// of the needle match; this checks the bytes in between and, unless
+/// overlapping matches are wanted, skips candidates inside the previous
+/// match. Candidates must therefore be checked in ascending order.
+pub(crate) struct Matches<'a> {
+    haystack: &'a [u8],
+    needle: &'a [u8],
+    overlapping: bool,
+    next: usize,
+    count: usize,
+}
+
+impl<'a> Matches<'a> {
+    pub(crate) fn new(haystack: &'a [u8], needle: &'a [u8], overlapping: bool) -> Self {
+        debug_assert!(needle.len() >= 2 && needle.len() <= haystack.len());
+        Matches {
+            haystack,
+            needle,
+            overlapping,
+            next: 0,
+            count: 0,
+        }
+    }
+
+    /// Count a match at `position` if the first and last bytes, which the
+    /// caller already compared, are accompanied by the rest of the needle.
+    pub(crate) fn check(&mut self, position: usize) {
+        let last = self.needle.len() - 1;
+        if position >= self.next
+            && self.haystack[position + 1..position + last] == self.needle[1..last]
+        {
+            self.count += 1;
+            if !self.overlapping {
+                self.next = position + self.needle.len();
+            }
+        }
+    }
+
+    /// Check every remaining position from `start` on and return the count.
+    pub(crate) fn finish(mut self, start: usize) -> usize {
+        let last = self.needle.len() - 1;
+        for position in start..self.haystack.len() - last {
+            if self.haystack[position] == self.needle[0]
+                && self.haystack[position + last] == self.needle[last]
+            {
+                self.check(position);
+            }
+        }
+        self.count
+    }
+}
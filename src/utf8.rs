@@ -0,0 +1,183 @@
+#[cfg(not(feature = "runtime-dispatch-simd"))]
+use core::{fmt, str};
+#[cfg(feature = "runtime-dispatch-simd")]
+use std::{error, fmt, str};
+
+use crate::count_valid;
+
+// The lookup tables for validating UTF-8 with SIMD, one lane per byte.
+// Each table maps a nibble to the set of errors it can take part in, as
+// bits: a byte pair is malformed if the high and low nibble of the first
+// byte and the high nibble of the second all share an error bit. Bit 7
+// marks a continuation byte after a continuation byte, which is only an
+// error if it is not the third or fourth byte of a sequence.
+
+/// Indexed by the high nibble of the previous byte
+pub(crate) const UTF8_PREV_HIGH: [u8; 16] = [
+    0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x80, 0x80, 0x80, 0x80, 0x21, 0x01, 0x15, 0x49,
+];
+/// Indexed by the low nibble of the previous byte
+pub(crate) const UTF8_PREV_LOW: [u8; 16] = [
+    0xe7, 0xa3, 0x83, 0x83, 0x8b, 0xcb, 0xcb, 0xcb, 0xcb, 0xcb, 0xcb, 0xcb, 0xcb, 0xdb, 0xcb, 0xcb,
+];
+/// Indexed by the high nibble of the current byte
+pub(crate) const UTF8_HIGH: [u8; 16] = [
+    0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0xe6, 0xae, 0xba, 0xba, 0x01, 0x01, 0x01, 0x01,
+];
+
+/// An error from validating UTF-8, as returned by
+/// [`try_num_chars`](fn.try_num_chars.html) and [`Utf8Counter`](struct.Utf8Counter.html)
+///
+/// This carries the same information as `core::str::Utf8Error`, which
+/// cannot be constructed outside of the standard library.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Utf8Error {
+    valid_up_to: usize,
+    error_len: Option<u8>,
+}
+
+impl Utf8Error {
+    pub(crate) fn new(valid_up_to: usize, error_len: Option<usize>) -> Self {
+        Utf8Error {
+            valid_up_to,
+            error_len: error_len.map(|len| len as u8),
+        }
+    }
+
+    /// The index up to which the input was valid UTF-8
+    pub fn valid_up_to(&self) -> usize {
+        self.valid_up_to
+    }
+
+    /// The length of the invalid byte sequence at `valid_up_to`, or `None`
+    /// if the input ended in the middle of a sequence that could still
+    /// have become valid
+    pub fn error_len(&self) -> Option<usize> {
+        self.error_len.map(|len| len as usize)
+    }
+}
+
+impl fmt::Display for Utf8Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.error_len {
+            Some(len) => write!(
+                f,
+                "invalid utf-8 sequence of {} bytes from index {}",
+                len, self.valid_up_to
+            ),
+            None => write!(
+                f,
+                "incomplete utf-8 byte sequence from index {}",
+                self.valid_up_to
+            ),
+        }
+    }
+}
+
+#[cfg(feature = "runtime-dispatch-simd")]
+impl error::Error for Utf8Error {}
+
+/// Counts the Unicode codepoints of UTF-8 text that arrives in chunks,
+/// validating it along the way
+///
+/// A sequence split between two chunks is carried over to the next call
+/// to [`update`](#method.update), so the chunks may be cut at any byte.
+/// The bulk of every chunk is counted with [`num_chars`](fn.num_chars.html).
+///
+/// # Example
+///
+/// ```
+/// let mut counter = bytecount::Utf8Counter::new();
+/// counter.update(b"J\xC3");
+/// counter.update(b"\xBCrgen");
+/// assert_eq!(counter.finish(), Ok(6));
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct Utf8Counter {
+    chars: usize,
+    offset: usize,
+    partial: [u8; 4],
+    partial_len: usize,
+    error: Option<Utf8Error>,
+}
+
+impl Utf8Counter {
+    /// Create a counter that has not seen any input
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Count the next chunk of input
+    ///
+    /// Once invalid input has been seen, further chunks are ignored.
+    pub fn update(&mut self, mut utf8_chars: &[u8]) {
+        if self.error.is_some() {
+            return;
+        }
+        if self.partial_len > 0 {
+            let len = self.partial_len;
+            let width = sequence_width(self.partial[0]);
+            let take = utf8_chars.len().min(width - len);
+            self.partial[len..len + take].copy_from_slice(&utf8_chars[..take]);
+            match str::from_utf8(&self.partial[..len + take]) {
+                Ok(_) => {
+                    self.chars += 1;
+                    self.offset += width;
+                    self.partial_len = 0;
+                    utf8_chars = &utf8_chars[take..];
+                }
+                // Still incomplete, so this chunk has been used up
+                Err(error) if error.error_len().is_none() => {
+                    self.partial_len = len + take;
+                    return;
+                }
+                Err(error) => {
+                    self.error = Some(Utf8Error::new(self.offset, error.error_len()));
+                    return;
+                }
+            }
+        }
+        let (chars, error) = count_valid(utf8_chars);
+        self.chars += chars;
+        match error {
+            None => self.offset += utf8_chars.len(),
+            // Keep a sequence cut off by the end of the chunk for later
+            Some(error) if error.error_len().is_none() => {
+                let tail = &utf8_chars[error.valid_up_to()..];
+                self.partial[..tail.len()].copy_from_slice(tail);
+                self.partial_len = tail.len();
+                self.offset += error.valid_up_to();
+            }
+            Some(error) => {
+                self.error = Some(Utf8Error::new(
+                    self.offset + error.valid_up_to(),
+                    error.error_len(),
+                ));
+            }
+        }
+    }
+
+    /// Return the number of codepoints in all input so far, or the first
+    /// error in it
+    ///
+    /// Input that ends in the middle of a sequence is an error, with an
+    /// `error_len` of `None`. Error positions are relative to the start
+    /// of the first chunk.
+    pub fn finish(self) -> Result<usize, Utf8Error> {
+        match self.error {
+            Some(error) => Err(error),
+            None if self.partial_len > 0 => Err(Utf8Error::new(self.offset, None)),
+            None => Ok(self.chars),
+        }
+    }
+}
+
+/// The encoded length of a sequence, given a leading byte that starts a
+/// valid prefix of one
+fn sequence_width(leader: u8) -> usize {
+    match leader {
+        0xC0..=0xDF => 2,
+        0xE0..=0xEF => 3,
+        _ => 4,
+    }
+}
@@ -3,8 +3,28 @@ extern crate bytecount;
 extern crate quickcheck;
 extern crate rand;
 
-use bytecount::{count, naive_count, naive_num_chars, num_chars};
+use bytecount::{
+    byte_to_char_offset, char_to_byte_offset, count, count_any, count_many, count_range, count_set,
+    count_substring, count_substring_overlapping, histogram, histogram_into,
+    naive_byte_to_char_offset, naive_char_to_byte_offset, naive_count, naive_count_any,
+    naive_count_many, naive_count_range, naive_count_set, naive_count_substring,
+    naive_count_substring_overlapping, naive_histogram, naive_nth, naive_nth_from_end,
+    naive_num_chars, naive_num_chars_lossy, naive_num_chars_utf16, naive_rcount_until,
+    naive_try_num_chars, naive_utf16_len, naive_utf16_len_from_utf8, naive_utf8_len_from_latin1,
+    naive_utf8_len_from_utf16, naive_utf8_length_histogram, nth, nth_from_end, num_chars,
+    num_chars_lossy, num_chars_utf16, offset_to_utf16_position, rcount_until, text_stats,
+    try_num_chars, utf16_len, utf16_len_from_utf8, utf16_position_to_offset, utf8_len_from_latin1,
+    utf8_len_from_utf16, utf8_length_histogram, ByteSet, Counter, TextStats, Utf8Counter,
+};
+#[cfg(feature = "runtime-dispatch-simd")]
+use bytecount::{
+    count_reader, count_reader_with_buffer_size, num_chars_reader,
+    num_chars_reader_with_buffer_size, par_count, par_num_chars, split_at_char_boundaries, split_at_lines,
+    LineIndex,
+};
 use rand::RngCore;
+#[cfg(feature = "runtime-dispatch-simd")]
+use std::io;
 
 fn random_bytes(len: usize) -> Vec<u8> {
     let mut result = vec![0; len];
@@ -56,6 +76,609 @@ fn check_count_overflow_many() {
     }
 }
 
+fn counter_total(haystack: &[u8], needle: u8, cuts: &[usize]) -> u64 {
+    let mut counter = Counter::new(needle);
+    let mut start = 0;
+    for &cut in cuts {
+        let end = start + cut.min(haystack.len() - start);
+        counter.update(&haystack[start..end]);
+        start = end;
+    }
+    counter.update(&haystack[start..]);
+    counter.total()
+}
+
+quickcheck! {
+    fn check_counter_correct(x: (Vec<u8>, u8, Vec<usize>)) -> bool {
+        let (haystack, needle, cuts) = x;
+        let cuts: Vec<_> = cuts.iter().map(|cut| cut % 100).collect();
+        counter_total(&haystack, needle, &cuts) == naive_count(&haystack, needle) as u64
+    }
+}
+
+#[test]
+fn check_counter_large_rand() {
+    let haystack = random_bytes(if cfg!(miri) { 200 } else { 100_000 });
+    let cuts: Vec<_> = (0..1000).map(|i| i % 131).collect();
+    for i in 0..=255 {
+        assert_eq!(counter_total(&haystack, i, &cuts), naive_count(&haystack, i) as u64);
+    }
+}
+
+#[test]
+fn check_counter_overflow_many() {
+    let haystack = [b'x'; 20000];
+    let mut counter = Counter::new(b'x');
+    for i in 0..200 {
+        counter.update(&haystack[..i]);
+    }
+    assert_eq!(counter.total(), 199 * 200 / 2);
+}
+
+/// A reader that yields at most `step` bytes at a time and is interrupted
+/// before every read
+#[cfg(feature = "runtime-dispatch-simd")]
+struct Trickle<'a> {
+    data: &'a [u8],
+    step: usize,
+    interrupt: bool,
+}
+
+#[cfg(feature = "runtime-dispatch-simd")]
+impl io::Read for Trickle<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.interrupt = !self.interrupt;
+        if self.interrupt {
+            return Err(io::ErrorKind::Interrupted.into());
+        }
+        let len = self.data.len().min(self.step).min(buf.len());
+        buf[..len].copy_from_slice(&self.data[..len]);
+        self.data = &self.data[len..];
+        Ok(len)
+    }
+}
+
+#[cfg(feature = "runtime-dispatch-simd")]
+quickcheck! {
+    fn check_count_reader_correct(x: (Vec<u8>, u8, usize)) -> bool {
+        let (haystack, needle, step) = x;
+        let expected = naive_count(&haystack, needle) as u64;
+        let trickle = Trickle { data: &haystack, step: step % 100 + 1, interrupt: false };
+        count_reader(&haystack[..], needle).unwrap() == expected
+            && count_reader_with_buffer_size(trickle, needle, step % 200).unwrap() == expected
+    }
+
+    fn check_num_chars_reader_correct(x: (Vec<u8>, usize)) -> bool {
+        let (haystack, step) = x;
+        let expected = naive_num_chars(&haystack) as u64;
+        let trickle = Trickle { data: &haystack, step: step % 100 + 1, interrupt: false };
+        num_chars_reader(&haystack[..]).unwrap() == expected
+            && num_chars_reader_with_buffer_size(trickle, step % 200).unwrap() == expected
+    }
+}
+
+#[test]
+#[cfg(feature = "runtime-dispatch-simd")]
+fn check_count_reader_large_rand() {
+    let haystack = random_bytes(if cfg!(miri) { 200 } else { 1_000_000 });
+    let trickle = Trickle { data: &haystack, step: 100_000, interrupt: false };
+    assert_eq!(count_reader(trickle, b'\n').unwrap(), naive_count(&haystack, b'\n') as u64);
+    let trickle = Trickle { data: &haystack, step: 100_000, interrupt: false };
+    assert_eq!(num_chars_reader(trickle).unwrap(), naive_num_chars(&haystack) as u64);
+}
+
+#[test]
+#[cfg(feature = "runtime-dispatch-simd")]
+fn check_count_reader_error() {
+    struct Broken;
+    impl io::Read for Broken {
+        fn read(&mut self, _: &mut [u8]) -> io::Result<usize> {
+            Err(io::ErrorKind::BrokenPipe.into())
+        }
+    }
+    let error = count_reader(Broken, b'\n').unwrap_err();
+    assert_eq!(error.kind(), io::ErrorKind::BrokenPipe);
+    let error = num_chars_reader(Broken).unwrap_err();
+    assert_eq!(error.kind(), io::ErrorKind::BrokenPipe);
+}
+
+#[test]
+#[cfg(feature = "runtime-dispatch-simd")]
+fn check_count_reader_huge_buffer_size() {
+    let text = &b"a\nb\nc"[..];
+    assert_eq!(count_reader_with_buffer_size(text, b'\n', usize::MAX).unwrap(), 2);
+    assert_eq!(num_chars_reader_with_buffer_size(text, usize::MAX).unwrap(), 5);
+}
+
+#[test]
+#[cfg(feature = "runtime-dispatch-simd")]
+fn check_par_count_large_rand() {
+    let haystack = random_bytes(if cfg!(miri) { 200 } else { 5_000_000 });
+    for &threads in &[0, 1, 2, 3, 8, 64] {
+        for &len in &[0, 1 << 20, (2 << 20) + 1, haystack.len()] {
+            let haystack = &haystack[..len.min(haystack.len())];
+            for &needle in &[0, b'\n', 255] {
+                assert_eq!(par_count(haystack, needle, threads), count(haystack, needle));
+            }
+            assert_eq!(par_num_chars(haystack, threads), num_chars(haystack));
+        }
+    }
+}
+
+#[cfg(feature = "runtime-dispatch-simd")]
+fn check_pieces(haystack: &[u8], n: usize, pieces: &[&[u8]]) -> bool {
+    pieces.len() <= n.max(1)
+        && pieces.iter().all(|piece| !piece.is_empty())
+        && pieces.concat() == haystack
+        && pieces.windows(2).all(|pair| pair[0].as_ptr_range().end == pair[1].as_ptr())
+}
+
+#[cfg(feature = "runtime-dispatch-simd")]
+quickcheck! {
+    fn check_split_at_lines_correct(x: (Vec<u8>, usize)) -> bool {
+        let (haystack, n) = x;
+        let n = n % 20;
+        let pieces = split_at_lines(&haystack, n);
+        let mut ends = pieces.iter().rev().skip(1);
+        check_pieces(&haystack, n, &pieces) && ends.all(|piece| piece.last() == Some(&b'\n'))
+    }
+
+    fn check_split_at_char_boundaries_correct(x: (String, usize)) -> bool {
+        let (text, n) = x;
+        let n = n % 20;
+        let pieces = split_at_char_boundaries(text.as_bytes(), n);
+        check_pieces(text.as_bytes(), n, &pieces)
+            && pieces.iter().all(|piece| std::str::from_utf8(piece).is_ok())
+    }
+
+    fn check_split_many_pieces(x: (String, usize)) -> bool {
+        let (text, n) = x;
+        let n = usize::MAX - n % 1024;
+        let haystack = text.as_bytes();
+        let pieces = split_at_lines(haystack, n);
+        let mut ends = pieces.iter().rev().skip(1);
+        let lines_ok = check_pieces(haystack, n, &pieces)
+            && ends.all(|piece| piece.last() == Some(&b'\n'));
+        let pieces = split_at_char_boundaries(haystack, n);
+        lines_ok
+            && check_pieces(haystack, n, &pieces)
+            && pieces.len() == text.chars().count()
+    }
+}
+
+#[test]
+#[cfg(feature = "runtime-dispatch-simd")]
+fn check_split_large() {
+    let text = "Grüße, Jürgen ❤\n".repeat(if cfg!(miri) { 100 } else { 100_000 });
+    let haystack = text.as_bytes();
+    for &n in &[1, 2, 7, 64] {
+        let pieces = split_at_lines(haystack, n);
+        assert!(check_pieces(haystack, n, &pieces));
+        assert_eq!(pieces.len(), n);
+        for piece in &pieces {
+            assert_eq!(piece.last(), Some(&b'\n'));
+            assert!(piece.len() <= haystack.len() / n + 20);
+        }
+        let pieces = split_at_char_boundaries(haystack, n);
+        assert!(check_pieces(haystack, n, &pieces));
+        assert_eq!(pieces.len(), n);
+        let chars: usize = pieces.iter().map(|piece| num_chars(piece)).sum();
+        assert_eq!(chars, text.chars().count());
+    }
+    assert!(split_at_lines(&[], 4).is_empty());
+    assert_eq!(split_at_lines(b"no newline", 4), [&b"no newline"[..]]);
+    assert_eq!(split_at_char_boundaries("❤".as_bytes(), 4), ["❤".as_bytes()]);
+}
+
+quickcheck! {
+    fn check_nth_correct(x: (Vec<u8>, u8, usize)) -> bool {
+        let (haystack, needle, n) = x;
+        nth(&haystack, needle, n) == naive_nth(&haystack, needle, n)
+    }
+
+    fn check_nth_small_alphabet(x: (Vec<bool>, usize)) -> bool {
+        let (haystack, n) = x;
+        let haystack: Vec<u8> = haystack.iter().map(|&b| b as u8).collect();
+        let n = n % (haystack.len() + 1);
+        nth(&haystack, 1, n) == naive_nth(&haystack, 1, n)
+    }
+}
+
+#[test]
+fn check_nth_large() {
+    let haystack = vec![0u8; if cfg!(miri) { 2_000 } else { 10_000_000 }];
+    for &n in &[0, 1, 4079, 4080, 8159, 8160, 16319, 16320, haystack.len() - 1, haystack.len()] {
+        assert_eq!(naive_nth(&haystack, 0, n), nth(&haystack, 0, n));
+    }
+    assert_eq!(None, nth(&haystack, 1, 0));
+}
+
+#[test]
+fn check_nth_large_rand() {
+    let haystack = random_bytes(if cfg!(miri) { 200 } else { 100_000 });
+    for i in 0..=255 {
+        let count = count(&haystack, i);
+        for n in [0, count / 3, count / 2, count.max(1) - 1, count] {
+            assert_eq!(naive_nth(&haystack, i, n), nth(&haystack, i, n));
+        }
+    }
+}
+
+#[test]
+fn check_nth_many() {
+    let string = [b'x'; 20000];
+    for i in 0..20000 {
+        assert_eq!(nth(&string[..i], b'x', i / 2), if i == 0 { None } else { Some(i / 2) });
+        assert_eq!(nth(&string[..i], b'x', i), None);
+    }
+}
+
+quickcheck! {
+    fn check_nth_from_end_correct(x: (Vec<u8>, u8, usize)) -> bool {
+        let (haystack, needle, n) = x;
+        nth_from_end(&haystack, needle, n) == naive_nth_from_end(&haystack, needle, n)
+    }
+
+    fn check_rcount_until_small_alphabet(x: (Vec<bool>, usize)) -> bool {
+        let (haystack, limit) = x;
+        let haystack: Vec<u8> = haystack.iter().map(|&b| b as u8).collect();
+        let limit = limit % (haystack.len() + 2);
+        rcount_until(&haystack, 1, limit) == naive_rcount_until(&haystack, 1, limit)
+    }
+}
+
+#[test]
+fn check_nth_from_end_large() {
+    let haystack = vec![0u8; if cfg!(miri) { 2_000 } else { 10_000_000 }];
+    for &n in &[0, 1, 4079, 4080, 8159, 8160, 16319, 16320, haystack.len() - 1, haystack.len()] {
+        assert_eq!(naive_nth_from_end(&haystack, 0, n), nth_from_end(&haystack, 0, n));
+    }
+    assert_eq!(None, nth_from_end(&haystack, 1, 0));
+    assert_eq!(None, nth_from_end(&haystack, 0, usize::MAX));
+}
+
+#[test]
+fn check_rcount_until_large_rand() {
+    let haystack = random_bytes(if cfg!(miri) { 200 } else { 100_000 });
+    for i in 0..=255 {
+        let count = count(&haystack, i);
+        for limit in [1, count / 3, count / 2, count, count + 1] {
+            assert_eq!(
+                naive_rcount_until(&haystack, i, limit),
+                rcount_until(&haystack, i, limit)
+            );
+        }
+    }
+}
+
+#[test]
+fn check_rcount_until_many() {
+    let string = [b'x'; 20000];
+    for i in 0..20000 {
+        assert_eq!(rcount_until(&string[..i], b'x', i / 2), (i / 2, i - i / 2));
+        assert_eq!(rcount_until(&string[..i], b'x', i + 1), (i, 0));
+    }
+}
+
+#[cfg(feature = "runtime-dispatch-simd")]
+fn naive_line_col(haystack: &[u8], offset: usize) -> Option<(usize, usize)> {
+    if offset > haystack.len() {
+        return None;
+    }
+    let line = naive_count(&haystack[..offset], b'\n');
+    let start = haystack[..offset]
+        .iter()
+        .rposition(|&byte| byte == b'\n')
+        .map_or(0, |i| i + 1);
+    Some((line, offset - start))
+}
+
+#[cfg(feature = "runtime-dispatch-simd")]
+fn check_line_index(haystack: &[u8], offsets: impl Iterator<Item = usize>) -> bool {
+    let index = LineIndex::new(haystack);
+    let mut starts = vec![0];
+    starts.extend(naive_positions(haystack, b'\n').map(|i| i + 1));
+    index.line_count() == starts.len()
+        && (0..starts.len() + 1).all(|line| index.line_start(line) == starts.get(line).cloned())
+        && offsets.into_iter().all(|offset| {
+            let expected = naive_line_col(haystack, offset);
+            index.line_col(offset) == expected && index.line_of(offset) == expected.map(|x| x.0)
+        })
+}
+
+#[cfg(feature = "runtime-dispatch-simd")]
+fn naive_positions(haystack: &[u8], needle: u8) -> impl Iterator<Item = usize> + '_ {
+    haystack
+        .iter()
+        .enumerate()
+        .filter(move |&(_, &byte)| byte == needle)
+        .map(|(i, _)| i)
+}
+
+#[cfg(feature = "runtime-dispatch-simd")]
+quickcheck! {
+    fn check_line_index_correct(haystack: Vec<bool>) -> bool {
+        let haystack: Vec<u8> = haystack.iter().map(|&b| if b { b'\n' } else { b'x' }).collect();
+        check_line_index(&haystack, 0..haystack.len() + 2)
+    }
+}
+
+#[test]
+#[cfg(feature = "runtime-dispatch-simd")]
+fn check_line_index_large_rand() {
+    let haystack = random_bytes(if cfg!(miri) { 200 } else { 100_000 });
+    assert!(check_line_index(&haystack, (0..haystack.len() + 2).step_by(37)));
+}
+
+#[test]
+#[cfg(feature = "runtime-dispatch-simd")]
+fn check_line_index_many() {
+    let string = b"\n".repeat(2000);
+    for i in 0..2000 {
+        let index = LineIndex::new(&string[..i]);
+        assert_eq!(index.line_count(), i + 1);
+        assert_eq!(index.line_start(i), Some(i));
+        assert_eq!(index.line_col(i), Some((i, 0)));
+    }
+}
+
+quickcheck! {
+    fn check_count_any_correct(x: (Vec<u8>, Vec<u8>)) -> bool {
+        let (haystack, needles) = x;
+        count_any(&haystack, &needles) == naive_count_any(&haystack, &needles)
+    }
+}
+
+#[test]
+fn check_count_any_large() {
+    let haystack = vec![0u8; if cfg!(miri) { 2_000 } else { 10_000_000 }];
+    assert_eq!(naive_count_any(&haystack, &[0, 1]), count_any(&haystack, &[0, 1]));
+    assert_eq!(naive_count_any(&haystack, &[1, 2]), count_any(&haystack, &[1, 2]));
+}
+
+#[test]
+fn check_count_any_large_rand() {
+    let haystack = random_bytes(if cfg!(miri) { 200 } else { 100_000 });
+    for i in 0..=255u8 {
+        let needles: Vec<u8> = (0..i % 10).map(|j| i.wrapping_add(j * 17)).collect();
+        assert_eq!(
+            naive_count_any(&haystack, &needles),
+            count_any(&haystack, &needles)
+        );
+    }
+}
+
+#[test]
+fn check_count_any_duplicates() {
+    let haystack = random_bytes(1_000);
+    assert_eq!(count(&haystack, 7), count_any(&haystack, &[7, 7, 7]));
+}
+
+#[test]
+fn check_count_any_overflow_many() {
+    let string = [b'x', b'y'].repeat(10000);
+    for i in 0..20000 {
+        assert_eq!(count_any(&string[..i], b"xy"), i);
+    }
+}
+
+quickcheck! {
+    fn check_count_many_correct(x: (Vec<u8>, [u8; 4])) -> bool {
+        let (haystack, needles) = x;
+        count_many(&haystack, needles) == naive_count_many(&haystack, needles)
+    }
+}
+
+#[test]
+fn check_count_many_large() {
+    let haystack = vec![0u8; if cfg!(miri) { 2_000 } else { 10_000_000 }];
+    assert_eq!(naive_count_many(&haystack, [0, 1, 0]), count_many(&haystack, [0, 1, 0]));
+}
+
+#[test]
+fn check_count_many_large_rand() {
+    let haystack = random_bytes(if cfg!(miri) { 200 } else { 100_000 });
+    for i in 0..=255u8 {
+        let needles = [i, i.wrapping_add(1), !i];
+        assert_eq!(needles.map(|n| count(&haystack, n)), count_many(&haystack, needles));
+        assert_eq!([count(&haystack, i)], count_many(&haystack, [i]));
+    }
+    assert_eq!([0usize; 0], count_many(&haystack, []));
+}
+
+#[test]
+fn check_count_many_overflow_many() {
+    let string = [b'x', b'y'].repeat(10000);
+    for i in 0..20000 {
+        assert_eq!(count_many(&string[..i], [b'x', b'y']), [i - i / 2, i / 2]);
+    }
+}
+
+quickcheck! {
+    fn check_count_set_correct(x: (Vec<u8>, Vec<u8>)) -> bool {
+        let (haystack, members) = x;
+        let set = ByteSet::from_bytes(&members);
+        count_set(&haystack, &set) == naive_count_set(&haystack, &set)
+            && count_set(&haystack, &set) == naive_count_any(&haystack, &members)
+    }
+}
+
+#[test]
+fn check_count_set_large() {
+    let haystack = vec![0u8; if cfg!(miri) { 2_000 } else { 10_000_000 }];
+    let set = ByteSet::from_range(0..=9);
+    assert_eq!(naive_count_set(&haystack, &set), count_set(&haystack, &set));
+    let set = set.complement();
+    assert_eq!(naive_count_set(&haystack, &set), count_set(&haystack, &set));
+}
+
+#[test]
+fn check_count_set_large_rand() {
+    let haystack = random_bytes(if cfg!(miri) { 200 } else { 100_000 });
+    for i in 0..=255 {
+        let single = ByteSet::from_bytes(&[i]);
+        assert_eq!(count(&haystack, i), count_set(&haystack, &single));
+        let range = ByteSet::from_range(i / 2..=i);
+        assert_eq!(naive_count_set(&haystack, &range), count_set(&haystack, &range));
+    }
+}
+
+#[test]
+fn check_count_set_overflow_many() {
+    let string = [b'x'; 20000];
+    let set = ByteSet::from_bytes(b"xyz");
+    for i in 0..20000 {
+        assert_eq!(count_set(&string[..i], &set), i);
+    }
+}
+
+#[test]
+fn check_byte_set() {
+    let set = ByteSet::from_predicate(|b| b % 3 == 0);
+    assert_eq!(set.len(), 86);
+    assert!(set.iter().all(|b| b % 3 == 0));
+    assert_eq!(set.complement().len(), 170);
+    assert_eq!(set.union(&set.complement()).len(), 256);
+    assert!(ByteSet::new().is_empty());
+    let mut set = ByteSet::from_range(b'a'..=b'z');
+    set.remove(b'q');
+    assert!(!set.contains(b'q') && set.contains(b'r'));
+    assert_eq!(set.len(), 25);
+}
+
+quickcheck! {
+    fn check_count_range_correct(x: (Vec<u8>, u8, u8)) -> bool {
+        let (haystack, lo, hi) = x;
+        count_range(&haystack, lo..=hi) == naive_count_range(&haystack, lo..=hi)
+    }
+}
+
+#[test]
+fn check_count_range_large() {
+    let haystack = vec![0u8; if cfg!(miri) { 2_000 } else { 10_000_000 }];
+    assert_eq!(naive_count_range(&haystack, 0..=0), count_range(&haystack, 0..=0));
+    assert_eq!(naive_count_range(&haystack, 1..=255), count_range(&haystack, 1..=255));
+}
+
+#[test]
+fn check_count_range_large_rand() {
+    let haystack = random_bytes(if cfg!(miri) { 200 } else { 100_000 });
+    for lo in (0..=255).step_by(if cfg!(miri) { 51 } else { 5 }) {
+        for hi in (lo..=255).step_by(if cfg!(miri) { 51 } else { 17 }) {
+            assert_eq!(
+                naive_count_range(&haystack, lo..=hi),
+                count_range(&haystack, lo..=hi)
+            );
+        }
+        assert_eq!(count_range(&haystack, lo..=lo), count(&haystack, lo));
+    }
+}
+
+#[test]
+fn check_count_range_overflow_many() {
+    let string = [b'x'; 20000];
+    for i in 0..20000 {
+        assert_eq!(count_range(&string[..i], b'a'..=b'z'), i);
+    }
+}
+
+quickcheck! {
+    fn check_count_substring_correct(x: (Vec<u8>, Vec<u8>)) -> bool {
+        let (haystack, needle) = x;
+        count_substring(&haystack, &needle) == naive_count_substring(&haystack, &needle)
+            && count_substring_overlapping(&haystack, &needle)
+                == naive_count_substring_overlapping(&haystack, &needle)
+    }
+
+    fn check_count_substring_small_alphabet(x: (Vec<bool>, Vec<bool>)) -> bool {
+        // Random needles hardly ever occur in random haystacks, so use just
+        // two different bytes to get plenty of (overlapping) matches.
+        let haystack: Vec<u8> = x.0.iter().map(|&b| b as u8).collect();
+        let needle: Vec<u8> = x.1.iter().take(5).map(|&b| b as u8).collect();
+        count_substring(&haystack, &needle) == naive_count_substring(&haystack, &needle)
+            && count_substring_overlapping(&haystack, &needle)
+                == naive_count_substring_overlapping(&haystack, &needle)
+    }
+}
+
+#[test]
+fn check_count_substring_large() {
+    let haystack = vec![0u8; if cfg!(miri) { 2_000 } else { 10_000_000 }];
+    for needle in [&[0, 0][..], &[0, 0, 0], &[0, 1, 0]] {
+        assert_eq!(
+            naive_count_substring(&haystack, needle),
+            count_substring(&haystack, needle)
+        );
+        assert_eq!(
+            naive_count_substring_overlapping(&haystack, needle),
+            count_substring_overlapping(&haystack, needle)
+        );
+    }
+}
+
+#[test]
+fn check_count_substring_large_rand() {
+    let haystack = random_bytes(if cfg!(miri) { 200 } else { 100_000 });
+    for len in 2..40 {
+        for start in (0..haystack.len() - len).step_by(haystack.len() / 10) {
+            let needle = &haystack[start..start + len];
+            assert_eq!(
+                naive_count_substring(&haystack, needle),
+                count_substring(&haystack, needle)
+            );
+            assert_eq!(
+                naive_count_substring_overlapping(&haystack, needle),
+                count_substring_overlapping(&haystack, needle)
+            );
+        }
+    }
+}
+
+#[test]
+fn check_count_substring_many() {
+    let string = b"abab".repeat(5000);
+    for i in 0..string.len() {
+        assert_eq!(count_substring(&string[..i], b"aba"), (i + 1) / 4);
+        assert_eq!(count_substring_overlapping(&string[..i], b"aba"), (i.max(1) - 1) / 2);
+    }
+}
+
+quickcheck! {
+    fn check_histogram_correct(haystack: Vec<u8>) -> bool {
+        histogram(&haystack)[..] == naive_histogram(&haystack)[..]
+    }
+}
+
+#[test]
+fn check_histogram_large() {
+    let haystack = vec![0u8; if cfg!(miri) { 2_000 } else { 10_000_000 }];
+    assert_eq!(naive_histogram(&haystack)[..], histogram(&haystack)[..]);
+}
+
+#[test]
+fn check_histogram_large_rand() {
+    let haystack = random_bytes(if cfg!(miri) { 2_000 } else { 100_000 });
+    let expected = naive_histogram(&haystack);
+    assert_eq!(expected[..], histogram(&haystack)[..]);
+    for i in 0..=255 {
+        assert_eq!(expected[i as usize], count(&haystack, i));
+    }
+}
+
+#[test]
+fn check_histogram_into() {
+    let haystack = random_bytes(if cfg!(miri) { 2_000 } else { 100_000 });
+    let mut histogram = [0; 256];
+    for chunk in haystack.chunks(777) {
+        histogram_into(&mut histogram, chunk);
+    }
+    histogram_into(&mut histogram, &haystack);
+    let expected = naive_histogram(&haystack);
+    for i in 0..256 {
+        assert_eq!(histogram[i], 2 * expected[i] as u64);
+    }
+}
+
 quickcheck! {
     fn check_num_chars_correct(haystack: Vec<u8>) -> bool {
         num_chars(&haystack) == naive_num_chars(&haystack)
@@ -81,6 +704,458 @@ fn check_num_chars_overflow() {
     assert_eq!(num_chars(&haystack), naive_num_chars(&haystack));
 }
 
+quickcheck! {
+    fn check_char_to_byte_offset_correct(x: (String, usize)) -> bool {
+        let (text, n) = x;
+        let n = n % (text.chars().count() + 2);
+        char_to_byte_offset(text.as_bytes(), n)
+            == text.char_indices().map(|(i, _)| i).chain(Some(text.len())).nth(n)
+    }
+
+    fn check_char_to_byte_offset_bytes(x: (Vec<u8>, usize)) -> bool {
+        let (haystack, n) = x;
+        char_to_byte_offset(&haystack, n) == naive_char_to_byte_offset(&haystack, n)
+    }
+
+    fn check_byte_to_char_offset_correct(x: (String, usize)) -> bool {
+        let (text, offset) = x;
+        let offset = offset % (text.len() + 2);
+        let expected = if text.is_char_boundary(offset) {
+            Some(text[..offset].chars().count())
+        } else {
+            None
+        };
+        byte_to_char_offset(text.as_bytes(), offset) == expected
+            && naive_byte_to_char_offset(text.as_bytes(), offset) == expected
+    }
+}
+
+#[test]
+fn check_char_to_byte_offset_large_rand() {
+    let haystack = random_bytes(if cfg!(miri) { 200 } else { 100_000 });
+    let chars = naive_num_chars(&haystack);
+    for n in (0..chars + 2).step_by(if cfg!(miri) { 7 } else { 997 }) {
+        let offset = char_to_byte_offset(&haystack, n);
+        assert_eq!(naive_char_to_byte_offset(&haystack, n), offset);
+        if let Some(offset) = offset {
+            assert_eq!(byte_to_char_offset(&haystack, offset), Some(n));
+        }
+    }
+}
+
+#[test]
+fn check_char_to_byte_offset_many() {
+    let string = "é".repeat(10000);
+    for i in 0..10000 {
+        assert_eq!(char_to_byte_offset(&string.as_bytes()[..2 * i], i), Some(2 * i));
+        assert_eq!(char_to_byte_offset(&string.as_bytes()[..2 * i], i + 1), None);
+        assert_eq!(byte_to_char_offset(string.as_bytes(), 2 * i), Some(i));
+        assert_eq!(byte_to_char_offset(string.as_bytes(), 2 * i + 1), None);
+    }
+}
+
+fn utf16_positions(text: &str) -> Vec<(usize, (usize, usize))> {
+    let mut positions = vec![];
+    for (line, (start, text)) in text
+        .split('\n')
+        .scan(0, |start, line| {
+            let line_start = *start;
+            *start += line.len() + 1;
+            Some((line_start, line))
+        })
+        .enumerate()
+    {
+        let mut column = 0;
+        for (i, c) in text.char_indices().chain(Some((text.len(), ' '))) {
+            positions.push((start + i, (line, column)));
+            column += c.len_utf16();
+        }
+    }
+    positions
+}
+
+quickcheck! {
+    fn check_utf16_len_correct(haystack: Vec<u8>) -> bool {
+        utf16_len(&haystack) == naive_utf16_len(&haystack)
+    }
+
+    fn check_utf16_len_str(text: String) -> bool {
+        utf16_len(text.as_bytes()) == text.encode_utf16().count()
+    }
+
+    fn check_utf16_position_correct(text: String) -> bool {
+        let text = text.replace('a', "\n");
+        let bytes = text.as_bytes();
+        utf16_positions(&text).into_iter().all(|(offset, (line, column))| {
+            offset_to_utf16_position(bytes, offset) == Some((line, column))
+                && utf16_position_to_offset(bytes, line, column) == Some(offset)
+        })
+    }
+}
+
+#[test]
+fn check_utf16_len_large() {
+    let haystack = "🦀".repeat(if cfg!(miri) { 500 } else { 2_500_000 });
+    assert_eq!(utf16_len(haystack.as_bytes()), haystack.encode_utf16().count());
+}
+
+#[test]
+fn check_utf16_len_large_rand() {
+    let haystack = random_bytes(if cfg!(miri) { 200 } else { 100_000 });
+    assert_eq!(naive_utf16_len(&haystack), utf16_len(&haystack));
+}
+
+#[test]
+fn check_utf16_len_overflow_many() {
+    let string = "\u{10000}".repeat(5000);
+    for i in 0..5000 {
+        assert_eq!(utf16_len(&string.as_bytes()[..4 * i]), 2 * i);
+    }
+}
+
+#[test]
+fn check_utf16_position_long_lines() {
+    let line = "aé€🦀".repeat(if cfg!(miri) { 20 } else { 2000 });
+    let text = [line.as_str(), "", &line].join("\n");
+    for (offset, (line, column)) in utf16_positions(&text).into_iter().step_by(7) {
+        assert_eq!(offset_to_utf16_position(text.as_bytes(), offset), Some((line, column)));
+        assert_eq!(utf16_position_to_offset(text.as_bytes(), line, column), Some(offset));
+    }
+    assert_eq!(utf16_position_to_offset(text.as_bytes(), 0, 4), None);
+    assert_eq!(utf16_position_to_offset(text.as_bytes(), 1, 1), None);
+    assert_eq!(utf16_position_to_offset(text.as_bytes(), 3, 0), None);
+    assert_eq!(offset_to_utf16_position(text.as_bytes(), 2), None);
+}
+
+quickcheck! {
+    fn check_utf8_len_from_utf16_correct(haystack: Vec<u16>) -> bool {
+        utf8_len_from_utf16(&haystack) == naive_utf8_len_from_utf16(&haystack)
+    }
+
+    fn check_utf8_len_from_utf16_str(text: String) -> bool {
+        let utf16: Vec<u16> = text.encode_utf16().collect();
+        utf8_len_from_utf16(&utf16) == text.len()
+    }
+
+    fn check_utf8_len_from_latin1_correct(haystack: Vec<u8>) -> bool {
+        utf8_len_from_latin1(&haystack) == naive_utf8_len_from_latin1(&haystack)
+    }
+
+    fn check_utf16_len_from_utf8_correct(haystack: Vec<u8>) -> bool {
+        utf16_len_from_utf8(&haystack) == naive_utf16_len_from_utf8(&haystack)
+    }
+}
+
+#[test]
+fn check_utf8_len_from_utf16_large() {
+    let haystack = vec![0x7FFu16; if cfg!(miri) { 2_000 } else { 10_000_000 }];
+    assert_eq!(naive_utf8_len_from_utf16(&haystack), utf8_len_from_utf16(&haystack));
+    let haystack = vec![0x7Fu16; if cfg!(miri) { 2_000 } else { 10_000_000 }];
+    assert_eq!(naive_utf8_len_from_utf16(&haystack), utf8_len_from_utf16(&haystack));
+}
+
+#[test]
+fn check_utf8_len_from_utf16_large_rand() {
+    let bytes = random_bytes(if cfg!(miri) { 400 } else { 200_000 });
+    let haystack: Vec<u16> = bytes
+        .chunks(2)
+        .map(|pair| u16::from_le_bytes([pair[0], pair[1] >> (pair[1] % 8)]))
+        .collect();
+    assert_eq!(naive_utf8_len_from_utf16(&haystack), utf8_len_from_utf16(&haystack));
+}
+
+#[test]
+fn check_utf8_len_from_utf16_overflow_many() {
+    // Cover the tails and block boundaries of all backends
+    let string = vec![0u16; if cfg!(miri) { 2_000 } else { 140_000 }];
+    let ends = [0, 1 << 14, 1 << 16, 1 << 17];
+    for &end in ends.iter().filter(|&&end| end < string.len()) {
+        for i in end..(end + 40).min(string.len()) {
+            assert_eq!(utf8_len_from_utf16(&string[..i]), i);
+        }
+    }
+}
+
+quickcheck! {
+    fn check_num_chars_utf16_correct(haystack: Vec<u16>) -> bool {
+        num_chars_utf16(&haystack) == naive_num_chars_utf16(&haystack)
+    }
+
+    fn check_num_chars_utf16_str(text: String) -> bool {
+        let utf16: Vec<u16> = text.encode_utf16().collect();
+        num_chars_utf16(&utf16) == text.chars().count()
+    }
+}
+
+#[test]
+fn check_num_chars_utf16_large() {
+    let haystack = vec![0xDC00u16; if cfg!(miri) { 2_000 } else { 10_000_000 }];
+    assert_eq!(0, num_chars_utf16(&haystack));
+    let haystack = vec![0xDBFFu16; if cfg!(miri) { 2_000 } else { 10_000_000 }];
+    assert_eq!(haystack.len(), num_chars_utf16(&haystack));
+}
+
+#[test]
+fn check_num_chars_utf16_large_rand() {
+    let bytes = random_bytes(if cfg!(miri) { 400 } else { 200_000 });
+    let haystack: Vec<u16> = bytes
+        .chunks(2)
+        .map(|pair| u16::from_le_bytes([pair[0], pair[1] | 0xD8]))
+        .collect();
+    assert_eq!(naive_num_chars_utf16(&haystack), num_chars_utf16(&haystack));
+}
+
+#[test]
+fn check_num_chars_utf16_overflow_many() {
+    let string = vec![0xDFFFu16; if cfg!(miri) { 2_000 } else { 140_000 }];
+    let ends = [0, 1 << 14, 1 << 16, 1 << 17];
+    for &end in ends.iter().filter(|&&end| end < string.len()) {
+        for i in end..(end + 40).min(string.len()) {
+            assert_eq!(num_chars_utf16(&string[..i]), 0);
+        }
+    }
+}
+
+#[test]
+fn check_utf8_len_from_latin1_large_rand() {
+    let haystack = random_bytes(if cfg!(miri) { 200 } else { 100_000 });
+    assert_eq!(naive_utf8_len_from_latin1(&haystack), utf8_len_from_latin1(&haystack));
+}
+
+fn check_try_num_chars_std(haystack: &[u8]) -> bool {
+    match (try_num_chars(haystack), std::str::from_utf8(haystack)) {
+        (Ok(chars), Ok(text)) => chars == text.chars().count(),
+        (Err(error), Err(expected)) => {
+            error.valid_up_to() == expected.valid_up_to()
+                && error.error_len() == expected.error_len()
+                && error.to_string() == expected.to_string()
+        }
+        _ => false,
+    }
+}
+
+quickcheck! {
+    fn check_try_num_chars_correct(haystack: Vec<u8>) -> bool {
+        try_num_chars(&haystack) == naive_try_num_chars(&haystack)
+            && check_try_num_chars_std(&haystack)
+    }
+
+    fn check_try_num_chars_str(x: (String, usize, u8)) -> bool {
+        let (text, at, byte) = x;
+        let mut haystack = text.into_bytes();
+        let ok = check_try_num_chars_std(&haystack);
+        if !haystack.is_empty() {
+            let at = at % haystack.len();
+            haystack[at] = byte;
+        }
+        ok && check_try_num_chars_std(&haystack)
+    }
+}
+
+#[test]
+fn check_try_num_chars_block_boundaries() {
+    for &c in &["é", "€", "🦀"] {
+        for prefix in 4090..4100 {
+            let text = "a".repeat(prefix) + &c.repeat(3000);
+            assert!(check_try_num_chars_std(text.as_bytes()));
+            for cut in 1..c.len() {
+                let mut bytes = text.as_bytes()[..prefix + cut].to_vec();
+                assert!(check_try_num_chars_std(&bytes));
+                bytes.push(b'a');
+                assert!(check_try_num_chars_std(&bytes));
+                bytes.extend_from_slice(text.as_bytes());
+                assert!(check_try_num_chars_std(&bytes));
+            }
+        }
+    }
+}
+
+#[test]
+fn check_try_num_chars_vector_boundaries() {
+    // Bytes at the edges of the ranges that decide whether a sequence is
+    // valid, put at and around the ends of the SIMD vectors
+    let edges = [
+        0x00, 0x7F, 0x80, 0x8F, 0x90, 0x9F, 0xA0, 0xBF, 0xC0, 0xC1, 0xC2, 0xDF, 0xE0, 0xE1, 0xEC,
+        0xED, 0xEE, 0xEF, 0xF0, 0xF1, 0xF3, 0xF4, 0xF5, 0xFF,
+    ];
+    let mut haystack = vec![b'a'; 64];
+    for &a in &edges {
+        for &b in &edges {
+            for &c in &edges {
+                for at in [13, 14, 15, 29, 30, 31, 61] {
+                    haystack[at..at + 3].copy_from_slice(&[a, b, c]);
+                    assert!(check_try_num_chars_std(&haystack), "{:x?}", &haystack);
+                    assert!(check_try_num_chars_std(&haystack[..at + 2]));
+                    haystack[at..at + 3].copy_from_slice(b"aaa");
+                }
+            }
+        }
+    }
+    for a in 0..=255 {
+        for b in 0..=255 {
+            for at in [15, 31, 62] {
+                haystack[at..at + 2].copy_from_slice(&[a, b]);
+                assert!(check_try_num_chars_std(&haystack));
+                haystack[at..at + 2].copy_from_slice(b"aa");
+            }
+        }
+    }
+}
+
+#[test]
+fn check_try_num_chars_large() {
+    let haystack = "Grüße, 🦀!".repeat(if cfg!(miri) { 100 } else { 1_000_000 });
+    assert_eq!(try_num_chars(haystack.as_bytes()), Ok(haystack.chars().count()));
+    let mut haystack = haystack.into_bytes();
+    let last = haystack.len() - 1;
+    haystack[last] = 0xFF;
+    assert!(check_try_num_chars_std(&haystack));
+}
+
+fn check_utf8_counter_std(haystack: &[u8], cuts: &[usize]) -> bool {
+    let mut counter = Utf8Counter::new();
+    let mut start = 0;
+    for &cut in cuts {
+        let end = start + cut.min(haystack.len() - start);
+        counter.update(&haystack[start..end]);
+        start = end;
+    }
+    counter.update(&haystack[start..]);
+    match (counter.finish(), try_num_chars(haystack)) {
+        (Ok(chars), Ok(expected)) => chars == expected,
+        (Err(error), Err(expected)) => error == expected,
+        _ => false,
+    }
+}
+
+quickcheck! {
+    fn check_utf8_counter_correct(x: (Vec<u8>, Vec<usize>)) -> bool {
+        let (haystack, cuts) = x;
+        let cuts: Vec<_> = cuts.iter().map(|cut| cut % 8).collect();
+        check_utf8_counter_std(&haystack, &cuts)
+    }
+
+    fn check_utf8_counter_str(x: (String, Vec<usize>, usize, u8)) -> bool {
+        let (text, cuts, at, byte) = x;
+        let cuts: Vec<_> = cuts.iter().map(|cut| cut % 8).collect();
+        let mut haystack = text.into_bytes();
+        let ok = check_utf8_counter_std(&haystack, &cuts);
+        if !haystack.is_empty() {
+            let at = at % haystack.len();
+            haystack[at] = byte;
+        }
+        ok && check_utf8_counter_std(&haystack, &cuts)
+    }
+}
+
+#[test]
+fn check_utf8_counter_every_cut() {
+    let text = "aé€🦀".repeat(3);
+    let bytes = text.as_bytes();
+    for first in 0..bytes.len() {
+        for second in 0..bytes.len() - first {
+            assert!(check_utf8_counter_std(bytes, &[first, second]));
+            assert!(check_utf8_counter_std(&bytes[..bytes.len() - 1], &[first, second]));
+            let mut invalid = bytes.to_vec();
+            invalid[first] = 0xFF;
+            assert!(check_utf8_counter_std(&invalid, &[first, second]));
+        }
+    }
+}
+
+#[test]
+fn check_utf8_counter_large() {
+    let haystack = "Grüße, 🦀!".repeat(if cfg!(miri) { 100 } else { 100_000 });
+    let haystack = haystack.as_bytes();
+    let cuts = [1, 4095, 4097, 3, 10_000, 7];
+    assert!(check_utf8_counter_std(haystack, &cuts));
+    let mut counter = Utf8Counter::new();
+    for chunk in haystack.chunks(1001) {
+        counter.update(chunk);
+    }
+    assert_eq!(counter.finish(), try_num_chars(haystack));
+}
+
+fn check_num_chars_lossy_std(haystack: &[u8]) -> bool {
+    let expected = String::from_utf8_lossy(haystack).chars().count();
+    num_chars_lossy(haystack) == expected && naive_num_chars_lossy(haystack) == expected
+}
+
+quickcheck! {
+    fn check_num_chars_lossy_correct(haystack: Vec<u8>) -> bool {
+        check_num_chars_lossy_std(&haystack)
+    }
+
+    fn check_num_chars_lossy_str(x: (String, Vec<(usize, u8)>)) -> bool {
+        let (text, errors) = x;
+        let mut haystack = text.into_bytes();
+        if !haystack.is_empty() {
+            for (at, byte) in errors {
+                let at = at % haystack.len();
+                haystack[at] = byte;
+            }
+        }
+        check_num_chars_lossy_std(&haystack)
+    }
+}
+
+#[test]
+fn check_num_chars_lossy_block_boundaries() {
+    for &c in &["é", "€", "🦀"] {
+        for prefix in 4090..4100 {
+            let text = "a".repeat(prefix) + &c.repeat(3000);
+            for cut in 0..c.len() {
+                let mut bytes = text.as_bytes()[..prefix + cut].to_vec();
+                assert!(check_num_chars_lossy_std(&bytes));
+                bytes.extend_from_slice(text.as_bytes());
+                assert!(check_num_chars_lossy_std(&bytes));
+            }
+        }
+    }
+}
+
+#[test]
+fn check_num_chars_lossy_large_rand() {
+    let haystack = random_bytes(if cfg!(miri) { 200 } else { 100_000 });
+    assert!(check_num_chars_lossy_std(&haystack));
+}
+
+quickcheck! {
+    fn check_utf8_length_histogram_correct(haystack: Vec<u8>) -> bool {
+        utf8_length_histogram(&haystack) == naive_utf8_length_histogram(&haystack)
+    }
+
+    fn check_utf8_length_histogram_str(text: String) -> bool {
+        let mut expected = [0; 5];
+        for c in text.chars() {
+            expected[c.len_utf8() - 1] += 1;
+            expected[4] += c.len_utf8() - 1;
+        }
+        utf8_length_histogram(text.as_bytes()) == expected
+    }
+}
+
+#[test]
+fn check_utf8_length_histogram_large() {
+    let haystack = vec![0xF7u8; if cfg!(miri) { 2_000 } else { 10_000_000 }];
+    assert_eq!(utf8_length_histogram(&haystack), [0, 0, 0, haystack.len(), 0]);
+}
+
+#[test]
+fn check_utf8_length_histogram_large_rand() {
+    let haystack = random_bytes(if cfg!(miri) { 200 } else { 100_000 });
+    assert_eq!(naive_utf8_length_histogram(&haystack), utf8_length_histogram(&haystack));
+}
+
+#[test]
+fn check_utf8_length_histogram_overflow_many() {
+    let string = "aé€🦀".repeat(2000);
+    for i in (0..string.len()).step_by(3) {
+        let haystack = &string.as_bytes()[..i];
+        assert_eq!(naive_utf8_length_histogram(haystack), utf8_length_histogram(haystack));
+    }
+}
+
 #[test]
 fn check_num_chars_overflow_many() {
     let string = [b'x'; 20000];
@@ -88,3 +1163,41 @@ fn check_num_chars_overflow_many() {
         assert_eq!(num_chars(&string[..i]), i);
     }
 }
+
+fn naive_text_stats(haystack: &[u8]) -> TextStats {
+    TextStats {
+        bytes: haystack.len(),
+        newlines: naive_count(haystack, b'\n'),
+        chars: naive_num_chars(haystack),
+    }
+}
+
+quickcheck! {
+    fn check_text_stats_correct(haystack: Vec<u8>) -> bool {
+        text_stats(&haystack) == naive_text_stats(&haystack)
+    }
+}
+
+#[test]
+fn check_text_stats_large() {
+    let haystack = vec![b'\n'; if cfg!(miri) { 2_000 } else { 10_000_000 }];
+    assert_eq!(naive_text_stats(&haystack), text_stats(&haystack));
+}
+
+#[test]
+fn check_text_stats_large_rand() {
+    let haystack = random_bytes(if cfg!(miri) { 200 } else { 100_000 });
+    for i in (0..haystack.len()).step_by(997) {
+        assert_eq!(naive_text_stats(&haystack[i..]), text_stats(&haystack[i..]));
+    }
+}
+
+#[test]
+fn check_text_stats_overflow_many() {
+    let string = "ö\n".repeat(10000);
+    for i in 0..string.len() {
+        let stats = text_stats(&string.as_bytes()[..i]);
+        assert_eq!(stats.newlines, i / 3);
+        assert_eq!(stats.chars, i - (i + 1) / 3);
+    }
+}